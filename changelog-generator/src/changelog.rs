@@ -1,68 +1,427 @@
 //! Structurally diff two [`Snapshot`]
 
 use std::{
-    collections::{BTreeMap, BTreeSet, BinaryHeap},
-    path::Path,
+    collections::{BTreeMap, BinaryHeap, HashMap},
+    convert::Infallible,
+    path::{Path, PathBuf},
     process::Output,
     string::FromUtf8Error,
+    time::Instant,
 };
 
 use arcstr::ArcStr;
 use chrono::{DateTime, Utc};
-use serde::Serialize;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
 use snafu::{OptionExt, ResultExt, Snafu};
+use tracing::{debug, error, info, info_span, warn};
 use xshell::{Shell, cmd};
 
 use crate::{
-    repo_log::{RepoChangeLog, RepoChangelogError, generate_repo_changelog},
+    config::{CategoryRule, ChangeRule, ForgeRule, RepoFilter},
+    contributors::{Contributor, Contributors},
+    mailmap::{Identity, Mailmap},
+    repo_log::{
+        DiffStat, RepoChangeLog, RepoChangelogError, generate_repo_changelog, prior_authors,
+    },
+    schema::SCHEMA_VERSION,
     snapshot::{CommitHash, RepoStatus, Snapshot},
 };
 
-#[derive(Debug, Clone, Serialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 pub struct NewRepoStatus {
+    #[schemars(with = "String")]
     pub upstream: ArcStr,
     pub recent_changes: Vec<Change>,
     pub commit: CommitHash,
 }
 
-#[derive(Debug, Clone, Serialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 pub struct RemovedRepoStatus {
     pub last_seen_commit: CommitHash,
 }
 
-#[derive(Debug, Clone, Serialize)]
+/// The changes between two snapshots, saved by `--json`
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 pub struct ChangeLog {
-    added_repos: BTreeMap<ArcStr, NewRepoStatus>,
-    removed_repos: BTreeMap<ArcStr, RemovedRepoStatus>,
+    /// The version of this format, changelogs without it are of version 0
+    pub schema_version: u32,
+    /// The build tag of the source snapshot, see `--from-tag`
+    #[serde(default)]
+    pub source_tag: Option<String>,
+    #[schemars(with = "BTreeMap<String, NewRepoStatus>")]
+    pub added_repos: BTreeMap<ArcStr, NewRepoStatus>,
+    #[schemars(with = "BTreeMap<String, RemovedRepoStatus>")]
+    pub removed_repos: BTreeMap<ArcStr, RemovedRepoStatus>,
     /// changes ordered by datetime
-    log: Vec<Change>,
+    ///
+    /// The same change that appears in several repos or commits are
+    /// grouped into one entry, see [`Change::duplicates`].
+    pub log: Vec<Change>,
     /// changes per repo
-    changes: BTreeMap<ArcStr, RepoChangeLog>,
+    #[schemars(with = "BTreeMap<String, RepoChangeLog>")]
+    pub changes: BTreeMap<ArcStr, RepoChangeLog>,
+    /// revert/original pairs hidden from the changelog
+    pub hidden_reverts: Vec<HiddenRevert>,
+    pub contributors: Contributors,
+}
+
+impl Default for ChangeLog {
+    fn default() -> Self {
+        Self {
+            schema_version: SCHEMA_VERSION,
+            source_tag: None,
+            added_repos: BTreeMap::new(),
+            removed_repos: BTreeMap::new(),
+            log: Vec::new(),
+            changes: BTreeMap::new(),
+            hidden_reverts: Vec::new(),
+            contributors: Contributors::default(),
+        }
+    }
+}
+
+/// Options for [`ChangeLog::generate`], usually from a [`Config`](crate::config::Config)
+#[derive(Debug, Clone)]
+pub struct GenerateOptions {
+    /// Hide changes that are reverted within the range, together with their reverts.
+    pub hide_reverted: bool,
+    /// Global mailmap that takes precedence over the `.mailmap` in every repo
+    pub mailmap: Mailmap,
+    /// List the paths touched by every change in its [`DiffStat`].
+    pub list_paths: bool,
+    /// Only include changes touching these git pathspecs, per repo
+    pub pathspecs: BTreeMap<ArcStr, Vec<ArcStr>>,
+    /// Only include these repos
+    pub repos: RepoFilter,
+    /// How many recent changes of a newly added repo are listed
+    pub recent_changes: usize,
+    /// The sync stamp branch is this prefix followed by the manifest branch
+    pub stamp_branch_prefix: ArcStr,
+    /// How to link to changes, tried before the built-in rules
+    pub forges: Vec<ForgeRule>,
+    /// The first matching category is saved in [`Change::category`]
+    pub categories: Vec<CategoryRule>,
+    /// Leave out changes matching any of the rules
+    pub suppress: Vec<ChangeRule>,
+    /// Skip repos failing to generate instead of failing,
+    /// see [`GenerateSummary::failures`]
+    pub keep_going: bool,
+}
+
+impl Default for GenerateOptions {
+    fn default() -> Self {
+        Self {
+            hide_reverted: false,
+            mailmap: Mailmap::default(),
+            list_paths: false,
+            pathspecs: BTreeMap::new(),
+            repos: RepoFilter::default(),
+            recent_changes: 10,
+            stamp_branch_prefix: arcstr::literal!("m/"),
+            forges: Vec::new(),
+            categories: Vec::new(),
+            suppress: Vec::new(),
+            keep_going: false,
+        }
+    }
+}
+
+/// Parse a `repo=pathspec` filter, e.g. `frameworks/base/=core/java/**`
+///
+/// The trailing `/` of the repo is ignored, like in the config.
+pub fn parse_pathspec(arg: &str) -> Option<(&str, &str)> {
+    let (repo, pathspec) = arg.split_once('=')?;
+    let repo = repo.trim_end_matches('/');
+    (!repo.is_empty() && !pathspec.is_empty()).then_some((repo, pathspec))
+}
+
+impl GenerateOptions {
+    pub(crate) fn pathspec(&self, repo: &str) -> &[ArcStr] {
+        self.pathspecs
+            .get(repo)
+            .map(Vec::as_slice)
+            .unwrap_or_default()
+    }
+}
+
+/// Generate a [`ChangeLog`] between two [`Snapshot`]s of a repo checkout
+///
+/// ```no_run
+/// use changelog_generator::{ChangeLogBuilder, snapshot::Snapshot};
+///
+/// let from = Snapshot::parse(std::fs::read_to_string("old.snapshot")?)?;
+/// let to = Snapshot::parse(std::fs::read_to_string("new.snapshot")?)?;
+/// let changelog = ChangeLogBuilder::new("/path/to/tree")
+///     .from(from)
+///     .to(to)
+///     .filter("frameworks/base", "core/java/android/hardware/**")
+///     .hide_reverted(true)
+///     .build()?;
+/// # Ok::<_, Box<dyn std::error::Error>>(())
+/// ```
+#[derive(Debug, Clone)]
+pub struct ChangeLogBuilder {
+    tree: PathBuf,
+    from: Option<Snapshot>,
+    to: Option<Snapshot>,
+    options: GenerateOptions,
+}
+
+impl ChangeLogBuilder {
+    /// Start building a changelog of the repo checkout at `tree`,
+    /// which must contain the commits of both snapshots.
+    pub fn new(tree: impl Into<PathBuf>) -> Self {
+        Self {
+            tree: tree.into(),
+            from: None,
+            to: None,
+            options: GenerateOptions::default(),
+        }
+    }
+
+    /// The original snapshot
+    pub fn from(mut self, snapshot: Snapshot) -> Self {
+        self.from = Some(snapshot);
+        self
+    }
+
+    /// The target snapshot
+    pub fn to(mut self, snapshot: Snapshot) -> Self {
+        self.to = Some(snapshot);
+        self
+    }
+
+    /// Only include changes of `repo` touching the git `pathspec`,
+    /// could be called multiple times to add more pathspecs.
+    pub fn filter(mut self, repo: impl Into<ArcStr>, pathspec: impl Into<ArcStr>) -> Self {
+        self.options
+            .pathspecs
+            .entry(repo.into())
+            .or_default()
+            .push(pathspec.into());
+        self
+    }
+
+    /// See [`GenerateOptions::hide_reverted`]
+    pub fn hide_reverted(mut self, hide_reverted: bool) -> Self {
+        self.options.hide_reverted = hide_reverted;
+        self
+    }
+
+    /// See [`GenerateOptions::mailmap`]
+    pub fn mailmap(mut self, mailmap: Mailmap) -> Self {
+        self.options.mailmap = mailmap;
+        self
+    }
+
+    /// See [`GenerateOptions::list_paths`]
+    pub fn list_paths(mut self, list_paths: bool) -> Self {
+        self.options.list_paths = list_paths;
+        self
+    }
+
+    /// See [`GenerateOptions::keep_going`]
+    pub fn keep_going(mut self, keep_going: bool) -> Self {
+        self.options.keep_going = keep_going;
+        self
+    }
+
+    /// Replace all the options at once
+    pub fn options(mut self, options: GenerateOptions) -> Self {
+        self.options = options;
+        self
+    }
+
+    pub fn build(&self) -> Result<ChangeLog> {
+        self.build_with_summary(&mut GenerateSummary::default())
+    }
+
+    /// See [`ChangeLog::generate_with_summary`]
+    pub fn build_with_summary(&self, summary: &mut GenerateSummary) -> Result<ChangeLog> {
+        let from = self
+            .from
+            .as_ref()
+            .context(MissingSnapshotSnafu { which: "original" })?;
+        let to = self
+            .to
+            .as_ref()
+            .context(MissingSnapshotSnafu { which: "target" })?;
+        ChangeLog::generate_with_summary(from, to, &self.tree, &self.options, summary)
+    }
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+/// A change and its revert, which cancel out each other.
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct HiddenRevert {
+    #[schemars(with = "String")]
+    pub repo: ArcStr,
+    #[schemars(with = "String")]
+    pub title: ArcStr,
+    #[schemars(with = "String")]
+    pub commit: ArcStr,
+    #[schemars(with = "String")]
+    pub revert_commit: ArcStr,
+}
+
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize, JsonSchema,
+)]
 pub enum ChangeKind {
     Merge,
     Normal,
+    /// A revert of another change, see [`Change::reverts`]
+    Revert,
 }
 
-#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize, JsonSchema)]
 pub struct Change {
     pub datetime: DateTime<Utc>,
     pub kind: ChangeKind,
+    #[schemars(with = "String")]
     pub repo: ArcStr,
+    #[schemars(with = "String")]
     pub title: ArcStr,
+    #[schemars(with = "String")]
     pub description: ArcStr,
+    /// The same as `author.name`, for templates written before signatures were recorded
+    #[schemars(with = "String")]
     pub author_name: ArcStr,
+    /// The same as `author.email`
+    #[schemars(with = "String")]
     pub author_email: ArcStr,
+    pub author: Signature,
+    pub committer: Signature,
+    #[schemars(with = "Option<String>")]
     pub change_id: Option<ArcStr>,
+    /// Trailers like `Bug: 123` from the last paragraph of the commit message
+    pub trailers: Vec<Trailer>,
+    /// Canonical identities credited with `Co-authored-by:` trailers
+    pub co_authors: Vec<Identity>,
+    #[schemars(with = "String")]
     pub commit: ArcStr,
+    #[schemars(with = "Option<String>")]
     pub commit_url: Option<ArcStr>,
+    #[schemars(with = "Option<String>")]
     pub review_url: Option<ArcStr>,
+    /// Commits this change is cherry-picked from, i.e. `(cherry picked from commit …)`
+    #[schemars(with = "Vec<String>")]
+    pub cherry_picked_from: Vec<ArcStr>,
+    /// The commit reverted by this change, i.e. `This reverts commit …`
+    #[schemars(with = "Option<String>")]
+    pub reverts: Option<ArcStr>,
+    #[schemars(with = "Option<String>")]
+    pub patch_id: Option<ArcStr>,
+    pub diffstat: DiffStat,
+    /// Other copies of the same change, e.g. cherry-picked into another repo.
+    /// Only populated in [`ChangeLog`]'s log.
+    pub duplicates: Vec<ChangeRef>,
+    /// The first matching category of the config
+    #[schemars(with = "Option<String>")]
+    pub category: Option<ArcStr>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize, JsonSchema)]
+pub struct Trailer {
+    #[schemars(with = "String")]
+    pub key: ArcStr,
+    #[schemars(with = "String")]
+    pub value: ArcStr,
+}
+
+/// Who and when authored or committed a [`Change`]
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize, JsonSchema)]
+pub struct Signature {
+    #[schemars(with = "String")]
+    pub name: ArcStr,
+    #[schemars(with = "String")]
+    pub email: ArcStr,
+    pub date: DateTime<Utc>,
+    /// The identity after applying mailmap
+    pub canonical: Identity,
+}
+
+/// A reference to a copy of a [`Change`]
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize, JsonSchema)]
+pub struct ChangeRef {
+    #[schemars(with = "String")]
+    pub repo: ArcStr,
+    #[schemars(with = "String")]
+    pub commit: ArcStr,
+    #[schemars(with = "Option<String>")]
+    pub commit_url: Option<ArcStr>,
+    #[schemars(with = "Option<String>")]
+    pub review_url: Option<ArcStr>,
+}
+
+impl From<&Change> for ChangeRef {
+    fn from(change: &Change) -> Self {
+        Self {
+            repo: change.repo.clone(),
+            commit: change.commit.clone(),
+            commit_url: change.commit_url.clone(),
+            review_url: change.review_url.clone(),
+        }
+    }
+}
+
+#[cfg(test)]
+impl Change {
+    /// A normal change authored and committed by `Someone` at the epoch,
+    /// whose other fields are empty
+    pub(crate) fn test(repo: &str, commit: &str) -> Self {
+        let identity = Identity {
+            name: ArcStr::from("Someone"),
+            email: ArcStr::from("someone@example.com"),
+        };
+        let signature = Signature {
+            name: identity.name.clone(),
+            email: identity.email.clone(),
+            date: DateTime::UNIX_EPOCH,
+            canonical: identity,
+        };
+        Self {
+            datetime: signature.date,
+            kind: ChangeKind::Normal,
+            repo: ArcStr::from(repo),
+            title: ArcStr::from("Fix a bug"),
+            description: ArcStr::new(),
+            author_name: signature.name.clone(),
+            author_email: signature.email.clone(),
+            author: signature.clone(),
+            committer: signature,
+            change_id: None,
+            trailers: Vec::new(),
+            co_authors: Vec::new(),
+            commit: ArcStr::from(commit),
+            commit_url: None,
+            review_url: None,
+            cherry_picked_from: Vec::new(),
+            reverts: None,
+            patch_id: None,
+            diffstat: DiffStat::default(),
+            duplicates: Vec::new(),
+            category: None,
+        }
+    }
+
+    /// Let `signature` author and commit the change
+    pub(crate) fn signed(self, signature: Signature) -> Self {
+        Self {
+            datetime: signature.date,
+            author_name: signature.name.clone(),
+            author_email: signature.email.clone(),
+            author: signature.clone(),
+            committer: signature,
+            ..self
+        }
+    }
 }
 
 #[derive(Debug, Snafu)]
 pub enum ChangeLogError {
+    #[snafu(display("the {which} snapshot is required"))]
+    MissingSnapshot { which: &'static str },
     #[snafu(display("failed to open a command shell"))]
     ShellCreation { source: xshell::Error },
     #[snafu(display("command exec failed"))]
@@ -86,47 +445,134 @@ pub enum ChangeLogError {
 pub type Result<T, E = ChangeLogError> = std::result::Result<T, E>;
 
 impl ChangeLog {
-    pub fn generate(orig: &Snapshot, target: &Snapshot, tree: impl AsRef<Path>) -> Result<Self> {
-        let orig_repos: BTreeSet<ArcStr> = orig.repos.keys().cloned().collect();
-        let target_repos: BTreeSet<ArcStr> = orig.repos.keys().cloned().collect();
-        let added = target_repos
-            .difference(&orig_repos)
-            .filter(|repo_path| std::fs::exists(tree.as_ref().join(repo_path.as_str())).unwrap_or_default());
-        let removed = orig_repos.difference(&target_repos);
-        let common = orig_repos.intersection(&target_repos);
-        let changed = common
-            .filter(|r| orig.repos[r.as_str()] != target.repos[r.as_str()])
-            .filter(|repo_path| std::fs::exists(tree.as_ref().join(repo_path.as_str())).unwrap_or_default());
+    pub fn generate(
+        orig: &Snapshot,
+        target: &Snapshot,
+        tree: impl AsRef<Path>,
+        options: &GenerateOptions,
+    ) -> Result<Self> {
+        Self::generate_with_summary(orig, target, tree, options, &mut GenerateSummary::default())
+    }
+
+    /// Like [`ChangeLog::generate`], counting what is done in `summary`,
+    /// which is filled in even if it fails.
+    pub fn generate_with_summary(
+        orig: &Snapshot,
+        target: &Snapshot,
+        tree: impl AsRef<Path>,
+        options: &GenerateOptions,
+        summary: &mut GenerateSummary,
+    ) -> Result<Self> {
+        let started = Instant::now();
+        let changelog = Self::generate_counting(orig, target, tree, options, summary);
+        summary.changes = changelog.as_ref().map_or(0, |c| c.log.len());
+        summary.elapsed_ms = elapsed_ms(started);
+        info!(
+            changed = summary.changed,
+            added = summary.added,
+            removed = summary.removed,
+            failed = summary.failures.len(),
+            commits = summary.commits,
+            changes = summary.changes,
+            elapsed_ms = summary.elapsed_ms,
+            ok = changelog.is_ok(),
+            "finished"
+        );
+        changelog
+    }
+
+    fn generate_counting(
+        orig: &Snapshot,
+        target: &Snapshot,
+        tree: impl AsRef<Path>,
+        options: &GenerateOptions,
+        summary: &mut GenerateSummary,
+    ) -> Result<Self> {
+        let (added, removed, changed) = select_repos(orig, target, tree.as_ref(), options);
+        info!(
+            changed = changed.len(),
+            added = added.len(),
+            removed = removed.len(),
+            "comparing snapshots"
+        );
         let mut changes = BTreeMap::new();
-        let sync_stamp_branch = get_sync_stamp_branch(&tree)?;
+        let sync_stamp_branch = get_sync_stamp_branch(&tree, &options.stamp_branch_prefix)?;
+        debug!(sync_stamp_branch, "found the sync stamp branch");
         let mut added_repos = BTreeMap::new();
         let mut removed_repos = BTreeMap::new();
+        let mut hidden_reverts = Vec::new();
 
         // Get normal changelogs
-        for repo in changed {
-            let repo_changelog = generate_repo_changelog(
+        for (i, repo) in changed.iter().enumerate() {
+            let _span = info_span!("repo", repo = repo.as_str()).entered();
+            let started = Instant::now();
+            let repo_changelog = match generate_repo_changelog(
                 &orig.repos[repo.as_str()],
                 &target.repos[repo.as_str()],
                 repo,
                 tree.as_ref(),
                 &sync_stamp_branch,
+                options,
             )
-            .with_context(|_| SingleRepoSnafu { repo: repo.clone() })?;
-            changes.insert(repo.to_owned(), repo_changelog);
+            .with_context(|_| SingleRepoSnafu { repo: repo.clone() })
+            {
+                Ok(repo_changelog) => repo_changelog,
+                Err(e) if options.keep_going => {
+                    summary.fail(repo, &e, started, true);
+                    continue;
+                }
+                Err(e) => {
+                    summary.fail(repo, &e, started, false);
+                    return Err(e);
+                }
+            };
+            summary.changed += 1;
+            summary.commits += repo_changelog.logs.len();
+            debug!(
+                progress = %format_args!("{}/{}", i + 1, changed.len()),
+                commits = repo_changelog.logs.len(),
+                elapsed_ms = elapsed_ms(started),
+                "generated"
+            );
+            let mut logs = repo_changelog.logs;
+            hidden_reverts.extend(apply_rules(&mut logs, options));
+            let repo_changelog = RepoChangeLog::new(logs, repo_changelog.pathspec);
+            changes.insert(repo.clone(), repo_changelog);
         }
         // Generate for newly added repos
-        for repo in added {
-            let status = generate_new_repo_changelog(
-                10,
+        for repo in &added {
+            let _span = info_span!("repo", repo = repo.as_str()).entered();
+            let started = Instant::now();
+            let mut status = match generate_new_repo_changelog(
                 repo,
                 &target.repos[repo.as_str()],
                 tree.as_ref(),
                 &sync_stamp_branch,
-            )?;
+                options,
+            ) {
+                Ok(status) => status,
+                Err(e) if options.keep_going => {
+                    summary.fail(repo, &e, started, true);
+                    continue;
+                }
+                Err(e) => {
+                    summary.fail(repo, &e, started, false);
+                    return Err(e);
+                }
+            };
+            summary.added += 1;
+            summary.commits += status.recent_changes.len();
+            debug!(
+                commits = status.recent_changes.len(),
+                elapsed_ms = elapsed_ms(started),
+                "added"
+            );
+            hidden_reverts.extend(apply_rules(&mut status.recent_changes, options));
             added_repos.insert(repo.clone(), status);
         }
         // Generate for removed repos
-        for repo in removed {
+        summary.removed = removed.len();
+        for repo in &removed {
             removed_repos.insert(
                 repo.clone(),
                 RemovedRepoStatus {
@@ -141,16 +587,434 @@ impl ChangeLog {
             .chain(added_repos.values().flat_map(|v| &v.recent_changes))
             .cloned()
             .collect();
-        Ok(ChangeLog {
+        let log = deduplicate(log.into_sorted_vec());
+        let started = Instant::now();
+        let contributors = Contributors::collect(&log, &changes, |repo, emails| {
+            let _span = info_span!("repo", repo = repo.as_str()).entered();
+            let started = Instant::now();
+            match prior_authors(
+                repo,
+                tree.as_ref(),
+                &orig.repos[repo.as_str()],
+                emails,
+                &options.mailmap,
+            )
+            .with_context(|_| SingleRepoSnafu { repo: repo.clone() })
+            {
+                Ok(prior) => Ok(Some(prior)),
+                // Nobody is reported as a first-time contributor because of the repo
+                Err(e) if options.keep_going => {
+                    summary.fail(repo, &e, started, true);
+                    Ok(None)
+                }
+                Err(e) => {
+                    summary.fail(repo, &e, started, false);
+                    Err(e)
+                }
+            }
+        })?;
+        debug!(
+            authors = contributors.authors.len(),
+            first_time = contributors.first_time.len(),
+            elapsed_ms = elapsed_ms(started),
+            "collected contributors"
+        );
+        let mut changelog = ChangeLog {
+            schema_version: SCHEMA_VERSION,
+            source_tag: None,
             added_repos,
             removed_repos,
-            log: log.into_sorted_vec(),
+            log,
             changes,
+            hidden_reverts,
+            contributors,
+        };
+        if !options.list_paths {
+            // Paths are still needed above for counting unique files across changes
+            changelog.strip_paths();
+        }
+        Ok(changelog)
+    }
+
+    /// Leave out the paths touched by the changes, which are only listed with
+    /// [`GenerateOptions::list_paths`]
+    pub fn strip_paths(&mut self) {
+        for repo in self.changes.values_mut() {
+            repo.diffstat.paths = None;
+            repo.diffstat_by_kind
+                .values_mut()
+                .chain(repo.diffstat_by_category.values_mut())
+                .for_each(|stat| stat.paths = None);
+        }
+        let changes = self.changes.values_mut().flat_map(|repo| &mut repo.logs);
+        let new_repo_changes = self
+            .added_repos
+            .values_mut()
+            .flat_map(|repo| &mut repo.recent_changes);
+        for change in changes.chain(new_repo_changes).chain(&mut self.log) {
+            change.diffstat.paths = None;
+        }
+    }
+
+    /// The part of the changelog in the repos and the changes to keep,
+    /// with summaries and contributors recomputed.
+    ///
+    /// First-time contributors stay first-time contributors if they still have changes.
+    pub fn filtered(
+        &self,
+        keep_repo: impl Fn(&str) -> bool,
+        keep_change: impl Fn(&Change) -> bool,
+    ) -> ChangeLog {
+        let keep = |change: &&Change| keep_repo(&change.repo) && keep_change(change);
+        let added_repos = self
+            .added_repos
+            .iter()
+            .filter(|(repo, _)| keep_repo(repo))
+            .map(|(repo, status)| {
+                let status = NewRepoStatus {
+                    recent_changes: status.recent_changes.iter().filter(keep).cloned().collect(),
+                    ..status.clone()
+                };
+                (repo.clone(), status)
+            })
+            .collect();
+        let removed_repos = self
+            .removed_repos
+            .iter()
+            .filter(|(repo, _)| keep_repo(repo))
+            .map(|(repo, status)| (repo.clone(), status.clone()))
+            .collect();
+        let changes: BTreeMap<ArcStr, RepoChangeLog> = self
+            .changes
+            .iter()
+            .filter(|(repo, _)| keep_repo(repo))
+            .filter_map(|(repo, repo_changelog)| {
+                let logs: Vec<Change> = repo_changelog.logs.iter().filter(keep).cloned().collect();
+                (!logs.is_empty()).then(|| {
+                    let repo_changelog = RepoChangeLog::new(logs, repo_changelog.pathspec.clone());
+                    (repo.clone(), repo_changelog)
+                })
+            })
+            .collect();
+        let log: Vec<Change> = self.log.iter().filter(keep).cloned().collect();
+        let hidden_reverts = self
+            .hidden_reverts
+            .iter()
+            .filter(|revert| keep_repo(&revert.repo))
+            .cloned()
+            .collect();
+        let Ok(mut contributors) =
+            Contributors::collect(&log, &changes, |_, _| Ok::<_, Infallible>(None));
+        contributors.first_time = self
+            .contributors
+            .first_time
+            .iter()
+            .filter(|identity| {
+                contributors
+                    .authors
+                    .iter()
+                    .any(|a| a.identity == **identity)
+            })
+            .cloned()
+            .collect();
+        ChangeLog {
+            schema_version: self.schema_version,
+            source_tag: self.source_tag.clone(),
+            added_repos,
+            removed_repos,
+            log,
+            changes,
+            hidden_reverts,
+            contributors,
+        }
+    }
+
+    /// A change log with every field populated, which shows what templates could use.
+    pub fn sample() -> Self {
+        let identity = Identity {
+            name: ArcStr::from("Alice"),
+            email: ArcStr::from("alice@example.com"),
+        };
+        let signature = Signature {
+            name: identity.name.clone(),
+            email: identity.email.clone(),
+            date: DateTime::UNIX_EPOCH,
+            canonical: identity.clone(),
+        };
+        let commit = ArcStr::from("0123456789abcdef0123456789abcdef01234567");
+        let diffstat = DiffStat {
+            files_changed: 1,
+            insertions: 1,
+            deletions: 1,
+            paths: Some(vec![ArcStr::from("README.md")]),
+        };
+        let mut change = Change {
+            datetime: DateTime::UNIX_EPOCH,
+            kind: ChangeKind::Normal,
+            repo: ArcStr::from("system/core"),
+            title: ArcStr::from("init: fix a crash"),
+            description: ArcStr::from("Some details."),
+            author_name: signature.name.clone(),
+            author_email: signature.email.clone(),
+            author: signature.clone(),
+            committer: signature,
+            change_id: Some(ArcStr::from("I0123456789abcdef0123456789abcdef01234567")),
+            trailers: vec![Trailer {
+                key: ArcStr::from("Bug"),
+                value: ArcStr::from("123456"),
+            }],
+            co_authors: vec![identity.clone()],
+            commit: commit.clone(),
+            commit_url: Some(ArcStr::from("https://example.com/commit")),
+            review_url: Some(ArcStr::from("https://example.com/review")),
+            cherry_picked_from: vec![commit.clone()],
+            reverts: Some(commit.clone()),
+            patch_id: Some(commit.clone()),
+            diffstat,
+            duplicates: Vec::new(),
+            category: Some(ArcStr::from("Init")),
+        };
+        change.duplicates.push(ChangeRef::from(&change));
+        let repo = change.repo.clone();
+        let contributor = Contributor {
+            identity: identity.clone(),
+            changes: 1,
+        };
+        let commit_hash = CommitHash::try_new(commit.to_string()).unwrap();
+        ChangeLog {
+            schema_version: SCHEMA_VERSION,
+            source_tag: None,
+            added_repos: BTreeMap::from([(
+                repo.clone(),
+                NewRepoStatus {
+                    upstream: ArcStr::from("lineage-23.0"),
+                    recent_changes: vec![change.clone()],
+                    commit: commit_hash.clone(),
+                },
+            )]),
+            removed_repos: BTreeMap::from([(
+                repo.clone(),
+                RemovedRepoStatus {
+                    last_seen_commit: commit_hash,
+                },
+            )]),
+            log: vec![change.clone()],
+            changes: BTreeMap::from([(
+                repo.clone(),
+                RepoChangeLog::new(vec![change.clone()], vec![ArcStr::from("init")]),
+            )]),
+            hidden_reverts: vec![HiddenRevert {
+                repo,
+                title: change.title.clone(),
+                commit: commit.clone(),
+                revert_commit: commit,
+            }],
+            contributors: Contributors {
+                authors: vec![contributor.clone()],
+                first_time: vec![identity],
+                co_authors: vec![contributor],
+            },
+        }
+    }
+}
+
+/// Remove changes that are reverted by another change in the list, together with the reverts.
+///
+/// The changes are processed from the newest to the oldest so that
+/// a revert of a revert cancels out the revert instead of the original change.
+fn hide_reverted(changes: &mut Vec<Change>) -> Vec<HiddenRevert> {
+    let mut order: Vec<usize> = (0..changes.len()).collect();
+    order.sort_by(|&a, &b| changes[b].datetime.cmp(&changes[a].datetime));
+    let mut hidden = vec![false; changes.len()];
+    let mut pairs = Vec::new();
+    for i in order {
+        if hidden[i] {
+            continue;
+        }
+        let Some(reverted) = changes[i].reverts.as_deref() else {
+            continue;
+        };
+        let original = (0..changes.len())
+            .find(|&j| !hidden[j] && j != i && changes[j].commit.starts_with(reverted));
+        if let Some(j) = original {
+            hidden[i] = true;
+            hidden[j] = true;
+            pairs.push(HiddenRevert {
+                repo: changes[j].repo.clone(),
+                title: changes[j].title.clone(),
+                commit: changes[j].commit.clone(),
+                revert_commit: changes[i].commit.clone(),
+            });
+        }
+    }
+    let mut index = 0;
+    changes.retain(|_| {
+        index += 1;
+        !hidden[index - 1]
+    });
+    pairs
+}
+
+/// Group copies of the same change into one logical entry.
+///
+/// Two changes are considered the same if they share a `Change-Id`, a patch id,
+/// or one of them is cherry-picked from the other.
+/// Merge commits are never grouped.
+///
+/// The first change of every group (in the order of the input) is kept and
+/// the other copies are recorded in its [`Change::duplicates`].
+fn deduplicate(changes: Vec<Change>) -> Vec<Change> {
+    #[derive(Hash, PartialEq, Eq)]
+    enum Key<'a> {
+        ChangeId(&'a str),
+        PatchId(&'a str),
+        Commit(&'a str),
+    }
+
+    fn find(parents: &mut [usize], mut i: usize) -> usize {
+        while parents[i] != i {
+            parents[i] = parents[parents[i]];
+            i = parents[i];
+        }
+        i
+    }
+
+    let mut parents: Vec<usize> = (0..changes.len()).collect();
+    let mut owners: HashMap<Key, usize> = HashMap::new();
+    for (i, change) in changes.iter().enumerate() {
+        if change.kind == ChangeKind::Merge {
+            continue;
+        }
+        let keys = change
+            .change_id
+            .as_deref()
+            .map(Key::ChangeId)
+            .into_iter()
+            .chain(change.patch_id.as_deref().map(Key::PatchId))
+            .chain(std::iter::once(Key::Commit(&change.commit)))
+            .chain(
+                change
+                    .cherry_picked_from
+                    .iter()
+                    .map(|c| Key::Commit(c.as_str())),
+            );
+        for key in keys {
+            let owner = *owners.entry(key).or_insert(i);
+            let (a, b) = (find(&mut parents, owner), find(&mut parents, i));
+            // Always let the earlier change be the representative
+            parents[a.max(b)] = a.min(b);
+        }
+    }
+
+    let mut duplicates: BTreeMap<usize, Vec<ChangeRef>> = BTreeMap::new();
+    for (i, change) in changes.iter().enumerate() {
+        let root = find(&mut parents, i);
+        if root != i {
+            duplicates
+                .entry(root)
+                .or_default()
+                .push(ChangeRef::from(change));
+        }
+    }
+    changes
+        .into_iter()
+        .enumerate()
+        .filter(|(i, _)| parents[*i] == *i)
+        .map(|(i, mut change)| {
+            change.duplicates = duplicates.remove(&i).unwrap_or_default();
+            change
         })
+        .collect()
+}
+
+/// What [`ChangeLog::generate_with_summary`] has done
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct GenerateSummary {
+    /// Changed repos whose changes are generated
+    pub changed: usize,
+    pub added: usize,
+    pub removed: usize,
+    /// Commits read from the changed and added repos
+    pub commits: usize,
+    /// Entries of [`ChangeLog::log`]
+    pub changes: usize,
+    /// Repos that failed, which are skipped with [`GenerateOptions::keep_going`],
+    /// or whose authors are not checked for first-time contributors
+    pub failures: Vec<RepoFailure>,
+    pub elapsed_ms: u64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct RepoFailure {
+    pub repo: ArcStr,
+    /// The error and its causes
+    pub error: String,
+}
+
+impl GenerateSummary {
+    fn fail(&mut self, repo: &ArcStr, error: &ChangeLogError, started: Instant, skipped: bool) {
+        let mut message = error.to_string();
+        let mut source = std::error::Error::source(error);
+        while let Some(e) = source {
+            message = format!("{message}: {e}");
+            source = e.source();
+        }
+        let elapsed_ms = elapsed_ms(started);
+        if skipped {
+            warn!(elapsed_ms, error = message, "skipped");
+        } else {
+            error!(elapsed_ms, error = message, "failed");
+        }
+        self.failures.push(RepoFailure {
+            repo: repo.clone(),
+            error: message,
+        });
+    }
+}
+
+fn elapsed_ms(started: Instant) -> u64 {
+    started.elapsed().as_millis() as u64
+}
+
+/// Leave out suppressed changes and categorize the rest,
+/// returning the revert/original pairs hidden with [`GenerateOptions::hide_reverted`].
+fn apply_rules(changes: &mut Vec<Change>, options: &GenerateOptions) -> Vec<HiddenRevert> {
+    changes.retain(|change| !options.suppress.iter().any(|rule| rule.matches(change)));
+    for change in changes.iter_mut() {
+        change.category = options
+            .categories
+            .iter()
+            .find(|category| category.rule.matches(change))
+            .map(|category| category.name.clone());
     }
+    if options.hide_reverted {
+        hide_reverted(changes)
+    } else {
+        Vec::new()
+    }
+}
+
+/// Select the added, removed and changed repos to generate changelogs for.
+///
+/// Repos not matching the filter are skipped,
+/// and so are added and changed repos that are not checked out in the tree.
+fn select_repos(
+    orig: &Snapshot,
+    target: &Snapshot,
+    tree: &Path,
+    options: &GenerateOptions,
+) -> (Vec<ArcStr>, Vec<ArcStr>, Vec<ArcStr>) {
+    let diff = orig.diff(target);
+    let matches = |repo: &ArcStr| options.repos.matches(repo);
+    let checked_out = |repo: &ArcStr| std::fs::exists(tree.join(repo.as_str())).unwrap_or_default();
+    let added = diff.added.into_keys().filter(matches).filter(checked_out);
+    let removed = diff.removed.into_keys().filter(matches);
+    let changed = diff.changed.into_keys().filter(matches).filter(checked_out);
+    (added.collect(), removed.collect(), changed.collect())
 }
 
-fn get_sync_stamp_branch(tree: &impl AsRef<Path>) -> Result<String> {
+/// The branch `repo sync` stamps the synced commits with, `m/<manifest branch>` by default
+pub(crate) fn get_sync_stamp_branch(tree: &impl AsRef<Path>, prefix: &str) -> Result<String> {
     let sh = Shell::new().context(ShellCreationSnafu)?;
     let top = tree.as_ref();
     let repo_info = output2string(
@@ -171,26 +1035,27 @@ fn get_sync_stamp_branch(tree: &impl AsRef<Path>) -> Result<String> {
         .strip_prefix("refs/heads/")
         .unwrap_or(manifest_branch);
 
-    Ok(format!("m/{manifest_branch}"))
+    Ok(format!("{prefix}{manifest_branch}"))
 }
 
 fn generate_new_repo_changelog(
-    max_recent_changes: usize,
     repo: &ArcStr,
     current: &RepoStatus,
     top: impl AsRef<Path>,
     sync_stamp_branch: &str,
+    options: &GenerateOptions,
 ) -> Result<NewRepoStatus> {
     let sh = Shell::new().context(ShellCreationSnafu)?;
     let repo = ArcStr::from(repo);
     let repo_path = top.as_ref().join(repo.as_str());
     let commit = current.commit.as_ref();
     // Get a start commit
-    let limit = max_recent_changes.to_string();
+    let limit = options.recent_changes.to_string();
+    let paths: Vec<&str> = options.pathspec(&repo).iter().map(|p| p.as_str()).collect();
     let recent_commits = output2string(
         cmd!(
             sh,
-            "git -C {repo_path} rev-list --first-parent --reverse --max-count={limit} {commit}"
+            "git -C {repo_path} rev-list --first-parent --reverse --max-count={limit} {commit} -- {paths...}"
         )
         .output()
         .context(CommandExecutionSnafu)?,
@@ -233,6 +1098,7 @@ fn generate_new_repo_changelog(
         &repo,
         top,
         sync_stamp_branch,
+        options,
     )
     .with_context(|_| SingleRepoSnafu { repo })?;
     Ok(NewRepoStatus {
@@ -245,3 +1111,204 @@ fn generate_new_repo_changelog(
 pub(crate) fn output2string(output: Output) -> Result<String> {
     String::from_utf8(output.stdout).with_context(|_| InvalidEncodingSnafu)
 }
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeZone;
+
+    use super::*;
+
+    fn signature(timestamp: i64) -> Signature {
+        let identity = Identity {
+            name: ArcStr::from("Someone"),
+            email: ArcStr::from("someone@example.com"),
+        };
+        Signature {
+            name: identity.name.clone(),
+            email: identity.email.clone(),
+            date: Utc.timestamp_opt(timestamp, 0).unwrap(),
+            canonical: identity,
+        }
+    }
+
+    fn change(repo: &str, commit: &str, timestamp: i64) -> Change {
+        Change::test(repo, commit).signed(signature(timestamp))
+    }
+
+    #[test]
+    fn test_json_roundtrip() {
+        let sample = ChangeLog::sample();
+        let json = serde_json::to_value(&sample).unwrap();
+        let parsed: ChangeLog = serde_json::from_value(json.clone()).unwrap();
+        assert_eq!(serde_json::to_value(&parsed).unwrap(), json);
+    }
+
+    #[test]
+    fn test_builder_requires_snapshots() {
+        let snapshot = Snapshot {
+            repos: BTreeMap::new(),
+        };
+        let err = ChangeLogBuilder::new("/nonexistent")
+            .from(snapshot)
+            .filter("system/core", "init")
+            .build()
+            .unwrap_err();
+        assert!(matches!(
+            err,
+            ChangeLogError::MissingSnapshot { which: "target" }
+        ));
+    }
+
+    #[test]
+    fn test_select_repos() {
+        let snapshot = |repos: &[(&str, &str)]| {
+            Snapshot::parse(
+                repos
+                    .iter()
+                    .map(|(repo, commit)| format!("{repo}: {commit}\n"))
+                    .collect(),
+            )
+            .unwrap()
+        };
+        let orig = snapshot(&[
+            ("system/core", "aaaaaaaa"),
+            ("device/same", "bbbbbbbb"),
+            ("vendor/removed", "cccccccc"),
+        ]);
+        let target = snapshot(&[
+            ("system/core", "dddddddd"),
+            ("device/same", "bbbbbbbb"),
+            ("device/added", "eeeeeeee"),
+            ("device/not-checked-out", "ffffffff"),
+        ]);
+        let tree = std::env::temp_dir().join(format!("changelog-select-{}", std::process::id()));
+        for repo in ["system/core", "device/same", "device/added"] {
+            std::fs::create_dir_all(tree.join(repo)).unwrap();
+        }
+        let (added, removed, changed) =
+            select_repos(&orig, &target, &tree, &GenerateOptions::default());
+        std::fs::remove_dir_all(&tree).unwrap();
+        assert_eq!(added, ["device/added"]);
+        assert_eq!(removed, ["vendor/removed"]);
+        assert_eq!(changed, ["system/core"]);
+    }
+
+    #[test]
+    fn test_parse_pathspec() {
+        assert_eq!(
+            parse_pathspec("frameworks/base/=core/java/**"),
+            Some(("frameworks/base", "core/java/**"))
+        );
+        assert_eq!(
+            parse_pathspec("system/core=a=b"),
+            Some(("system/core", "a=b"))
+        );
+        assert_eq!(parse_pathspec("system/core"), None);
+        assert_eq!(parse_pathspec("system/core="), None);
+        assert_eq!(parse_pathspec("/=init"), None);
+
+        let builder = ChangeLogBuilder::new("/nonexistent")
+            .filter("system/core", "init")
+            .filter("system/core", "rootdir");
+        assert_eq!(builder.options.pathspec("system/core"), ["init", "rootdir"]);
+        assert!(builder.options.pathspec("system/vold").is_empty());
+    }
+
+    #[test]
+    fn test_deduplicate_by_change_id() {
+        let mut a = change("system/core", "aaaa", 1);
+        let mut b = change("system/vold", "bbbb", 2);
+        let c = change("system/vold", "cccc", 3);
+        a.change_id = Some(ArcStr::from("I1234"));
+        b.change_id = Some(ArcStr::from("I1234"));
+        let log = deduplicate(vec![a, b, c]);
+        assert_eq!(log.len(), 2);
+        assert_eq!(&*log[0].commit, "aaaa");
+        assert_eq!(log[0].duplicates.len(), 1);
+        assert_eq!(&*log[0].duplicates[0].repo, "system/vold");
+        assert_eq!(&*log[0].duplicates[0].commit, "bbbb");
+        assert!(log[1].duplicates.is_empty());
+    }
+
+    #[test]
+    fn test_deduplicate_transitively() {
+        // a and b share a patch id, c is cherry-picked from b
+        let mut a = change("device/foo", "aaaa", 1);
+        let mut b = change("device/bar", "bbbb", 2);
+        let mut c = change("device/baz", "cccc", 3);
+        a.patch_id = Some(ArcStr::from("p1"));
+        b.patch_id = Some(ArcStr::from("p1"));
+        c.cherry_picked_from = vec![ArcStr::from("bbbb")];
+        let log = deduplicate(vec![a, b, c]);
+        assert_eq!(log.len(), 1);
+        let commits: Vec<_> = log[0].duplicates.iter().map(|d| &*d.commit).collect();
+        assert_eq!(commits, ["bbbb", "cccc"]);
+    }
+
+    #[test]
+    fn test_hide_reverted() {
+        let a = change("device/foo", "aaaa", 1);
+        let b = change("device/foo", "bbbb", 2);
+        let mut c = change("device/foo", "cccc", 3);
+        c.kind = ChangeKind::Revert;
+        c.reverts = Some(ArcStr::from("aaaa"));
+        let mut changes = vec![a, b, c];
+        let hidden = hide_reverted(&mut changes);
+        assert_eq!(changes.len(), 1);
+        assert_eq!(&*changes[0].commit, "bbbb");
+        assert_eq!(hidden.len(), 1);
+        assert_eq!(&*hidden[0].commit, "aaaa");
+        assert_eq!(&*hidden[0].revert_commit, "cccc");
+    }
+
+    #[test]
+    fn test_hide_reverted_revert_of_revert() {
+        let a = change("device/foo", "aaaa", 1);
+        let mut b = change("device/foo", "bbbb", 2);
+        b.reverts = Some(ArcStr::from("aaaa"));
+        let mut c = change("device/foo", "cccc", 3);
+        c.reverts = Some(ArcStr::from("bbbb"));
+        let mut changes = vec![a, b, c];
+        let hidden = hide_reverted(&mut changes);
+        assert_eq!(changes.len(), 1);
+        assert_eq!(&*changes[0].commit, "aaaa");
+        assert_eq!(&*hidden[0].commit, "bbbb");
+    }
+
+    #[test]
+    fn test_hide_reverted_keeps_revert_of_older_change() {
+        let mut a = change("device/foo", "aaaa", 1);
+        a.reverts = Some(ArcStr::from("ffff"));
+        let mut changes = vec![a];
+        assert!(hide_reverted(&mut changes).is_empty());
+        assert_eq!(changes.len(), 1);
+    }
+
+    #[test]
+    fn test_apply_rules_hides_reverted() {
+        // Like the recent changes of an added repo
+        let a = change("device/new", "aaaa", 1);
+        let mut b = change("device/new", "bbbb", 2);
+        b.reverts = Some(ArcStr::from("aaaa"));
+        let mut changes = vec![a, b];
+        assert!(apply_rules(&mut changes.clone(), &GenerateOptions::default()).is_empty());
+        let options = GenerateOptions {
+            hide_reverted: true,
+            ..GenerateOptions::default()
+        };
+        let hidden = apply_rules(&mut changes, &options);
+        assert!(changes.is_empty());
+        assert_eq!(&*hidden[0].repo, "device/new");
+    }
+
+    #[test]
+    fn test_deduplicate_ignores_merges() {
+        let mut a = change("device/foo", "aaaa", 1);
+        let mut b = change("device/foo", "bbbb", 2);
+        a.kind = ChangeKind::Merge;
+        b.kind = ChangeKind::Merge;
+        a.change_id = Some(ArcStr::from("I1234"));
+        b.change_id = Some(ArcStr::from("I1234"));
+        assert_eq!(deduplicate(vec![a, b]).len(), 2);
+    }
+}
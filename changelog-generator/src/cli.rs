@@ -1,30 +1,545 @@
-use std::path::PathBuf;
+use std::{path::PathBuf, str::FromStr};
 
-use palc::Parser;
+use changelog_generator::{
+    changelog::ChangeKind,
+    history::HistoryOrder,
+    template::{ContextKind, EscapeMode},
+};
+use palc::{Args, Parser, Subcommand, ValueEnum};
 
+/// Invoking without a subcommand is an alias of `generate`
+///
+/// Exits with 0 on success, 1 on invalid arguments or config and other failures,
+/// 3 on a bad input snapshot, 4 on a missing tree, 5 when git fails, 6 when a template fails,
+/// 7 when `generate --keep-going` skips failing repos,
+/// 8 when there are no changes and nothing is written, and 9 when `generate --state` finds no previous build to compare with.
 #[derive(Debug, Parser)]
 pub struct Cli {
-    #[arg(long, help = "The original tree status fd")]
-    pub from: u32,
-    #[arg(long, help = "The target tree status fd")]
-    pub to: u32,
+    #[command(subcommand)]
+    pub command: Option<Command>,
+    /// The tree to generate the changelog of, when invoked without a subcommand
+    pub tree: Option<PathBuf>,
+    #[command(flatten)]
+    pub generate: GenerateArgs,
+    // How much to log to stderr, global to be accepted after any subcommand
+    #[arg(
+        short,
+        long,
+        global = true,
+        help = "Log more, e.g. the timing of every repo with -v and every commit read with -vv"
+    )]
+    pub verbose: u8,
+    #[arg(
+        short,
+        long,
+        global = true,
+        help = "Log less, only warnings with -q and only errors with -qq"
+    )]
+    pub quiet: u8,
+    #[arg(
+        long,
+        global = true,
+        help = "Log as text or as JSON lines (default: text). RUST_LOG overrides the levels, e.g. RUST_LOG=changelog_generator=trace"
+    )]
+    pub log_format: Option<OutputFormat>,
+}
+
+// Parsed once, the size doesn't matter
+#[allow(clippy::large_enum_variant)]
+#[derive(Debug, Subcommand)]
+pub enum Command {
+    /// Capture the synced commit of every repo in a tree
+    Snapshot(SnapshotArgs),
+    /// Report the synced commit and uncommitted changes of every repo in a tree
+    Status(StatusArgs),
+    /// Compare two snapshots without looking into git history
+    Diff(DiffArgs),
+    /// Generate a changelog between two snapshots
+    Generate(GenerateCommand),
+    /// Render templates with a changelog saved by --json
+    Render(RenderArgs),
+    /// Search the changes of a changelog saved by --json
+    Query(QueryArgs),
+    /// Render the changelogs of all the builds of a device in the state repo into pages
+    History(HistoryArgs),
+    /// Render a static site of all the builds of a device in the state repo
+    Site(SiteArgs),
+    /// Compile templates and report unknown helpers, partials and fields
+    CheckTemplate(CheckTemplateArgs),
+    /// Print the JSON Schema of changelogs saved by --json
+    Schema(SchemaArgs),
+}
+
+#[derive(Debug, Args)]
+pub struct SnapshotArgs {
+    /// The root of the repo checkout
     pub tree: PathBuf,
+    #[arg(
+        long,
+        help = "The config file (default: changelog.toml in the tree or its state directory)"
+    )]
+    pub config: Option<PathBuf>,
+    #[command(flatten)]
+    pub output: OutputArgs,
+}
+
+#[derive(Debug, Args)]
+pub struct StatusArgs {
+    /// The root of the repo checkout
+    pub tree: PathBuf,
+    #[arg(
+        long,
+        help = "The config file (default: changelog.toml in the tree or its state directory)"
+    )]
+    pub config: Option<PathBuf>,
+    #[command(flatten)]
+    pub output: OutputArgs,
+}
+
+#[derive(Debug, Args)]
+pub struct DiffArgs {
+    #[command(flatten)]
+    pub snapshots: SnapshotSources,
+    #[command(flatten)]
+    pub output: OutputArgs,
+}
+
+#[derive(Debug, Args)]
+pub struct GenerateCommand {
+    /// The root of the repo checkout
+    pub tree: PathBuf,
+    #[command(flatten)]
+    pub args: GenerateArgs,
+}
+
+#[derive(Debug, Args)]
+pub struct GenerateArgs {
+    #[arg(
+        long,
+        help = "The config file (default: changelog.toml in the tree or its state directory)"
+    )]
+    pub config: Option<PathBuf>,
+    #[command(flatten)]
+    pub snapshots: SnapshotSources,
+    #[arg(
+        long,
+        help = "The state repo to read the snapshots of --device from instead of --from and --to, writing the outputs into the directory of --to-tag"
+    )]
+    pub state: Option<PathBuf>,
+    #[command(flatten)]
+    pub templates: TemplateArgs,
+    #[arg(short, long, help = "Output the changelog as JSON")]
+    pub json: Option<PathBuf>,
+    #[arg(short, long, help = "Output the changelog in debug format")]
+    pub debug: Option<String>,
+    #[arg(
+        long,
+        help = "Hide changes that are reverted within the range together with their reverts"
+    )]
+    pub hide_reverted: bool,
+    #[arg(
+        long,
+        help = "A global mailmap file that takes precedence over the .mailmap in every repo"
+    )]
+    pub mailmap: Option<PathBuf>,
+    #[arg(long, help = "List the paths touched by every change")]
+    pub list_paths: bool,
+    #[arg(
+        long,
+        help = "Only include changes of REPO touching PATHSPEC, e.g. --pathspec=frameworks/base=core/java/android/hardware/**"
+    )]
+    pub pathspec: Vec<String>,
+    #[arg(
+        long,
+        help = "Skip repos failing to generate instead of failing, exiting with 7 after writing the outputs"
+    )]
+    pub keep_going: bool,
+    #[arg(
+        long,
+        help = "Write the exit status, counts and failures of the run as JSON, also when it fails"
+    )]
+    pub summary_json: Option<PathBuf>,
+}
+
+impl GenerateArgs {
+    /// The first flag that is given, to reject generate flags before another subcommand
+    pub fn first_given(&self) -> Option<&'static str> {
+        let Self {
+            config,
+            snapshots: SnapshotSources { from, to },
+            state,
+            templates:
+                TemplateArgs {
+                    template,
+                    profile,
+                    output_dir,
+                    renderer:
+                        RendererArgs {
+                            template_dir,
+                            var,
+                            vars_file,
+                            timezone,
+                        },
+                    escape,
+                    release_tag,
+                    device,
+                    from_tag,
+                    to_tag,
+                },
+            json,
+            debug,
+            hide_reverted,
+            mailmap,
+            list_paths,
+            pathspec,
+            keep_going,
+            summary_json,
+        } = self;
+        [
+            ("--config", config.is_some()),
+            ("--from", from.is_some()),
+            ("--to", to.is_some()),
+            ("--state", state.is_some()),
+            ("--template", !template.is_empty()),
+            ("--profile", !profile.is_empty()),
+            ("--output-dir", output_dir.is_some()),
+            ("--template-dir", !template_dir.is_empty()),
+            ("--var", !var.is_empty()),
+            ("--vars-file", !vars_file.is_empty()),
+            ("--timezone", timezone.is_some()),
+            ("--escape", !escape.is_empty()),
+            ("--release-tag", release_tag.is_some()),
+            ("--device", device.is_some()),
+            ("--from-tag", from_tag.is_some()),
+            ("--to-tag", to_tag.is_some()),
+            ("--json", json.is_some()),
+            ("--debug", debug.is_some()),
+            ("--hide-reverted", *hide_reverted),
+            ("--mailmap", mailmap.is_some()),
+            ("--list-paths", *list_paths),
+            ("--pathspec", !pathspec.is_empty()),
+            ("--keep-going", *keep_going),
+            ("--summary-json", summary_json.is_some()),
+        ]
+        .into_iter()
+        .find_map(|(flag, given)| given.then_some(flag))
+    }
+}
+
+/// The snapshots to compare, shared by `diff` and `generate`
+#[derive(Debug, Args)]
+pub struct SnapshotSources {
+    #[arg(
+        long,
+        help = "The original snapshot, either a file or an open fd like --from=3 3<snapshot"
+    )]
+    pub from: Option<String>,
+    #[arg(
+        long,
+        help = "The target snapshot, either a file or an open fd like --to=4 4<snapshot"
+    )]
+    pub to: Option<String>,
+}
+
+#[derive(Debug, Args)]
+pub struct QueryArgs {
+    #[arg(long, help = "A changelog saved by --json")]
+    pub changelog: PathBuf,
+    #[arg(long, help = "Only include changes of these repos")]
+    pub repo: Vec<String>,
+    #[arg(
+        long,
+        help = "Only include changes whose author name or email contains AUTHOR, ignoring case"
+    )]
+    pub author: Option<String>,
+    #[arg(long, help = "Only include changes of this kind")]
+    pub kind: Option<KindArg>,
+    #[arg(
+        long,
+        help = "Only include changes whose commit message matches a regex"
+    )]
+    pub grep: Option<String>,
+    #[command(flatten)]
+    pub output: OutputArgs,
+}
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+pub enum KindArg {
+    Merge,
+    Normal,
+    Revert,
+}
+
+impl From<KindArg> for ChangeKind {
+    fn from(kind: KindArg) -> Self {
+        match kind {
+            KindArg::Merge => ChangeKind::Merge,
+            KindArg::Normal => ChangeKind::Normal,
+            KindArg::Revert => ChangeKind::Revert,
+        }
+    }
+}
+
+/// Where and how to write the result, shared by the subcommands printing one
+#[derive(Debug, Args)]
+pub struct OutputArgs {
+    #[arg(short, long, help = "Write to a file instead of stdout")]
+    pub output: Option<PathBuf>,
+    #[arg(long, help = "The output format (default: text)")]
+    pub format: Option<OutputFormat>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum OutputFormat {
+    Text,
+    Json,
+}
+
+#[derive(Debug, Args)]
+pub struct RenderArgs {
+    #[arg(long, help = "A changelog saved by --json")]
+    pub changelog: PathBuf,
+    #[arg(long, help = "The config file")]
+    pub config: Option<PathBuf>,
+    #[command(flatten)]
+    pub templates: TemplateArgs,
+}
+
+#[derive(Debug, Args)]
+pub struct CheckTemplateArgs {
+    /// Template files, built-in templates like builtin:markdown or templates loaded by --template-dir
+    pub templates: Vec<String>,
+    #[arg(
+        long,
+        help = "Load templates and partials from a directory, overriding built-in ones with the same name"
+    )]
+    pub template_dir: Vec<PathBuf>,
+    #[arg(
+        long,
+        help = "Check against the context of a changelog, a history page or a site page \
+                (default: history for names like markdown/history, site for site/*)"
+    )]
+    pub context: Option<ContextKindArg>,
+}
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+pub enum ContextKindArg {
+    Changelog,
+    History,
+    Site,
+}
+
+impl From<ContextKindArg> for ContextKind {
+    fn from(kind: ContextKindArg) -> Self {
+        match kind {
+            ContextKindArg::Changelog => ContextKind::ChangeLog,
+            ContextKindArg::History => ContextKind::History,
+            ContextKindArg::Site => ContextKind::Site,
+        }
+    }
+}
+
+#[derive(Debug, Args)]
+pub struct SchemaArgs {
+    #[arg(short, long, help = "Write to a file instead of stdout")]
+    pub output: Option<PathBuf>,
+}
+
+/// How to render templates, shared by generating and rendering a saved changelog
+#[derive(Debug, Args)]
+pub struct TemplateArgs {
     #[arg(
         short,
         long,
-        help = "Use a handlebars template to render the changelog"
+        help = "Use a handlebars template to render the changelog, e.g. -t out.md@template.handlebars or -t out.md@builtin:markdown (built-in: markdown, html, html-page, text, rss)"
+    )]
+    pub template: Vec<TemplateOutput>,
+    #[arg(
+        long,
+        help = "Only write these profiles of the config (default: all of them)"
+    )]
+    pub profile: Vec<String>,
+    #[arg(
+        long,
+        help = "The directory of relative destinations of profiles (default: the directory of the config)"
+    )]
+    pub output_dir: Option<PathBuf>,
+    #[command(flatten)]
+    pub renderer: RendererArgs,
+    #[arg(
+        long,
+        help = "Escape expressions in OUTPUT with MODE (html, markdown, telegram, json or none), e.g. --escape=changelog.md=markdown. \
+                Inferred from the built-in template or the extension of OUTPUT by default"
+    )]
+    pub escape: Vec<EscapeArg>,
+    #[arg(
+        long,
+        help = "The release tag, available as meta.release_tag in templates"
+    )]
+    pub release_tag: Option<String>,
+    #[arg(
+        long,
+        help = "The device triple, available as meta.device in templates and required by --state"
+    )]
+    pub device: Option<String>,
+    #[arg(
+        long,
+        help = "An identifier of the original snapshot, e.g. its build tag. With --state, the build to compare with (default: the one in last_build)"
+    )]
+    pub from_tag: Option<String>,
+    #[arg(
+        long,
+        help = "An identifier of the target snapshot, e.g. its build tag. With --state, the build to generate the changelog of (default: capture the tree)"
+    )]
+    pub to_tag: Option<String>,
+}
+
+/// How templates are loaded and rendered, shared by the subcommands rendering templates
+#[derive(Debug, Args)]
+pub struct RendererArgs {
+    #[arg(
+        long,
+        help = "Load templates and partials from a directory, overriding built-in ones with the same name"
+    )]
+    pub template_dir: Vec<PathBuf>,
+    #[arg(
+        long,
+        help = "Define a template variable available as vars.KEY, e.g. --var=channel=nightly"
+    )]
+    pub var: Vec<String>,
+    #[arg(
+        long,
+        help = "Load template variables from a JSON or TOML (by extension) file, overridden by --var"
     )]
-    pub template: Vec<String>,
+    pub vars_file: Vec<PathBuf>,
+    #[arg(
+        long,
+        help = "The default timezone of date helpers in templates, e.g. Asia/Shanghai (default: UTC)"
+    )]
+    pub timezone: Option<String>,
+}
+
+#[derive(Debug, Args)]
+pub struct HistoryArgs {
+    #[arg(long, help = "The state repo")]
+    pub state: PathBuf,
+    #[arg(
+        long,
+        help = "The device triple, whose builds are the directories in STATE/DEVICE"
+    )]
+    pub device: String,
+    #[arg(
+        long,
+        help = "The config file (default: changelog.toml of the device or the state repo)"
+    )]
+    pub config: Option<PathBuf>,
     #[arg(
         short,
         long,
-        help = "Output the changelog as JSON"
+        help = "Render the pages of OUTPUT with a template, e.g. -t CHANGELOG.md@builtin:markdown/history \
+                (default: CHANGELOG.md and CHANGELOG.html in the directory of the device)"
+    )]
+    pub template: Vec<TemplateOutput>,
+    #[arg(
+        long,
+        help = "Order releases by build tag or by their newest change, the newest first (default: tag)"
+    )]
+    pub order: Option<HistoryOrderArg>,
+    #[arg(
+        long,
+        help = "The releases in a page, where pages after the first are written to e.g. CHANGELOG-2.md, 0 for a single page (default: 20)"
+    )]
+    pub per_page: Option<usize>,
+    #[command(flatten)]
+    pub renderer: RendererArgs,
+}
+
+#[derive(Debug, Args)]
+pub struct SiteArgs {
+    #[arg(long, help = "The state repo")]
+    pub state: PathBuf,
+    #[arg(
+        long,
+        help = "The device triple, whose builds are the directories in STATE/DEVICE"
     )]
-    pub json: Option<String>,
+    pub device: String,
+    #[arg(
+        long,
+        help = "The config file (default: changelog.toml of the device or the state repo)"
+    )]
+    pub config: Option<PathBuf>,
     #[arg(
         short,
         long,
-        help = "Output the changelog in debug format"
+        help = "The directory to write the site into, e.g. next to the OTA JSON published on GitHub Pages"
     )]
-    pub debug: Option<String>,
+    pub output: PathBuf,
+    #[arg(
+        long,
+        help = "Order releases by build tag or by their newest change, the newest first (default: tag)"
+    )]
+    pub order: Option<HistoryOrderArg>,
+    #[command(flatten)]
+    pub renderer: RendererArgs,
+}
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+pub enum HistoryOrderArg {
+    Tag,
+    Date,
+}
+
+impl From<HistoryOrderArg> for HistoryOrder {
+    fn from(order: HistoryOrderArg) -> Self {
+        match order {
+            HistoryOrderArg::Tag => HistoryOrder::Tag,
+            HistoryOrderArg::Date => HistoryOrder::Date,
+        }
+    }
+}
+
+/// `OUTPUT@TEMPLATE` of `--template`
+#[derive(Debug, Clone)]
+pub struct TemplateOutput {
+    pub output: PathBuf,
+    pub template: String,
+}
+
+impl FromStr for TemplateOutput {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let Some((output, template)) = s.split_once('@') else {
+            return Err(format!(
+                "{s} should specify output path and template path like -t=output@template"
+            ));
+        };
+        Ok(Self {
+            output: PathBuf::from(output),
+            template: template.to_string(),
+        })
+    }
+}
+
+/// `OUTPUT=MODE` of `--escape`
+#[derive(Debug, Clone)]
+pub struct EscapeArg {
+    pub output: PathBuf,
+    pub mode: EscapeMode,
+}
+
+impl FromStr for EscapeArg {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let Some((output, mode)) = s.split_once('=') else {
+            return Err(format!(
+                "{s} should specify output path and escape mode like --escape=output=markdown"
+            ));
+        };
+        Ok(Self {
+            output: PathBuf::from(output),
+            mode: mode.parse()?,
+        })
+    }
 }
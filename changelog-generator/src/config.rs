@@ -0,0 +1,831 @@
+//! `changelog.toml`, the configuration of the generator
+//!
+//! Every section is optional, e.g.
+//!
+//! ```toml
+//! [generate]
+//! recent_changes = 10         # changes listed for a newly added repo
+//! stamp_branch_prefix = "m/"  # the sync stamp branch is the prefix + the manifest branch
+//! hide_reverted = true
+//! mailmap = "mailmap"
+//! keep_going = true           # skip repos failing to generate, exiting with 7
+//!
+//! [filters]
+//! include = ["device/**", "vendor/**"]  # globs of repos, all repos if empty
+//! exclude = ["vendor/*/proprietary"]
+//! pathspec = { "frameworks/base" = ["core/java/android/hardware/**"] }
+//!
+//! # Links of changes, tried in order before the built-in rules for AOSP and GitHub
+//! [[forges]]
+//! match = "^https://gitlab\\.example\\.com/"  # a regex of the remote url
+//! commit_url = "{url}/-/commit/{commit}"
+//! review_url = "https://gitlab.example.com/q/{change-id}"
+//!
+//! # The first matching category is saved as `category` of a change
+//! [[categories]]
+//! name = "Security"
+//! match = { title = "(?i)security|CVE-\\d+" }
+//!
+//! # Changes matching any rule are left out
+//! [[suppress]]
+//! author = "^Translation Bot"
+//! repos = ["packages/apps/*"]
+//!
+//! [templates]
+//! dirs = ["templates"]
+//! timezone = "Asia/Shanghai"
+//! vars = { channel = "nightly" }
+//!
+//! # Parts of the changelog an output could be limited to
+//! [filter_sets.device]
+//! include = ["device/**", "kernel/**"]
+//! exclude = []
+//! categories = ["Security"]   # all changes if empty
+//! kinds = ["Normal", "Revert"]
+//! suppress = [{ title = "^Automatic translation import" }]
+//!
+//! # Outputs written by every run, or only those chosen by `--profile`
+//! [profiles.markdown]
+//! template = "builtin:markdown"
+//! destination = "changelog.md"
+//! escape = "markdown"         # inferred by default, see `--escape`
+//!
+//! [profiles.device]
+//! template = "builtin:markdown"
+//! destination = "changelog-device.md"
+//! filter = "device"
+//! locale = "zh-CN"            # of dates in the template, available as `meta.locale`
+//!
+//! [profiles.rss]
+//! template = "builtin:rss"
+//! destination = "changelog.xml"
+//!
+//! [profiles.json]
+//! format = "json"             # the changelog JSON like `--json`, without a template
+//! destination = "changelog.json"
+//! ```
+//!
+//! A rule (`match` of a category or a `suppress` entry) matches a change
+//! when all of its conditions hold:
+//! `title` and `author` are regexes of the title and the author name or email,
+//! `repos` and `paths` are globs of the repo and the touched paths.
+//! The touched paths are generated for the `suppress` rules of filter sets
+//! even without `list_paths`, but only listed in the outputs with it.
+//!
+//! Relative paths are relative to the directory of the config file,
+//! except that destinations are relative to `--output-dir` if it is given.
+
+use std::{
+    borrow::Cow,
+    collections::BTreeMap,
+    fmt, io,
+    path::{Path, PathBuf},
+};
+
+use arcstr::ArcStr;
+use chrono::Locale;
+use chrono_tz::Tz;
+use globset::{GlobBuilder, GlobMatcher};
+use regex::Regex;
+use serde::{Deserialize, Deserializer, de};
+use snafu::{ResultExt, Snafu};
+
+use crate::{
+    changelog::{Change, ChangeKind, ChangeLog, GenerateOptions},
+    mailmap::{Mailmap, MailmapError},
+    template::{EscapeMode, parse_locale},
+};
+
+/// The name of the config file searched in the tree
+pub const CONFIG_FILE: &str = "changelog.toml";
+
+#[derive(Debug, Snafu)]
+pub enum ConfigError {
+    #[snafu(display("failed to read config {path:?}"))]
+    Read { path: PathBuf, source: io::Error },
+    #[snafu(display("invalid config {path:?}"))]
+    Parse {
+        path: PathBuf,
+        source: toml::de::Error,
+    },
+    #[snafu(display("failed to load the mailmap of the config"))]
+    Mailmap { source: MailmapError },
+}
+
+pub type Result<T, E = ConfigError> = std::result::Result<T, E>;
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub generate: GenerateConfig,
+    pub filters: FilterConfig,
+    pub forges: Vec<ForgeRule>,
+    pub categories: Vec<CategoryRule>,
+    pub suppress: Vec<ChangeRule>,
+    pub templates: TemplatesConfig,
+    pub filter_sets: BTreeMap<String, FilterSet>,
+    pub profiles: BTreeMap<String, Profile>,
+    /// The directory of the config file, which relative destinations are relative to
+    #[serde(skip)]
+    pub dir: PathBuf,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct GenerateConfig {
+    /// How many recent changes of a newly added repo are listed
+    pub recent_changes: usize,
+    /// The sync stamp branch created by `repo sync` is this prefix followed by the manifest branch
+    pub stamp_branch_prefix: String,
+    pub hide_reverted: bool,
+    pub list_paths: bool,
+    pub mailmap: Option<PathBuf>,
+    /// Skip repos failing to generate instead of failing
+    pub keep_going: bool,
+}
+
+impl Default for GenerateConfig {
+    fn default() -> Self {
+        Self {
+            recent_changes: 10,
+            stamp_branch_prefix: "m/".to_string(),
+            hide_reverted: false,
+            list_paths: false,
+            mailmap: None,
+            keep_going: false,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct FilterConfig {
+    pub include: Vec<Glob>,
+    pub exclude: Vec<Glob>,
+    /// Only include changes touching these git pathspecs, per repo
+    pub pathspec: BTreeMap<String, Vec<String>>,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TemplatesConfig {
+    /// Load templates and partials from these directories
+    pub dirs: Vec<PathBuf>,
+    /// The default timezone of date helpers
+    #[serde(deserialize_with = "deserialize_timezone")]
+    pub timezone: Option<Tz>,
+    /// Available as `vars.KEY` in templates, overridden by `--vars-file` and `--var`
+    pub vars: serde_json::Map<String, serde_json::Value>,
+}
+
+/// A named output of a run, e.g. `[profiles.device]`
+#[derive(Debug, Clone, Deserialize)]
+#[serde(try_from = "RawProfile")]
+pub struct Profile {
+    pub format: ProfileFormat,
+    /// A template file, a built-in template like `builtin:markdown` or a template in `dirs`,
+    /// only of the template format
+    pub template: Option<String>,
+    pub destination: PathBuf,
+    /// The name of a filter set, the whole changelog if unset
+    pub filter: Option<String>,
+    /// The locale of dates in the template, English if unset
+    pub locale: Option<Locale>,
+    pub escape: Option<EscapeMode>,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ProfileFormat {
+    /// Render a handlebars template
+    #[default]
+    Template,
+    /// The changelog JSON like `--json`
+    Json,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RawProfile {
+    #[serde(default)]
+    format: ProfileFormat,
+    template: Option<String>,
+    destination: Option<PathBuf>,
+    filter: Option<String>,
+    #[serde(default, deserialize_with = "deserialize_locale")]
+    locale: Option<Locale>,
+    escape: Option<EscapeMode>,
+}
+
+impl TryFrom<RawProfile> for Profile {
+    type Error = &'static str;
+
+    fn try_from(raw: RawProfile) -> Result<Self, Self::Error> {
+        let RawProfile {
+            format,
+            template,
+            destination,
+            filter,
+            locale,
+            escape,
+        } = raw;
+        let Some(destination) = destination else {
+            return Err("a profile needs a destination to write to");
+        };
+        match (format, &template) {
+            (ProfileFormat::Template, None) => {
+                return Err("a profile of the template format needs a template");
+            }
+            (ProfileFormat::Json, Some(_)) => {
+                return Err("a profile of the json format takes no template");
+            }
+            _ => {}
+        }
+        Ok(Self {
+            format,
+            template,
+            destination,
+            filter,
+            locale,
+            escape,
+        })
+    }
+}
+
+/// The part of the changelog an output profile shows
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct FilterSet {
+    /// Globs of repos, all repos if empty
+    pub include: Vec<Glob>,
+    pub exclude: Vec<Glob>,
+    /// Only changes of these categories, all changes if empty
+    pub categories: Vec<ArcStr>,
+    /// Only changes of these kinds, all changes if empty
+    pub kinds: Vec<ChangeKind>,
+    /// Changes matching any rule are left out
+    pub suppress: Vec<ChangeRule>,
+}
+
+impl FilterSet {
+    pub fn matches_repo(&self, repo: &str) -> bool {
+        repo_matches(&self.include, &self.exclude, repo)
+    }
+
+    pub fn matches(&self, change: &Change) -> bool {
+        self.matches_repo(&change.repo)
+            && (self.categories.is_empty()
+                || change
+                    .category
+                    .as_ref()
+                    .is_some_and(|category| self.categories.contains(category)))
+            && (self.kinds.is_empty() || self.kinds.contains(&change.kind))
+            && !self.suppress.iter().any(|rule| rule.matches(change))
+    }
+
+    /// Whether a rule matches the paths touched by changes, see [`Config::changelog_of`]
+    pub fn uses_paths(&self) -> bool {
+        self.suppress.iter().any(|rule| !rule.paths.is_empty())
+    }
+
+    /// The part of `changelog` matching the filters
+    pub fn apply(&self, changelog: &ChangeLog) -> ChangeLog {
+        changelog.filtered(
+            |repo| self.matches_repo(repo),
+            |change| self.matches(change),
+        )
+    }
+}
+
+/// How to link to the commits and reviews of repos hosted on a forge
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ForgeRule {
+    /// Matched against the url of the remote
+    #[serde(rename = "match")]
+    pub url: Pattern,
+    /// Changes are not linked if unset
+    pub commit_url: Option<UrlTemplate>,
+    pub review_url: Option<UrlTemplate>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct CategoryRule {
+    pub name: ArcStr,
+    #[serde(rename = "match")]
+    pub rule: ChangeRule,
+}
+
+/// Conditions on a change, which must all hold
+#[derive(Debug, Clone, Deserialize)]
+#[serde(try_from = "RawChangeRule")]
+pub struct ChangeRule {
+    pub title: Option<Pattern>,
+    pub author: Option<Pattern>,
+    pub repos: Vec<Glob>,
+    pub paths: Vec<Glob>,
+}
+
+#[derive(Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct RawChangeRule {
+    title: Option<Pattern>,
+    author: Option<Pattern>,
+    repos: Vec<Glob>,
+    paths: Vec<Glob>,
+}
+
+impl TryFrom<RawChangeRule> for ChangeRule {
+    type Error = &'static str;
+
+    fn try_from(raw: RawChangeRule) -> Result<Self, Self::Error> {
+        let RawChangeRule {
+            title,
+            author,
+            repos,
+            paths,
+        } = raw;
+        if title.is_none() && author.is_none() && repos.is_empty() && paths.is_empty() {
+            return Err("a rule needs at least one of title, author, repos and paths");
+        }
+        Ok(Self {
+            title,
+            author,
+            repos,
+            paths,
+        })
+    }
+}
+
+impl ChangeRule {
+    pub fn matches(&self, change: &Change) -> bool {
+        let author = &change.author;
+        self.title
+            .as_ref()
+            .is_none_or(|title| title.is_match(&change.title))
+            && self.author.as_ref().is_none_or(|pattern| {
+                [
+                    &author.name,
+                    &author.email,
+                    &author.canonical.name,
+                    &author.canonical.email,
+                ]
+                .iter()
+                .any(|s| pattern.is_match(s))
+            })
+            && (self.repos.is_empty() || self.repos.iter().any(|g| g.is_match(&change.repo)))
+            && (self.paths.is_empty()
+                || change
+                    .diffstat
+                    .paths
+                    .iter()
+                    .flatten()
+                    .any(|path| self.paths.iter().any(|g| g.is_match(path))))
+    }
+}
+
+/// Repos to generate the changelog of
+#[derive(Debug, Clone, Default)]
+pub struct RepoFilter {
+    /// All repos if empty
+    pub include: Vec<Glob>,
+    pub exclude: Vec<Glob>,
+}
+
+impl RepoFilter {
+    pub fn matches(&self, repo: &str) -> bool {
+        repo_matches(&self.include, &self.exclude, repo)
+    }
+}
+
+fn repo_matches(include: &[Glob], exclude: &[Glob], repo: &str) -> bool {
+    (include.is_empty() || include.iter().any(|g| g.is_match(repo)))
+        && !exclude.iter().any(|g| g.is_match(repo))
+}
+
+/// A regex
+#[derive(Debug, Clone)]
+pub struct Pattern(Regex);
+
+impl Pattern {
+    pub fn is_match(&self, haystack: &str) -> bool {
+        self.0.is_match(haystack)
+    }
+}
+
+impl<'de> Deserialize<'de> for Pattern {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let pattern = String::deserialize(deserializer)?;
+        Regex::new(&pattern).map(Self).map_err(de::Error::custom)
+    }
+}
+
+/// A glob of paths, where `*` does not match `/` but `**` does
+#[derive(Debug, Clone)]
+pub struct Glob(GlobMatcher);
+
+impl Glob {
+    pub fn new(glob: &str) -> Result<Self, globset::Error> {
+        let glob = GlobBuilder::new(glob).literal_separator(true).build()?;
+        Ok(Self(glob.compile_matcher()))
+    }
+
+    pub fn is_match(&self, path: &str) -> bool {
+        self.0.is_match(path)
+    }
+}
+
+impl<'de> Deserialize<'de> for Glob {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let glob = String::deserialize(deserializer)?;
+        Self::new(&glob).map_err(de::Error::custom)
+    }
+}
+
+/// A url with placeholders `{url}` (of the remote), `{commit}` and `{change-id}`
+#[derive(Debug, Clone)]
+pub struct UrlTemplate(String);
+
+impl UrlTemplate {
+    const PLACEHOLDERS: &[&str] = &["url", "commit", "change-id"];
+
+    /// Fill in the url of the remote, leaving the other placeholders for each change
+    pub fn with_remote(&self, url: &str) -> String {
+        self.0.replace("{url}", url)
+    }
+}
+
+impl fmt::Display for UrlTemplate {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl<'de> Deserialize<'de> for UrlTemplate {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let template = String::deserialize(deserializer)?;
+        let mut rest = template.as_str();
+        while let Some(start) = rest.find('{') {
+            let Some(len) = rest[start..].find('}') else {
+                return Err(de::Error::custom("unclosed placeholder"));
+            };
+            let name = &rest[start + 1..start + len];
+            if !Self::PLACEHOLDERS.contains(&name) {
+                return Err(de::Error::custom(format!(
+                    "unknown placeholder {{{name}}}, expected {{url}}, {{commit}} or {{change-id}}"
+                )));
+            }
+            rest = &rest[start + len..];
+        }
+        Ok(Self(template))
+    }
+}
+
+fn deserialize_timezone<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Option<Tz>, D::Error> {
+    let timezone = String::deserialize(deserializer)?;
+    timezone.parse().map(Some).map_err(de::Error::custom)
+}
+
+fn deserialize_locale<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Option<Locale>, D::Error> {
+    let locale = String::deserialize(deserializer)?;
+    parse_locale(&locale)
+        .map(Some)
+        .ok_or_else(|| de::Error::custom(format!("unknown locale {locale:?}, expected e.g. zh-CN")))
+}
+
+impl Config {
+    /// Find the config of a tree, in its top directory or its state repo.
+    pub fn find(tree: impl AsRef<Path>) -> Option<PathBuf> {
+        let tree = tree.as_ref();
+        [tree.join(CONFIG_FILE), tree.join("state").join(CONFIG_FILE)]
+            .into_iter()
+            .find(|path| path.is_file())
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let content = std::fs::read_to_string(path).context(ReadSnafu { path })?;
+        let mut config = Self::parse(&content).context(ParseSnafu { path })?;
+        if let Some(dir) = path.parent() {
+            config.resolve_paths(dir);
+        }
+        Ok(config)
+    }
+
+    pub fn parse(content: &str) -> Result<Self, toml::de::Error> {
+        let config: Self = toml::from_str(content)?;
+        for (name, profile) in &config.profiles {
+            if let Some(filter) = &profile.filter
+                && !config.filter_sets.contains_key(filter)
+            {
+                return Err(de::Error::custom(format!(
+                    "profiles.{name}.filter: unknown filter set {filter:?}"
+                )));
+            }
+        }
+        Ok(config)
+    }
+
+    /// Make relative paths relative to `dir`
+    fn resolve_paths(&mut self, dir: &Path) {
+        if let Some(mailmap) = &mut self.generate.mailmap {
+            *mailmap = dir.join(&*mailmap);
+        }
+        for template_dir in &mut self.templates.dirs {
+            *template_dir = dir.join(&*template_dir);
+        }
+        for template in self
+            .profiles
+            .values_mut()
+            .filter_map(|p| p.template.as_mut())
+        {
+            // Could also be a built-in template or a template in `dirs`
+            let path = dir.join(&*template);
+            if path.is_file() {
+                *template = path.to_string_lossy().into_owned();
+            }
+        }
+        self.dir = dir.to_path_buf();
+    }
+
+    /// Whether a filter set needs the paths touched by changes, so that they should be
+    /// generated even without `list_paths`
+    pub fn filters_by_paths(&self) -> bool {
+        self.filter_sets.values().any(FilterSet::uses_paths)
+    }
+
+    /// The changelog `profile` shows, limited by its filter set.
+    ///
+    /// The paths touched by changes are left out afterwards unless `list_paths`,
+    /// as they may have been generated only for the filter set.
+    pub fn changelog_of<'a>(
+        &self,
+        profile: &Profile,
+        changelog: &'a ChangeLog,
+        list_paths: bool,
+    ) -> Cow<'a, ChangeLog> {
+        let mut changelog = match &profile.filter {
+            Some(filter) => Cow::Owned(self.filter_sets[filter].apply(changelog)),
+            None => Cow::Borrowed(changelog),
+        };
+        // Without filter sets by paths, they have not been generated in the first place
+        if !list_paths && self.filters_by_paths() {
+            changelog.to_mut().strip_paths();
+        }
+        changelog
+    }
+
+    /// Where to write a profile, relative to `output_dir` or else the directory of the config
+    pub fn destination(&self, profile: &Profile, output_dir: Option<&Path>) -> PathBuf {
+        output_dir.unwrap_or(&self.dir).join(&profile.destination)
+    }
+
+    pub fn generate_options(&self) -> Result<GenerateOptions> {
+        let mailmap = match &self.generate.mailmap {
+            Some(path) => Mailmap::load(path).context(MailmapSnafu)?,
+            None => Mailmap::default(),
+        };
+        let pathspecs = self
+            .filters
+            .pathspec
+            .iter()
+            .map(|(repo, pathspecs)| {
+                let pathspecs = pathspecs.iter().map(|p| ArcStr::from(p.as_str()));
+                (
+                    ArcStr::from(repo.trim_end_matches('/')),
+                    pathspecs.collect(),
+                )
+            })
+            .collect();
+        Ok(GenerateOptions {
+            hide_reverted: self.generate.hide_reverted,
+            mailmap,
+            list_paths: self.generate.list_paths,
+            pathspecs,
+            repos: RepoFilter {
+                include: self.filters.include.clone(),
+                exclude: self.filters.exclude.clone(),
+            },
+            recent_changes: self.generate.recent_changes,
+            stamp_branch_prefix: ArcStr::from(self.generate.stamp_branch_prefix.as_str()),
+            forges: self.forges.clone(),
+            categories: self.categories.clone(),
+            suppress: self.suppress.clone(),
+            keep_going: self.generate.keep_going,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::changelog::ChangeLog;
+
+    use super::*;
+
+    #[test]
+    fn test_empty_config() {
+        let config = Config::parse("").unwrap();
+        assert_eq!(config.generate.recent_changes, 10);
+        assert_eq!(config.generate.stamp_branch_prefix, "m/");
+        assert!(config.profiles.is_empty());
+    }
+
+    #[test]
+    fn test_full_config() {
+        let config = Config::parse(
+            r#"
+[generate]
+recent_changes = 5
+stamp_branch_prefix = "stamp/"
+
+[filters]
+include = ["device/**", "system/*"]
+exclude = ["device/bar"]
+pathspec = { "frameworks/base/" = ["core"] }
+
+[[forges]]
+match = "^https://gitlab\\.example\\.com/"
+commit_url = "{url}/-/commit/{commit}"
+
+[[categories]]
+name = "Init"
+match = { title = "^init:", repos = ["system/*"] }
+
+[[suppress]]
+author = "(?i)^alice$"
+paths = ["**/*.md"]
+
+[templates]
+timezone = "Asia/Shanghai"
+vars = { channel = "nightly" }
+
+[filter_sets.init]
+include = ["system/*"]
+categories = ["Init"]
+
+[profiles.markdown]
+template = "builtin:markdown"
+destination = "changelog.md"
+escape = "markdown"
+
+[profiles.init]
+format = "json"
+destination = "init/changelog.json"
+filter = "init"
+locale = "zh-CN"
+"#,
+        )
+        .unwrap();
+        let options = config.generate_options().unwrap();
+        assert_eq!(options.recent_changes, 5);
+        assert_eq!(options.pathspec("frameworks/base"), ["core"]);
+        assert!(options.repos.matches("device/foo/sub"));
+        assert!(options.repos.matches("system/core"));
+        assert!(!options.repos.matches("system/core/sub"));
+        assert!(!options.repos.matches("device/bar"));
+        assert_eq!(
+            options.forges[0]
+                .commit_url
+                .as_ref()
+                .unwrap()
+                .with_remote("https://gitlab.example.com/foo"),
+            "https://gitlab.example.com/foo/-/commit/{commit}"
+        );
+        let change = &ChangeLog::sample().log[0];
+        assert!(options.categories[0].rule.matches(change));
+        // The sample is authored by Alice and touches README.md
+        assert!(options.suppress[0].matches(change));
+        assert_eq!(config.templates.timezone, Some(Tz::Asia__Shanghai));
+        let markdown = &config.profiles["markdown"];
+        assert_eq!(markdown.format, ProfileFormat::Template);
+        assert_eq!(markdown.escape, Some(EscapeMode::Markdown));
+        let init = &config.profiles["init"];
+        assert_eq!(init.format, ProfileFormat::Json);
+        assert_eq!(init.locale, Some(Locale::zh_CN));
+        assert_eq!(
+            config.destination(init, Some(Path::new("out"))),
+            Path::new("out/init/changelog.json")
+        );
+    }
+
+    #[test]
+    fn test_filter_set() {
+        let config = Config::parse(
+            r#"
+[filter_sets.a]
+include = ["system/*"]
+kinds = ["Merge"]
+
+[filter_sets.b]
+exclude = ["system/core"]
+"#,
+        )
+        .unwrap();
+        let sample = ChangeLog::sample();
+        let a = config.filter_sets["a"].apply(&sample);
+        assert!(a.log.is_empty());
+        assert!(a.changes.is_empty());
+        assert!(a.contributors.authors.is_empty());
+        assert!(a.contributors.first_time.is_empty());
+        let b = config.filter_sets["b"].apply(&sample);
+        assert!(b.log.iter().all(|change| change.repo != "system/core"));
+        assert!(!b.changes.contains_key("system/core"));
+        // Nothing is left out without filters
+        let all = FilterSet::default().apply(&sample);
+        assert_eq!(
+            serde_json::to_value(&all).unwrap(),
+            serde_json::to_value(&sample).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_filter_set_by_paths() {
+        let config = Config::parse(
+            r#"
+[filter_sets.code]
+suppress = [{ paths = ["**/*.md"] }]
+
+[profiles.code]
+format = "json"
+destination = "code.json"
+filter = "code"
+
+[profiles.all]
+format = "json"
+destination = "all.json"
+"#,
+        )
+        .unwrap();
+        assert!(config.filters_by_paths());
+        // Generated with the paths for the filter set, though they are not listed
+        let sample = ChangeLog::sample();
+        let code = config.changelog_of(&config.profiles["code"], &sample, false);
+        assert!(code.log.is_empty());
+        let all = config.changelog_of(&config.profiles["all"], &sample, false);
+        assert_eq!(all.log.len(), sample.log.len());
+        assert!(all.log.iter().all(|c| c.diffstat.paths.is_none()));
+        assert!(all.changes.values().all(|r| r.diffstat.paths.is_none()));
+        let listed = config.changelog_of(&config.profiles["all"], &sample, true);
+        assert!(listed.log[0].diffstat.paths.is_some());
+    }
+
+    /// Errors should point at the offending key
+    fn error(config: &str) -> String {
+        Config::parse(config).unwrap_err().to_string()
+    }
+
+    #[test]
+    fn test_unknown_key() {
+        let err = error("[generate]\nrecent_change = 5\n");
+        assert!(err.contains("line 2, column 1"), "{err}");
+        assert!(err.contains("unknown field `recent_change`"), "{err}");
+    }
+
+    #[test]
+    fn test_invalid_values() {
+        let err = error("[[forges]]\nmatch = \"(\"\n");
+        assert!(err.contains("line 2, column 9"), "{err}");
+        let err = error("[[forges]]\nmatch = \"x\"\ncommit_url = \"{url}/{sha}\"\n");
+        assert!(err.contains("line 3"), "{err}");
+        assert!(err.contains("unknown placeholder {sha}"), "{err}");
+        let err = error("[templates]\ntimezone = \"Mars/Base\"\n");
+        assert!(err.contains("line 2"), "{err}");
+        let err =
+            error("[profiles.a]\ndestination = \"a.md\"\ntemplate = \"x\"\nescape = \"rst\"\n");
+        assert!(err.contains("unknown escape mode"), "{err}");
+        let err =
+            error("[profiles.a]\ndestination = \"a.md\"\ntemplate = \"x\"\nlocale = \"xx\"\n");
+        assert!(
+            err.contains("line 4") && err.contains("unknown locale"),
+            "{err}"
+        );
+        let err = error("[[suppress]]\n");
+        assert!(err.contains("at least one of"), "{err}");
+    }
+
+    #[test]
+    fn test_invalid_profiles() {
+        let err = error("[profiles.a]\ntemplate = \"builtin:markdown\"\n");
+        assert!(err.contains("line 1"), "{err}");
+        assert!(err.contains("needs a destination"), "{err}");
+        let err = error("[profiles.a]\ndestination = \"a.md\"\n");
+        assert!(err.contains("needs a template"), "{err}");
+        let err = error("[profiles.a]\nformat = \"json\"\ntemplate = \"x\"\ndestination = \"a\"\n");
+        assert!(err.contains("takes no template"), "{err}");
+        let err = error("[profiles.a]\nformat = \"rss\"\ndestination = \"a\"\n");
+        assert!(
+            err.contains("line 2") && err.contains("unknown variant `rss`"),
+            "{err}"
+        );
+        let err = error("[profiles.a]\ntemplate = \"x\"\ndestination = \"a\"\nfilter = \"b\"\n");
+        assert!(
+            err.contains("profiles.a.filter: unknown filter set \"b\""),
+            "{err}"
+        );
+    }
+}
@@ -0,0 +1,199 @@
+//! Summarize the people behind a [`ChangeLog`](crate::changelog::ChangeLog)
+
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+
+use arcstr::ArcStr;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+use crate::{changelog::Change, mailmap::Identity, repo_log::RepoChangeLog};
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize, JsonSchema)]
+pub struct Contributors {
+    /// Unique canonical authors, the most active first
+    pub authors: Vec<Contributor>,
+    /// Authors who have no commits before the source snapshot
+    /// in any of the repos they contributed to
+    pub first_time: Vec<Identity>,
+    /// People credited with `Co-authored-by:` trailers
+    pub co_authors: Vec<Contributor>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub struct Contributor {
+    #[serde(flatten)]
+    pub identity: Identity,
+    /// The number of changes
+    pub changes: usize,
+}
+
+impl Contributors {
+    /// Collect contributors of the changes in `log`.
+    ///
+    /// `prior_authors` is called once per repo with the raw and canonical emails of its
+    /// authors, and should report which of them, lowercased, authored a commit in the repo
+    /// before the source snapshot, also under another email mapped to the same person.
+    /// If that is unknown for a repo, it should return `None`,
+    /// and none of the authors of the repo are reported as first-time contributors.
+    pub fn collect<E>(
+        log: &[Change],
+        changes: &BTreeMap<ArcStr, RepoChangeLog>,
+        mut prior_authors: impl FnMut(&ArcStr, &[&str]) -> Result<Option<HashSet<ArcStr>>, E>,
+    ) -> Result<Self, E> {
+        let authors = count(log.iter().map(|c| &c.author.canonical));
+        let co_authors = count(log.iter().flat_map(|c| &c.co_authors));
+
+        // canonical email -> repo -> raw emails
+        let mut emails: HashMap<ArcStr, BTreeMap<&ArcStr, BTreeSet<&str>>> = HashMap::new();
+        // repo -> raw emails of all its authors
+        let mut repo_emails: BTreeMap<&ArcStr, BTreeSet<&str>> = BTreeMap::new();
+        for (repo, repo_changelog) in changes {
+            for change in &repo_changelog.logs {
+                let repos = emails.entry(key(&change.author.canonical)).or_default();
+                let raw = repos.entry(repo).or_default();
+                raw.insert(&change.author.email);
+                raw.insert(&change.author.canonical.email);
+                repo_emails
+                    .entry(repo)
+                    .or_default()
+                    .extend(raw.iter().copied());
+            }
+        }
+        let mut prior = HashMap::new();
+        for (repo, raw) in repo_emails {
+            let raw: Vec<&str> = raw.into_iter().collect();
+            prior.insert(repo, prior_authors(repo, &raw)?);
+        }
+        let mut first_time = Vec::new();
+        for author in &authors {
+            let Some(repos) = emails.get(&key(&author.identity)) else {
+                // Only contributed to newly added repos
+                continue;
+            };
+            let is_first_time = repos.iter().all(|(repo, raw)| match &prior[repo] {
+                Some(prior) => !raw
+                    .iter()
+                    .any(|e| prior.contains(e.to_lowercase().as_str())),
+                None => false,
+            });
+            if is_first_time {
+                first_time.push(author.identity.clone());
+            }
+        }
+        Ok(Self {
+            authors,
+            first_time,
+            co_authors,
+        })
+    }
+}
+
+/// Emails are case-insensitive
+fn key(identity: &Identity) -> ArcStr {
+    ArcStr::from(identity.email.to_lowercase())
+}
+
+fn count<'a>(identities: impl Iterator<Item = &'a Identity>) -> Vec<Contributor> {
+    let mut contributors: Vec<Contributor> = Vec::new();
+    let mut index: HashMap<ArcStr, usize> = HashMap::new();
+    for identity in identities {
+        let i = *index.entry(key(identity)).or_insert_with(|| {
+            contributors.push(Contributor {
+                identity: identity.clone(),
+                changes: 0,
+            });
+            contributors.len() - 1
+        });
+        contributors[i].changes += 1;
+    }
+    contributors.sort_by(|a, b| {
+        b.changes
+            .cmp(&a.changes)
+            .then_with(|| a.identity.name.cmp(&b.identity.name))
+    });
+    contributors
+}
+
+#[cfg(test)]
+mod tests {
+    use std::convert::Infallible;
+
+    use chrono::{TimeZone, Utc};
+
+    use super::*;
+    use crate::changelog::Signature;
+
+    fn identity(name: &str, email: &str) -> Identity {
+        Identity {
+            name: ArcStr::from(name),
+            email: ArcStr::from(email),
+        }
+    }
+
+    fn change(repo: &str, author: Identity) -> Change {
+        Change::test(repo, "aaaa").signed(Signature {
+            name: author.name.clone(),
+            email: author.email.clone(),
+            date: Utc.timestamp_opt(0, 0).unwrap(),
+            canonical: author,
+        })
+    }
+
+    #[test]
+    fn test_collect() {
+        let alice = identity("Alice", "alice@example.com");
+        let bob = identity("Bob", "bob@example.com");
+        let carol = identity("Carol", "carol@example.com");
+        let mut with_co_author = change("device/foo", bob.clone());
+        with_co_author.co_authors.push(carol.clone());
+        let log = vec![
+            change("device/foo", alice.clone()),
+            change("device/foo", identity("alice", "Alice@Example.com")),
+            with_co_author,
+            change("device/new", carol.clone()),
+        ];
+        let changes = BTreeMap::from([(
+            ArcStr::from("device/foo"),
+            RepoChangeLog::new(log[..3].to_vec(), Vec::new()),
+        )]);
+        let mut calls = 0;
+        let contributors = Contributors::collect(&log, &changes, |repo, emails| {
+            calls += 1;
+            assert_eq!(repo, "device/foo");
+            assert!(emails.contains(&"Alice@Example.com") && emails.contains(&"bob@example.com"));
+            let prior = HashSet::from([ArcStr::from("alice@example.com")]);
+            Ok::<_, Infallible>(Some(prior))
+        })
+        .unwrap();
+        // once per repo, not per author
+        assert_eq!(calls, 1);
+        assert_eq!(
+            contributors.authors,
+            [
+                Contributor {
+                    identity: alice,
+                    changes: 2
+                },
+                Contributor {
+                    identity: bob.clone(),
+                    changes: 1
+                },
+                Contributor {
+                    identity: carol.clone(),
+                    changes: 1
+                },
+            ]
+        );
+        // carol only contributed to a new repo
+        assert_eq!(contributors.first_time, [bob]);
+        let unknown = Contributors::collect(&log, &changes, |_, _| Ok::<_, Infallible>(None));
+        assert!(unknown.unwrap().first_time.is_empty());
+        assert_eq!(
+            contributors.co_authors,
+            [Contributor {
+                identity: carol,
+                changes: 1
+            }]
+        );
+    }
+}
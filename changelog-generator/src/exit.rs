@@ -0,0 +1,68 @@
+//! Exit codes, so that scripts could tell why a run failed
+
+use std::{fmt, process::ExitCode};
+
+use color_eyre::Report;
+use serde::Serialize;
+
+/// How a run ended
+///
+/// Failures are attached to errors as context with `wrap_err`,
+/// so that the outermost one decides the exit code.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ExitStatus {
+    Success = 0,
+    /// Any other failure, e.g. invalid arguments or config
+    Failure = 1,
+    /// A snapshot could not be read or parsed
+    BadSnapshot = 3,
+    /// The tree is not a directory
+    MissingTree = 4,
+    /// git or repo failed
+    Git = 5,
+    /// A template could not be loaded or rendered
+    Template = 6,
+    /// The outputs are written, but some repos failed and were skipped
+    PartialSuccess = 7,
+    /// There are no changes, so no outputs are written
+    NoChanges = 8,
+    /// The state repo has no previous build to compare with
+    NoPreviousBuild = 9,
+}
+
+impl ExitStatus {
+    pub fn code(self) -> u8 {
+        self as u8
+    }
+
+    /// The status attached to an error, or else [`ExitStatus::Failure`]
+    pub fn of(report: &Report) -> Self {
+        report
+            .downcast_ref::<Self>()
+            .copied()
+            .unwrap_or(Self::Failure)
+    }
+}
+
+impl From<ExitStatus> for ExitCode {
+    fn from(status: ExitStatus) -> Self {
+        Self::from(status.code())
+    }
+}
+
+impl fmt::Display for ExitStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Success => "success",
+            Self::Failure => "failure",
+            Self::BadSnapshot => "bad input snapshot",
+            Self::MissingTree => "missing tree",
+            Self::Git => "git failed",
+            Self::Template => "template failed",
+            Self::PartialSuccess => "some repos failed",
+            Self::NoChanges => "no changes",
+            Self::NoPreviousBuild => "no previous build",
+        })
+    }
+}
@@ -0,0 +1,445 @@
+//! Combine the changelogs saved in the state repo into the history of a device,
+//! which is rendered into pages by templates like `markdown/history`.
+
+use std::{
+    cmp::Ordering,
+    collections::HashSet,
+    fs::File,
+    io::{self, BufReader},
+    path::PathBuf,
+};
+
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use snafu::{ResultExt, Snafu};
+use tracing::debug;
+
+use crate::{
+    changelog::ChangeLog,
+    schema::{self, SchemaError},
+    state::{DeviceState, StateError},
+    template::Meta,
+};
+
+/// The changelog saved in the directory of a build
+pub const CHANGELOG_FILE: &str = "changelog.json";
+
+#[derive(Debug, Snafu)]
+pub enum HistoryError {
+    #[snafu(display("failed to list the builds"))]
+    State { source: StateError },
+    #[snafu(display("failed to open changelog {path:?}"))]
+    Open { path: PathBuf, source: io::Error },
+    #[snafu(display("failed to parse changelog {path:?}"))]
+    Parse { path: PathBuf, source: SchemaError },
+}
+
+pub type Result<T, E = HistoryError> = std::result::Result<T, E>;
+
+/// How releases are ordered, the newest first
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum HistoryOrder {
+    /// By build tag, comparing numbers in tags numerically
+    #[default]
+    Tag,
+    /// By the newest change, then by build tag
+    Date,
+}
+
+/// The changelog of a build
+#[derive(Debug, Clone, Serialize)]
+pub struct Release {
+    pub tag: String,
+    /// The id of the section of the release, derived from the tag
+    pub anchor: String,
+    /// The build the changelog is generated from, see [`ChangeLog::source_tag`]
+    pub previous_tag: Option<String>,
+    /// The date of the newest change
+    pub date: Option<DateTime<Utc>>,
+    #[serde(flatten)]
+    pub changelog: ChangeLog,
+    /// The source tag is not saved, so it is guessed as the next older release
+    #[serde(skip)]
+    guess_previous: bool,
+}
+
+/// The releases of a device, the newest first
+#[derive(Debug, Clone, Default)]
+pub struct History {
+    pub releases: Vec<Release>,
+}
+
+impl History {
+    /// Read the changelog of every build of a device, skipping builds without one
+    pub fn load(device: &DeviceState, order: HistoryOrder) -> Result<Self> {
+        let mut releases = Vec::new();
+        for tag in device.builds().context(StateSnafu)? {
+            let path = device.dir.join(&tag).join(CHANGELOG_FILE);
+            let file = match File::open(&path) {
+                Ok(file) => file,
+                Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                    debug!(tag, "no changelog");
+                    continue;
+                }
+                Err(source) => return Err(HistoryError::Open { path, source }),
+            };
+            let (changelog, version) =
+                schema::read_versioned(BufReader::new(file)).context(ParseSnafu { path })?;
+            releases.push(if version < 2 {
+                Release::without_source(tag, changelog)
+            } else {
+                Release::new(tag, changelog)
+            });
+        }
+        Ok(Self::new(releases, order))
+    }
+
+    /// Order the releases, guessing the previous release of those without a source tag
+    pub fn new(mut releases: Vec<Release>, order: HistoryOrder) -> Self {
+        releases.sort_by(|a, b| match order {
+            HistoryOrder::Tag => natural_cmp(&b.tag, &a.tag),
+            HistoryOrder::Date => b
+                .date
+                .cmp(&a.date)
+                .then_with(|| natural_cmp(&b.tag, &a.tag)),
+        });
+        let older: Vec<_> = releases.iter().skip(1).map(|r| r.tag.clone()).collect();
+        for (release, older) in releases.iter_mut().zip(older) {
+            if release.guess_previous {
+                release.previous_tag = Some(older);
+            }
+        }
+        // Tags like `a.b` and `a-b` have the same anchor
+        let mut anchors = Anchors::default();
+        for release in &mut releases {
+            release.anchor = anchors.unique(&release.tag);
+        }
+        Self { releases }
+    }
+
+    /// Split into pages of `per_page` releases, or a single page if it is 0.
+    ///
+    /// The first page is written to `file` and the following ones to e.g. `CHANGELOG-2.md`.
+    /// There is always a page, which may have no releases.
+    pub fn pages(&self, file: &str, per_page: usize) -> Vec<Page<'_>> {
+        let chunks: Vec<&[Release]> = match per_page {
+            0 => vec![&self.releases],
+            n => self.releases.chunks(n).collect(),
+        };
+        let chunks = if chunks.is_empty() {
+            vec![&[][..]]
+        } else {
+            chunks
+        };
+        let links: Vec<PageLink> = chunks
+            .iter()
+            .enumerate()
+            .map(|(i, releases)| PageLink {
+                number: i + 1,
+                file: page_file(file, i + 1),
+                first_tag: releases.first().map(|r| r.tag.clone()),
+                last_tag: releases.last().map(|r| r.tag.clone()),
+            })
+            .collect();
+        chunks
+            .into_iter()
+            .enumerate()
+            .map(|(i, releases)| Page {
+                number: i + 1,
+                total: links.len(),
+                file: links[i].file.clone(),
+                prev: i.checked_sub(1).map(|j| links[j].file.clone()),
+                next: links.get(i + 1).map(|l| l.file.clone()),
+                pages: links.clone(),
+                releases,
+            })
+            .collect()
+    }
+}
+
+impl Release {
+    pub fn new(tag: String, changelog: ChangeLog) -> Self {
+        Self {
+            anchor: anchor(&tag),
+            date: changelog.log.iter().map(|c| c.datetime).max(),
+            previous_tag: changelog.source_tag.clone(),
+            tag,
+            changelog,
+            guess_previous: false,
+        }
+    }
+
+    /// A release of a changelog written before schema version 2,
+    /// which does not record the tag of its source snapshot
+    pub fn without_source(tag: String, changelog: ChangeLog) -> Self {
+        Self {
+            guess_previous: true,
+            ..Self::new(tag, changelog)
+        }
+    }
+}
+
+/// A page of [`History`], which is what history templates see together with `meta` and `vars`
+#[derive(Debug, Clone, Serialize)]
+pub struct Page<'a> {
+    /// Starting from 1
+    pub number: usize,
+    pub total: usize,
+    /// The file name of this page
+    pub file: String,
+    pub prev: Option<String>,
+    pub next: Option<String>,
+    /// All the pages, for navigation
+    pub pages: Vec<PageLink>,
+    pub releases: &'a [Release],
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct PageLink {
+    pub number: usize,
+    pub file: String,
+    /// The newest release in the page
+    pub first_tag: Option<String>,
+    /// The oldest release in the page
+    pub last_tag: Option<String>,
+}
+
+/// What history templates could see
+#[derive(Debug, Serialize)]
+pub struct HistoryContext<'a> {
+    pub meta: Meta,
+    pub page: Page<'a>,
+    /// User defined variables from `--vars-file` and `--var`
+    pub vars: serde_json::Map<String, serde_json::Value>,
+}
+
+impl HistoryContext<'_> {
+    /// A serialized context built from [`ChangeLog::sample`], for checking templates
+    pub(crate) fn sample() -> serde_json::Value {
+        let history = History::new(
+            vec![
+                Release::new("build-2".to_string(), ChangeLog::sample()),
+                Release::without_source("build-1".to_string(), ChangeLog::sample()),
+            ],
+            HistoryOrder::Tag,
+        );
+        let context = HistoryContext {
+            meta: Meta::default(),
+            page: history.pages("CHANGELOG.md", 0).remove(0),
+            vars: Default::default(),
+        };
+        serde_json::to_value(context).expect("the history context is always serializable")
+    }
+}
+
+/// The file name of a page, e.g. `CHANGELOG.md`, `CHANGELOG-2.md`
+pub fn page_file(file: &str, number: usize) -> String {
+    if number <= 1 {
+        return file.to_string();
+    }
+    match file.rsplit_once('.') {
+        Some((stem, extension)) if !stem.is_empty() => format!("{stem}-{number}.{extension}"),
+        _ => format!("{file}-{number}"),
+    }
+}
+
+/// Ids that are unique among the ones created by the same instance
+#[derive(Debug, Default)]
+pub(crate) struct Anchors(HashSet<String>);
+
+impl Anchors {
+    /// The anchor of `text`, numbered if it is taken, e.g. `a-b-2`
+    pub(crate) fn unique(&mut self, text: &str) -> String {
+        let base = anchor(text);
+        let mut anchor = base.clone();
+        let mut n = 1;
+        while !self.0.insert(anchor.clone()) {
+            n += 1;
+            anchor = format!("{base}-{n}");
+        }
+        anchor
+    }
+}
+
+/// An id usable in HTML and Markdown links and file names, e.g. `lineage-23-0-20261019`
+fn anchor(tag: &str) -> String {
+    let mut anchor = String::with_capacity(tag.len());
+    for c in tag.chars() {
+        if c.is_ascii_alphanumeric() || c == '_' {
+            anchor.push(c.to_ascii_lowercase());
+        } else if !anchor.is_empty() && !anchor.ends_with('-') {
+            anchor.push('-');
+        }
+    }
+    anchor.truncate(anchor.trim_end_matches('-').len());
+    if anchor.is_empty() {
+        anchor.push_str("release");
+    }
+    anchor
+}
+
+/// Compare strings with runs of digits compared as numbers, e.g. `build-9` < `build-10`
+fn natural_cmp(a: &str, b: &str) -> Ordering {
+    let (mut a, mut b) = (a, b);
+    loop {
+        let (Some(x), Some(y)) = (a.chars().next(), b.chars().next()) else {
+            return a.len().cmp(&b.len());
+        };
+        if x.is_ascii_digit() && y.is_ascii_digit() {
+            let (a_digits, a_rest) =
+                a.split_at(a.find(|c: char| !c.is_ascii_digit()).unwrap_or(a.len()));
+            let (b_digits, b_rest) =
+                b.split_at(b.find(|c: char| !c.is_ascii_digit()).unwrap_or(b.len()));
+            let (a_num, b_num) = (
+                a_digits.trim_start_matches('0'),
+                b_digits.trim_start_matches('0'),
+            );
+            let ordering = a_num
+                .len()
+                .cmp(&b_num.len())
+                .then_with(|| a_num.cmp(b_num))
+                .then_with(|| a_digits.len().cmp(&b_digits.len()));
+            if ordering.is_ne() {
+                return ordering;
+            }
+            (a, b) = (a_rest, b_rest);
+        } else {
+            if x != y {
+                return x.cmp(&y);
+            }
+            (a, b) = (&a[x.len_utf8()..], &b[y.len_utf8()..]);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::template::Renderer;
+
+    #[test]
+    fn test_natural_cmp() {
+        let mut tags = vec![
+            "build-10",
+            "build-9",
+            "build-09a",
+            "build-9a",
+            "a",
+            "build-",
+        ];
+        tags.sort_by(|a, b| natural_cmp(a, b));
+        assert_eq!(
+            tags,
+            [
+                "a",
+                "build-",
+                "build-9",
+                "build-9a",
+                "build-09a",
+                "build-10"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_anchor() {
+        assert_eq!(anchor("lineage-23.0-20261019"), "lineage-23-0-20261019");
+        assert_eq!(anchor("..Build 1.."), "build-1");
+        assert_eq!(anchor("..."), "release");
+    }
+
+    #[test]
+    fn test_pages() {
+        let release = |tag: &str| Release::without_source(tag.to_string(), ChangeLog::default());
+        let history = History::new(
+            vec![release("build-9"), release("build-10"), release("build-8")],
+            HistoryOrder::Tag,
+        );
+        let tags: Vec<_> = history.releases.iter().map(|r| r.tag.as_str()).collect();
+        assert_eq!(tags, ["build-10", "build-9", "build-8"]);
+        assert_eq!(history.releases[0].previous_tag.as_deref(), Some("build-9"));
+        assert_eq!(history.releases[2].previous_tag, None);
+
+        // e.g. build-9 failed, so build-10 is generated since build-8
+        let since = |tag: &str, source_tag: Option<&str>| {
+            let changelog = ChangeLog {
+                source_tag: source_tag.map(str::to_string),
+                ..ChangeLog::default()
+            };
+            Release::new(tag.to_string(), changelog)
+        };
+        let saved = History::new(
+            vec![
+                since("build-10", Some("build-8")),
+                release("build-9"),
+                since("build-8", None),
+            ],
+            HistoryOrder::Tag,
+        );
+        let previous: Vec<_> = saved
+            .releases
+            .iter()
+            .map(|r| r.previous_tag.as_deref())
+            .collect();
+        assert_eq!(previous, [Some("build-8"), Some("build-8"), None]);
+        let history_with_same_anchors =
+            History::new(vec![release("a.b"), release("a-b")], HistoryOrder::Tag);
+        let anchors: Vec<_> = history_with_same_anchors
+            .releases
+            .iter()
+            .map(|r| r.anchor.as_str())
+            .collect();
+        assert_eq!(anchors, ["a-b", "a-b-2"]);
+
+        let pages = history.pages("CHANGELOG.md", 2);
+        assert_eq!(pages.len(), 2);
+        assert_eq!(pages[0].file, "CHANGELOG.md");
+        assert_eq!(pages[0].next.as_deref(), Some("CHANGELOG-2.md"));
+        assert_eq!(pages[1].prev.as_deref(), Some("CHANGELOG.md"));
+        assert_eq!(pages[1].releases.len(), 1);
+        assert_eq!(pages[1].pages[0].last_tag.as_deref(), Some("build-9"));
+        assert_eq!(history.pages("CHANGELOG", 0).len(), 1);
+        assert_eq!(History::default().pages("CHANGELOG.md", 2).len(), 1);
+    }
+
+    #[test]
+    fn test_render_builtin_history() {
+        let history = History::new(
+            vec![
+                Release::new("build-1".to_string(), ChangeLog::sample()),
+                Release::new("build-2".to_string(), ChangeLog::default()),
+            ],
+            HistoryOrder::Tag,
+        );
+        let title = ChangeLog::sample().log[0].title.to_string();
+        let renderer = Renderer::new().unwrap();
+        let render = |template, page| {
+            let context = HistoryContext {
+                meta: Meta::default(),
+                page,
+                vars: Default::default(),
+            };
+            renderer.render(template, &context).unwrap()
+        };
+        for (template, file) in [
+            ("markdown/history", "CHANGELOG.md"),
+            ("html/history", "CHANGELOG.html"),
+        ] {
+            let pages: Vec<_> = history
+                .pages(file, 1)
+                .into_iter()
+                .map(|page| render(template, page))
+                .collect();
+            assert!(pages[0].contains("build-2"), "{template}");
+            assert!(pages[0].contains(&page_file(file, 2)), "{template}");
+            assert!(!pages[0].contains(&title), "{template}");
+            assert!(pages[1].contains(r#"id="build-1""#), "{template}");
+            assert!(pages[1].contains(&title), "{template}");
+        }
+        let empty = History::default();
+        assert_eq!(
+            render("markdown/history", empty.pages("CHANGELOG.md", 1).remove(0)),
+            "# Changelog\n\nNo releases yet.\n"
+        );
+    }
+}
@@ -0,0 +1,36 @@
+//! Changelog Generator
+//!
+//! This generator generates changelog for superprojects managed by `repo`.
+//! To achieve that, we scrape the git log of each git repo from its original commit to
+//! the target commit.
+//!
+//! This generator needs to run inside a repo checkout as it needs to invoke git for getting
+//! all the details.
+//!
+//! Optionally, we support excluding some repos and explicitly include some repos to create
+//! for example a device-specific changelog for AOSP builds.
+//!
+//! And we should also report updates in manifests repo and local_manifests
+//! (provided that it is a git repo)
+//!
+//! The generator is also usable as a library, see [`ChangeLogBuilder`]
+//! for generating a [`ChangeLog`], [`schema`] for reading a saved one and
+//! [`template::Renderer`] for rendering it.
+//! [`history::History`] combines the changelogs of all the builds in the [`state`] repo,
+//! which [`site::Site`] turns into a static site.
+//! Progress and timing are reported as [`tracing`] events, e.g. one span per repo.
+
+pub mod changelog;
+pub mod config;
+pub mod contributors;
+pub mod history;
+pub mod mailmap;
+pub mod repo_log;
+pub mod schema;
+pub mod site;
+pub mod snapshot;
+pub mod state;
+pub mod template;
+pub mod tree;
+
+pub use crate::changelog::{ChangeLog, ChangeLogBuilder, ChangeLogError};
@@ -0,0 +1,267 @@
+//! [`Mailmap`] maps the many identities of a person to a canonical one,
+//! following the format of git's `.mailmap` file.
+
+use std::path::Path;
+
+use arcstr::ArcStr;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use snafu::{ResultExt, Snafu};
+
+/// The name and email of a person
+#[derive(
+    Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize, JsonSchema,
+)]
+pub struct Identity {
+    #[schemars(with = "String")]
+    pub name: ArcStr,
+    #[schemars(with = "String")]
+    pub email: ArcStr,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct Entry {
+    proper_name: Option<ArcStr>,
+    proper_email: Option<ArcStr>,
+    commit_name: Option<String>,
+    commit_email: String,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Mailmap {
+    entries: Vec<Entry>,
+}
+
+type Result<T, E = MailmapError> = std::result::Result<T, E>;
+
+#[derive(Debug, Snafu)]
+pub enum MailmapError {
+    #[snafu(display("failed to read mailmap {path:?}"))]
+    Read {
+        path: String,
+        source: std::io::Error,
+    },
+    #[snafu(display("invalid mailmap line {line:?}: {reason}"))]
+    InvalidLine { line: String, reason: &'static str },
+}
+
+impl Mailmap {
+    /// Parse from the content of a mailmap file
+    /// e.g.
+    ///
+    /// Proper Name <proper@email.xx> Commit Name <commit@email.xx>
+    pub fn parse(input: &str) -> Result<Self> {
+        let mut entries = Vec::new();
+        for line in input.lines() {
+            let line = match line.split_once('#') {
+                Some((line, _)) => line,
+                None => line,
+            }
+            .trim();
+            if line.is_empty() {
+                continue;
+            }
+            let mut parts = Vec::new();
+            let mut rest = line;
+            while let Some((name, tail)) = rest.split_once('<') {
+                let (email, tail) =
+                    tail.split_once('>')
+                        .ok_or_else(|| MailmapError::InvalidLine {
+                            line: line.to_string(),
+                            reason: "email is not closed by `>`",
+                        })?;
+                let name = name.trim();
+                parts.push(((!name.is_empty()).then_some(name), email.trim()));
+                rest = tail;
+            }
+            if !rest.trim().is_empty() {
+                return Err(MailmapError::InvalidLine {
+                    line: line.to_string(),
+                    reason: "trailing content after the last email",
+                });
+            }
+            let entry = match parts.as_slice() {
+                [(Some(proper_name), commit_email)] => Entry {
+                    proper_name: Some(ArcStr::from(*proper_name)),
+                    proper_email: None,
+                    commit_name: None,
+                    commit_email: commit_email.to_lowercase(),
+                },
+                [(proper_name, proper_email), (commit_name, commit_email)] => Entry {
+                    proper_name: proper_name.map(ArcStr::from),
+                    proper_email: Some(ArcStr::from(*proper_email)),
+                    commit_name: commit_name.map(str::to_lowercase),
+                    commit_email: commit_email.to_lowercase(),
+                },
+                _ => {
+                    return Err(MailmapError::InvalidLine {
+                        line: line.to_string(),
+                        reason: "expected one or two emails",
+                    });
+                }
+            };
+            entries.push(entry);
+        }
+        Ok(Self { entries })
+    }
+
+    /// Load a mailmap file, an absent file is treated as an empty mailmap.
+    pub fn load(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        match std::fs::read_to_string(path) {
+            Ok(content) => Self::parse(&content),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(e) => Err(e).with_context(|_| ReadSnafu {
+                path: path.display().to_string(),
+            }),
+        }
+    }
+
+    /// Combine two mailmaps, entries from `other` take precedence.
+    pub fn merged(&self, other: &Mailmap) -> Mailmap {
+        Mailmap {
+            entries: self
+                .entries
+                .iter()
+                .chain(other.entries.iter())
+                .cloned()
+                .collect(),
+        }
+    }
+
+    /// Get the canonical identity of a person.
+    ///
+    /// Like git, emails and names are matched case-insensitively and
+    /// an entry matching both name and email wins over an email-only one.
+    pub fn resolve(&self, name: &str, email: &str) -> Identity {
+        let lower_name = name.to_lowercase();
+        let lower_email = email.to_lowercase();
+        let mut best: Option<&Entry> = None;
+        // Later entries override earlier ones
+        for entry in self.entries.iter().rev() {
+            if entry.commit_email != lower_email {
+                continue;
+            }
+            match &entry.commit_name {
+                Some(n) if *n == lower_name => {
+                    best = Some(entry);
+                    break;
+                }
+                Some(_) => continue,
+                None if best.is_none() => best = Some(entry),
+                None => continue,
+            }
+        }
+        Identity {
+            name: best
+                .and_then(|e| e.proper_name.clone())
+                .unwrap_or_else(|| ArcStr::from(name)),
+            email: best
+                .and_then(|e| e.proper_email.clone())
+                .unwrap_or_else(|| ArcStr::from(email)),
+        }
+    }
+
+    /// Get the other emails, lowercased, which are mapped to the canonical `email`,
+    /// e.g. an old address of the person
+    pub fn aliases(&self, email: &str) -> impl Iterator<Item = &str> {
+        let lower_email = email.to_lowercase();
+        self.entries
+            .iter()
+            .filter(move |e| {
+                e.proper_email
+                    .as_ref()
+                    .is_some_and(|p| p.to_lowercase() == lower_email)
+            })
+            .map(|e| e.commit_email.as_str())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MAILMAP: &str = r#"
+# comment
+Proper Name <commit@example.com>
+<proper@example.com> <Old@Example.com>
+Other Name <other@example.com> <other@old.example.com> # trailing comment
+Specific Name <specific@example.com> Bot <shared@example.com>
+Shared Name <shared@example.com>
+"#;
+
+    fn resolve(name: &str, email: &str) -> (String, String) {
+        let id = Mailmap::parse(MAILMAP).unwrap().resolve(name, email);
+        (id.name.to_string(), id.email.to_string())
+    }
+
+    #[test]
+    fn test_resolve_name_only() {
+        assert_eq!(
+            resolve("whoever", "commit@example.com"),
+            ("Proper Name".into(), "commit@example.com".into())
+        );
+    }
+
+    #[test]
+    fn test_resolve_email_only_case_insensitive() {
+        assert_eq!(
+            resolve("Old", "old@example.com"),
+            ("Old".into(), "proper@example.com".into())
+        );
+    }
+
+    #[test]
+    fn test_resolve_name_and_email() {
+        assert_eq!(
+            resolve("x", "other@old.example.com"),
+            ("Other Name".into(), "other@example.com".into())
+        );
+    }
+
+    #[test]
+    fn test_resolve_prefers_name_match() {
+        assert_eq!(
+            resolve("bot", "shared@example.com"),
+            ("Specific Name".into(), "specific@example.com".into())
+        );
+        assert_eq!(
+            resolve("Someone", "shared@example.com"),
+            ("Shared Name".into(), "shared@example.com".into())
+        );
+    }
+
+    #[test]
+    fn test_resolve_unknown() {
+        assert_eq!(
+            resolve("Nobody", "nobody@example.com"),
+            ("Nobody".into(), "nobody@example.com".into())
+        );
+    }
+
+    #[test]
+    fn test_merged_overrides() {
+        let global = Mailmap::parse("Global Name <commit@example.com>").unwrap();
+        let id = Mailmap::parse(MAILMAP)
+            .unwrap()
+            .merged(&global)
+            .resolve("x", "commit@example.com");
+        assert_eq!(&*id.name, "Global Name");
+    }
+
+    #[test]
+    fn test_aliases() {
+        let mailmap = Mailmap::parse(MAILMAP).unwrap();
+        assert_eq!(
+            mailmap.aliases("Proper@example.com").collect::<Vec<_>>(),
+            ["old@example.com"]
+        );
+        assert_eq!(mailmap.aliases("commit@example.com").count(), 0);
+    }
+
+    #[test]
+    fn test_parse_invalid_line() {
+        let err = Mailmap::parse("Name <unclosed@example.com").unwrap_err();
+        assert!(matches!(err, MailmapError::InvalidLine { .. }));
+    }
+}
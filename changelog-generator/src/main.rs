@@ -1,86 +1,906 @@
-//! Changelog Generator
-//!
-//! This generator generates changelog for superprojects managed by `repo`.
-//! To achieve that, we scrape the git log of each git repo from its original commit to
-//! the target commit.
-//!
-//! This generator needs to run inside a repo checkout as it needs to invoke git for getting
-//! all the details. (TODO: maybe use a library instead of shelling out)
-//!
-//! Optionally, we support excluding some repos and explicitly include some repos to create
-//! for example a device-specific changelog for AOSP builds.
-//!
-//! And we should also report updates in manifests repo and local_manifests
-//! (provided that it is a git repo)
+//! The command line interface of [`changelog_generator`]
 
 use std::{
+    collections::BTreeMap,
     fs::File,
-    io::{BufReader, BufWriter, Read, Write},
+    io::{BufReader, IsTerminal, Read, Write},
     os::fd::{FromRawFd, RawFd},
+    path::{Path, PathBuf},
+    process::ExitCode,
 };
 
-use color_eyre::eyre::{Context, bail};
+use chrono::Utc;
+use color_eyre::eyre::{Context, bail, eyre};
 use palc::Parser;
+use regex::Regex;
+use serde::Serialize;
+use tracing::{info, warn};
+use tracing_subscriber::{EnvFilter, filter::LevelFilter};
 
-use crate::{changelog::ChangeLog, cli::Cli, snapshot::Snapshot};
+use changelog_generator::{
+    ChangeLog, ChangeLogBuilder,
+    changelog::{Change, ChangeKind, GenerateSummary, parse_pathspec},
+    config::{Config, Profile, ProfileFormat},
+    history::{History, HistoryContext},
+    mailmap::Mailmap,
+    schema,
+    site::{SEARCH_INDEX_FILE, Site, SiteContext},
+    snapshot::Snapshot,
+    state::{DeviceState, StateError},
+    template::{self, ContextKind, EscapeMode, Meta, Renderer, SnapshotMeta, TemplateContext},
+    tree,
+};
+
+use crate::{
+    cli::{
+        CheckTemplateArgs, Cli, Command, DiffArgs, GenerateArgs, HistoryArgs, OutputArgs,
+        OutputFormat, QueryArgs, RenderArgs, RendererArgs, SchemaArgs, SiteArgs, SnapshotArgs,
+        SnapshotSources, StatusArgs, TemplateArgs, TemplateOutput,
+    },
+    exit::ExitStatus,
+};
 
-mod changelog;
 mod cli;
-mod repo_log;
-mod snapshot;
-mod template;
-
-fn main() -> color_eyre::Result<()> {
-    color_eyre::install()?;
-    let cli = Cli::parse();
-    if cli.debug.is_none() && cli.json.is_none() && cli.template.is_empty() {
+mod exit;
+
+fn main() -> ExitCode {
+    if let Err(report) = color_eyre::install() {
+        eprintln!("Error: {report:?}");
+        return ExitCode::FAILURE;
+    }
+    let cli = match Cli::try_parse_from(std::env::args_os()) {
+        Ok(cli) => cli,
+        Err(err) => {
+            // palc leaves out why a value is invalid, e.g. a malformed --template
+            match std::error::Error::source(&err) {
+                Some(reason) => eprintln!("{err}: {reason}"),
+                None => eprintln!("{err}"),
+            }
+            return ExitStatus::Failure.into();
+        }
+    };
+    init_logging(&cli);
+    match run(cli) {
+        Ok(status) => status.into(),
+        Err(report) => {
+            // Like returning the error from main, without panicking if stderr is closed
+            let _ = writeln!(std::io::stderr(), "Error: {report:?}");
+            ExitStatus::of(&report).into()
+        }
+    }
+}
+
+fn run(cli: Cli) -> color_eyre::Result<ExitStatus> {
+    let done = |result: color_eyre::Result<()>| result.map(|()| ExitStatus::Success);
+    // They would be silently ignored otherwise
+    if cli.command.is_some() {
+        if let Some(flag) = cli.generate.first_given() {
+            bail!("{flag} before a subcommand is not accepted, give it after `generate` instead")
+        }
+        if let Some(tree) = &cli.tree {
+            bail!("the tree {tree:?} before a subcommand is not accepted")
+        }
+    }
+    match cli.command {
+        Some(Command::Snapshot(args)) => done(snapshot(args)),
+        Some(Command::Status(args)) => done(status(args)),
+        Some(Command::Diff(args)) => done(diff(args)),
+        Some(Command::Generate(command)) => generate(command.tree, command.args),
+        Some(Command::Render(args)) => done(render(args)),
+        Some(Command::Query(args)) => done(query(args)),
+        Some(Command::History(args)) => done(history(args)),
+        Some(Command::Site(args)) => done(site(args)),
+        Some(Command::CheckTemplate(args)) => done(check_template(args)),
+        Some(Command::Schema(args)) => done(print_schema(args)),
+        // The invocation before subcommands were introduced
+        None => {
+            let Some(tree) = cli.tree else {
+                bail!("--from, --to and the tree are required to generate a changelog")
+            };
+            generate(tree, cli.generate)
+        }
+    }
+}
+
+/// Log to stderr at the level chosen by `-v`/`-q`, unless `RUST_LOG` is set
+fn init_logging(args: &Cli) {
+    const LEVELS: [LevelFilter; 5] = [
+        LevelFilter::ERROR,
+        LevelFilter::WARN,
+        LevelFilter::INFO,
+        LevelFilter::DEBUG,
+        LevelFilter::TRACE,
+    ];
+    let level = (2 + usize::from(args.verbose))
+        .saturating_sub(usize::from(args.quiet))
+        .min(LEVELS.len() - 1);
+    // Logs of dependencies are only interesting with RUST_LOG
+    let filter = EnvFilter::try_from_default_env().unwrap_or_else(|_| {
+        EnvFilter::builder()
+            .with_default_directive(LevelFilter::WARN.into())
+            .parse_lossy(format!("changelog_generator={}", LEVELS[level]))
+    });
+    let builder = tracing_subscriber::fmt()
+        .with_env_filter(filter)
+        .with_writer(std::io::stderr);
+    match args.log_format.unwrap_or(OutputFormat::Text) {
+        OutputFormat::Text => builder
+            .with_ansi(std::io::stderr().is_terminal())
+            .with_target(false)
+            .init(),
+        OutputFormat::Json => builder.json().init(),
+    }
+}
+
+/// Print the snapshot of a tree, replacing `snapshot.c`
+fn snapshot(args: SnapshotArgs) -> color_eyre::Result<()> {
+    check_tree(&args.tree)?;
+    let config = load_config(args.config.as_deref(), Some(&args.tree))?;
+    let snapshot = tree::capture(&args.tree, &config.generate.stamp_branch_prefix)
+        .with_context(|| format!("failed to capture snapshot of {:?}", args.tree))
+        .wrap_err(ExitStatus::Git)?;
+    let commits: BTreeMap<_, _> = snapshot
+        .repos
+        .iter()
+        .map(|(repo, status)| (repo, &status.commit))
+        .collect();
+    write_output(&args.output, &commits, || snapshot.to_string())
+}
+
+/// Print the status of a tree, replacing `format-repo-commit.sh`
+fn status(args: StatusArgs) -> color_eyre::Result<()> {
+    check_tree(&args.tree)?;
+    let config = load_config(args.config.as_deref(), Some(&args.tree))?;
+    let statuses = tree::status(&args.tree, &config.generate.stamp_branch_prefix)
+        .with_context(|| format!("failed to get status of {:?}", args.tree))
+        .wrap_err(ExitStatus::Git)?;
+    write_output(&args.output, &statuses, || {
+        statuses.iter().map(|s| format!("{s}\n")).collect()
+    })
+}
+
+/// Print the repos added, removed and changed between two snapshots
+fn diff(args: DiffArgs) -> color_eyre::Result<()> {
+    let (orig, target) = read_snapshots(args.snapshots)?;
+    let diff = orig.diff(&target);
+    write_output(&args.output, &diff, || diff.to_string())
+}
+
+/// Generate the changelog, then write `--summary-json` whether it succeeds or not
+fn generate(tree: PathBuf, mut args: GenerateArgs) -> color_eyre::Result<ExitStatus> {
+    let summary_json = args.summary_json.take();
+    let mut summary = GenerateSummary::default();
+    let mut written = Vec::new();
+    let result = generate_outputs(tree, args, &mut summary, &mut written);
+    if let Some(path) = summary_json
+        && let Err(e) = write_run_summary(&path, &result, summary, written)
+    {
+        if result.is_ok() {
+            return Err(e);
+        }
+        warn!("{e:#}");
+    }
+    result
+}
+
+fn generate_outputs(
+    tree: PathBuf,
+    mut args: GenerateArgs,
+    summary: &mut GenerateSummary,
+    written: &mut Vec<PathBuf>,
+) -> color_eyre::Result<ExitStatus> {
+    if args.state.is_none() && (args.snapshots.from.is_none() || args.snapshots.to.is_none()) {
+        bail!("--from and --to (or --state) and the tree are required to generate a changelog")
+    }
+    check_tree(&tree)?;
+    let builds = match args.state.take() {
+        Some(state) => Some(locate_builds(&state, &mut args)?),
+        None => None,
+    };
+    let config_path = args
+        .config
+        .clone()
+        .or_else(|| builds.as_ref().and_then(|b| b.device.find_config()));
+    let config = load_config(config_path.as_deref(), Some(&tree))?;
+    let outputs = outputs(&config, &args.templates, args.json.as_deref())?;
+    if args.debug.is_none() && outputs.is_empty() {
         bail!(
-            "Please choose at least one output format using --json=<OUTPUT>/--debug=<OUTPUT>/--template=<OUTPUT>@<PATH_TO_HANDLEBARS_TEMPALATE>"
+            "Please choose at least one output using a profile of the config or --json=<OUTPUT>/--debug=<OUTPUT>/--template=<OUTPUT>@<PATH_TO_HANDLEBARS_TEMPALATE|builtin:NAME>"
         )
     }
-    for fd in [cli.from, cli.to] {
-        if fd == 2 || fd == 1 {
-            bail!("Cannot use stdout/stderr for that!")
+    let (orig, target) = match builds {
+        Some(StateBuilds {
+            from, to: Some(to), ..
+        }) => (from, to),
+        Some(StateBuilds { from, to: None, .. }) => {
+            let to = tree::capture(&tree, &config.generate.stamp_branch_prefix)
+                .with_context(|| format!("failed to capture snapshot of {tree:?}"))
+                .wrap_err(ExitStatus::Git)?;
+            (from, to)
         }
+        None => read_snapshots(args.snapshots)?,
+    };
+    let (source_repos, target_repos) = (orig.repos.len(), target.repos.len());
+    let mut builder = ChangeLogBuilder::new(tree)
+        .options(config.generate_options()?)
+        .from(orig)
+        .to(target);
+    // Flags could only turn on what the config leaves off
+    if args.hide_reverted {
+        builder = builder.hide_reverted(true);
+    }
+    let list_paths = args.list_paths || config.generate.list_paths;
+    // Filter sets by paths need them, but the outputs leave them out again
+    if list_paths || config.filters_by_paths() {
+        builder = builder.list_paths(true);
+    }
+    if args.keep_going {
+        builder = builder.keep_going(true);
     }
-    let from_fd = if std::fs::exists(format!("/proc/self/fd/{}", cli.from))
-        .context("failed to check existence of --from-fd")?
+    if let Some(path) = args.mailmap {
+        builder = builder.mailmap(Mailmap::load(path)?);
+    }
+    for arg in args.pathspec {
+        let Some((repo, pathspec)) = parse_pathspec(&arg) else {
+            bail!("--pathspec={arg} should specify repo and pathspec like --pathspec=repo=pathspec")
+        };
+        builder = builder.filter(repo, pathspec);
+    }
+    let mut changelog = builder
+        .build_with_summary(summary)
+        .wrap_err(ExitStatus::Git)?;
+    changelog.source_tag = args.templates.from_tag.clone();
+    // Nothing is written without changes, so that no outputs are left behind unpublished
+    if summary.failures.is_empty()
+        && changelog.log.is_empty()
+        && changelog.added_repos.is_empty()
+        && changelog.removed_repos.is_empty()
     {
-        unsafe { File::from_raw_fd(cli.from as RawFd) }
+        info!("no changes, skipping the outputs");
+        return Ok(ExitStatus::NoChanges);
+    }
+    if let Some(output) = args.debug {
+        std::fs::write(output, format!("{changelog:#?}"))?;
+    }
+    let meta = meta(&args.templates, source_repos, target_repos);
+    write_outputs(
+        &changelog,
+        meta,
+        &args.templates,
+        &config,
+        outputs,
+        list_paths,
+        written,
+    )?;
+    Ok(if summary.failures.is_empty() {
+        ExitStatus::Success
     } else {
-        bail!("--from={} does not exist", cli.from)
+        ExitStatus::PartialSuccess
+    })
+}
+
+/// The outcome of `generate`, written by `--summary-json`
+#[derive(Serialize)]
+struct RunSummary {
+    status: ExitStatus,
+    exit_code: u8,
+    #[serde(flatten)]
+    generate: GenerateSummary,
+    /// The destinations written, which could be some of them on failure
+    outputs: Vec<PathBuf>,
+    error: Option<String>,
+}
+
+fn write_run_summary(
+    path: &Path,
+    result: &color_eyre::Result<ExitStatus>,
+    generate: GenerateSummary,
+    outputs: Vec<PathBuf>,
+) -> color_eyre::Result<()> {
+    let (status, error) = match result {
+        Ok(status) => (*status, None),
+        Err(report) => (ExitStatus::of(report), Some(format!("{report:#}"))),
+    };
+    let summary = RunSummary {
+        status,
+        exit_code: status.code(),
+        generate,
+        outputs,
+        error,
+    };
+    let content = serde_json::to_string_pretty(&summary)? + "\n";
+    std::fs::write(path, content)
+        .with_context(|| format!("failed to write the summary to {path:?}"))
+}
+
+/// The snapshots located by `--state`, with the tree to be captured if `to` is `None`
+struct StateBuilds {
+    device: DeviceState,
+    from: Snapshot,
+    to: Option<Snapshot>,
+}
+
+/// Read the snapshots of `--from-tag` (default: `last_build`) and `--to-tag` of `--device`,
+/// filling in the tags and writing the outputs into the directory of `--to-tag` by default.
+/// Without `--to-tag`, the changelog is generated up to the tree as it is now.
+fn locate_builds(state: &Path, args: &mut GenerateArgs) -> color_eyre::Result<StateBuilds> {
+    if args.snapshots.from.is_some() || args.snapshots.to.is_some() {
+        bail!("--state locates the snapshots by tags, which conflicts with --from and --to")
+    }
+    let templates = &mut args.templates;
+    let Some(device) = &templates.device else {
+        bail!("--state requires --device to locate the snapshots")
     };
-    let to_fd = if std::fs::exists(format!("/proc/self/fd/{}", cli.to))
-        .context("failed to check existence of --to-fd")?
+    let device = DeviceState::open(state, device)?;
+    let from = match &templates.from_tag {
+        Some(tag) => device.snapshot(tag).wrap_err(ExitStatus::BadSnapshot)?,
+        None => {
+            let Some(tag) = device.last_build()? else {
+                return Err(eyre!("{:?} has no last_build", device.dir))
+                    .wrap_err(ExitStatus::NoPreviousBuild);
+            };
+            let snapshot = match device.snapshot(&tag) {
+                // Not saved or removed, as if there is no previous build
+                Err(e @ (StateError::MissingBuild { .. } | StateError::MissingSnapshot { .. })) => {
+                    return Err(e)
+                        .with_context(|| format!("last_build is {tag:?}"))
+                        .wrap_err(ExitStatus::NoPreviousBuild);
+                }
+                snapshot => snapshot.wrap_err(ExitStatus::BadSnapshot)?,
+            };
+            templates.from_tag = Some(tag);
+            snapshot
+        }
+    };
+    let to = match &templates.to_tag {
+        Some(tag) => {
+            let snapshot = device.snapshot(tag).wrap_err(ExitStatus::BadSnapshot)?;
+            if templates.output_dir.is_none() {
+                templates.output_dir = Some(device.build_dir(tag)?);
+            }
+            Some(snapshot)
+        }
+        None => None,
+    };
+    Ok(StateBuilds { device, from, to })
+}
+
+/// Fail with [`ExitStatus::MissingTree`] unless the tree is a directory
+fn check_tree(tree: &Path) -> color_eyre::Result<()> {
+    if !tree.is_dir() {
+        return Err(eyre!("{tree:?} is not a directory")).wrap_err(ExitStatus::MissingTree);
+    }
+    Ok(())
+}
+
+/// Load `--config`, or else the config found in the tree, or else the defaults
+fn load_config(explicit: Option<&Path>, tree: Option<&Path>) -> color_eyre::Result<Config> {
+    match explicit
+        .map(Path::to_path_buf)
+        .or_else(|| tree.and_then(Config::find))
     {
-        unsafe { File::from_raw_fd(cli.to as RawFd) }
+        Some(path) => Ok(Config::load(path)?),
+        None => Ok(Config::default()),
+    }
+}
+
+/// Read `--from` and `--to`
+fn read_snapshots(sources: SnapshotSources) -> color_eyre::Result<(Snapshot, Snapshot)> {
+    let (Some(from), Some(to)) = (sources.from, sources.to) else {
+        bail!("both --from and --to are required")
+    };
+    let read = |flag, source| read_snapshot(flag, source).wrap_err(ExitStatus::BadSnapshot);
+    Ok((read("--from", &from)?, read("--to", &to)?))
+}
+
+/// Read a snapshot from a file or an open fd like `--from=3 3<snapshot`
+fn read_snapshot(flag: &str, source: &str) -> color_eyre::Result<Snapshot> {
+    let content = if let Ok(fd) = source.parse::<RawFd>() {
+        if fd == 2 || fd == 1 {
+            bail!("Cannot use stdout/stderr for that!")
+        }
+        if !std::fs::exists(format!("/proc/self/fd/{fd}"))
+            .with_context(|| format!("failed to check existence of {flag}={fd}"))?
+        {
+            bail!("{flag}={fd} does not exist")
+        }
+        let mut content = String::new();
+        BufReader::new(unsafe { File::from_raw_fd(fd) }).read_to_string(&mut content)?;
+        content
     } else {
-        bail!("--to={} does not exist", cli.to)
-    };
-    let mut orig = String::new();
-    let mut target = String::new();
-    BufReader::new(from_fd).read_to_string(&mut orig)?;
-    BufReader::new(to_fd).read_to_string(&mut target)?;
-    let orig = Snapshot::parse(orig)?;
-    let target = Snapshot::parse(target)?;
-    let changelog = ChangeLog::generate(&orig, &target, cli.tree)?;
-    if let Some(output) = cli.debug {
-        std::fs::write(output, format!("{changelog:#?}"))?;
+        std::fs::read_to_string(source)
+            .with_context(|| format!("failed to read {flag}={source}"))?
+    };
+    Snapshot::parse(content).with_context(|| format!("invalid snapshot {flag}={source}"))
+}
+
+/// Read a changelog saved by `--json`
+fn read_changelog(path: &Path) -> color_eyre::Result<ChangeLog> {
+    let file = File::open(path).with_context(|| format!("failed to open changelog {path:?}"))?;
+    schema::read(BufReader::new(file))
+        .with_context(|| format!("failed to parse changelog {path:?}"))
+}
+
+/// Write the text or JSON form of a result to `--output` or stdout
+fn write_output<T: Serialize>(
+    args: &OutputArgs,
+    value: &T,
+    text: impl FnOnce() -> String,
+) -> color_eyre::Result<()> {
+    let content = match args.format.unwrap_or(OutputFormat::Text) {
+        OutputFormat::Text => text(),
+        OutputFormat::Json => serde_json::to_string_pretty(value)? + "\n",
+    };
+    match &args.output {
+        Some(output) => std::fs::write(output, content)
+            .with_context(|| format!("failed to write {output:?}"))?,
+        None => std::io::stdout().write_all(content.as_bytes())?,
+    }
+    Ok(())
+}
+
+/// Render templates with a changelog saved by `--json`
+fn render(args: RenderArgs) -> color_eyre::Result<()> {
+    let config = load_config(args.config.as_deref(), None)?;
+    let outputs = outputs(&config, &args.templates, None)?;
+    if outputs.is_empty() {
+        bail!(
+            "Please choose at least one output using a profile of the config or --template=<OUTPUT>@<TEMPLATE>"
+        )
+    }
+    let changelog = read_changelog(&args.changelog)?;
+    // The snapshots are not saved with the changelog
+    let meta = meta(&args.templates, 0, 0);
+    // Paths are kept as they were saved
+    write_outputs(
+        &changelog,
+        meta,
+        &args.templates,
+        &config,
+        outputs,
+        true,
+        &mut Vec::new(),
+    )
+}
+
+/// Print the changes of a saved changelog matching all the filters
+fn query(args: QueryArgs) -> color_eyre::Result<()> {
+    let changelog = read_changelog(&args.changelog)?;
+    let grep = match &args.grep {
+        Some(pattern) => Some(Regex::new(pattern).with_context(|| format!("--grep={pattern}"))?),
+        None => None,
+    };
+    let author = args.author.as_deref().map(str::to_lowercase);
+    let kind = args.kind.map(ChangeKind::from);
+    let matches = |change: &Change| {
+        (args.repo.is_empty()
+            || args
+                .repo
+                .iter()
+                .any(|r| r.trim_end_matches('/') == change.repo))
+            && kind.is_none_or(|kind| change.kind == kind)
+            && author.as_deref().is_none_or(|author| {
+                let signature = &change.author;
+                [
+                    &signature.name,
+                    &signature.email,
+                    &signature.canonical.name,
+                    &signature.canonical.email,
+                ]
+                .iter()
+                .any(|s| s.to_lowercase().contains(author))
+            })
+            && grep.as_ref().is_none_or(|grep| {
+                grep.is_match(&change.title) || grep.is_match(&change.description)
+            })
+    };
+    let changes: Vec<&Change> = changelog.log.iter().filter(|c| matches(c)).collect();
+    write_output(&args.output, &changes, || {
+        changes
+            .iter()
+            .map(|c| {
+                let commit = c.commit.get(..12).unwrap_or(&c.commit);
+                format!(
+                    "{commit} {}: {} ({})\n",
+                    c.repo, c.title, c.author.canonical.name
+                )
+            })
+            .collect()
+    })
+}
+
+/// Render the changelogs of all the builds of a device into pages
+fn history(args: HistoryArgs) -> color_eyre::Result<()> {
+    let device = DeviceState::open(&args.state, &args.device)?;
+    let config = load_config(
+        args.config.or_else(|| device.find_config()).as_deref(),
+        None,
+    )?;
+    let history = History::load(&device, args.order.map(Into::into).unwrap_or_default())?;
+    let (mut renderer, vars) = load_renderer(&args.renderer, &config)?;
+    let outputs = if args.template.is_empty() {
+        [
+            ("CHANGELOG.md", "builtin:markdown/history"),
+            ("CHANGELOG.html", "builtin:html/history"),
+        ]
+        .into_iter()
+        .map(|(file, template)| TemplateOutput {
+            output: device.dir.join(file),
+            template: template.to_string(),
+        })
+        .collect()
+    } else {
+        args.template
+    };
+    let meta = Meta {
+        device: Some(device.device.clone()),
+        generated_at: Utc::now(),
+        ..Default::default()
+    };
+    for TemplateOutput { output, template } in outputs {
+        let name = resolve_template(&mut renderer, &template)
+            .with_context(|| format!("the template of {}", output.display()))
+            .wrap_err(ExitStatus::Template)?;
+        let Some(file) = output.file_name().and_then(|f| f.to_str()) else {
+            bail!("{output:?} is not a file name")
+        };
+        renderer.set_escape(EscapeMode::infer(
+            &output,
+            template.strip_prefix("builtin:"),
+        ));
+        for page in history.pages(file, args.per_page.unwrap_or(20)) {
+            let destination = output.with_file_name(&page.file);
+            let context = HistoryContext {
+                meta: meta.clone(),
+                page,
+                vars: vars.clone(),
+            };
+            let content = renderer
+                .render(&name, &context)
+                .with_context(|| format!("failed to render {destination:?}"))
+                .wrap_err(ExitStatus::Template)?;
+            std::fs::write(&destination, content)
+                .with_context(|| format!("failed to write {destination:?}"))?;
+            info!(destination = ?destination, "wrote");
+        }
+    }
+    Ok(())
+}
+
+/// Render the pages and the search index of a static site of a device
+fn site(args: SiteArgs) -> color_eyre::Result<()> {
+    let device = DeviceState::open(&args.state, &args.device)?;
+    let config = load_config(
+        args.config.or_else(|| device.find_config()).as_deref(),
+        None,
+    )?;
+    let history = History::load(&device, args.order.map(Into::into).unwrap_or_default())?;
+    let site = Site::new(history);
+    let (mut renderer, vars) = load_renderer(&args.renderer, &config)?;
+    renderer.set_escape(EscapeMode::Html);
+    let meta = Meta {
+        device: Some(device.device.clone()),
+        generated_at: Utc::now(),
+        ..Default::default()
+    };
+    for dir in ["releases", "repos"] {
+        let dir = args.output.join(dir);
+        std::fs::create_dir_all(&dir).with_context(|| format!("failed to create {dir:?}"))?;
+    }
+    for page in site.pages() {
+        let destination = args.output.join(&page.path);
+        let template = page.template;
+        let context = SiteContext {
+            meta: meta.clone(),
+            site: &site.summary,
+            page,
+            vars: vars.clone(),
+        };
+        let content = renderer
+            .render(template, &context)
+            .with_context(|| format!("failed to render {destination:?}"))
+            .wrap_err(ExitStatus::Template)?;
+        std::fs::write(&destination, content)
+            .with_context(|| format!("failed to write {destination:?}"))?;
+    }
+    let destination = args.output.join(SEARCH_INDEX_FILE);
+    std::fs::write(&destination, serde_json::to_string(&site.search_index())?)
+        .with_context(|| format!("failed to write {destination:?}"))?;
+    info!(
+        output = ?args.output,
+        releases = site.releases.len(),
+        repos = site.repos.len(),
+        "wrote site"
+    );
+    Ok(())
+}
+
+/// Print the JSON Schema of the changelog
+fn print_schema(args: SchemaArgs) -> color_eyre::Result<()> {
+    let schema = serde_json::to_string_pretty(&schema::json_schema())? + "\n";
+    match args.output {
+        Some(output) => std::fs::write(output, schema)?,
+        None => std::io::stdout().write_all(schema.as_bytes())?,
+    }
+    Ok(())
+}
+
+/// Report problems of templates, failing if there are any
+fn check_template(args: CheckTemplateArgs) -> color_eyre::Result<()> {
+    let mut renderer = Renderer::new()?;
+    for dir in args.template_dir {
+        renderer
+            .load_dir(&dir)
+            .with_context(|| format!("failed to load templates from {dir:?}"))?;
+    }
+    let mut problems = 0;
+    for template in &args.templates {
+        let name = match resolve_template(&mut renderer, template) {
+            Ok(name) => name,
+            Err(e) => {
+                eprintln!("{template}: {e:#}");
+                problems += 1;
+                continue;
+            }
+        };
+        let kind = args
+            .context
+            .map_or_else(|| ContextKind::of(&name), Into::into);
+        for diagnostic in renderer.check(&name, kind) {
+            eprintln!("{diagnostic}");
+            problems += 1;
+        }
+    }
+    if problems > 0 {
+        return Err(eyre!("found {problems} problem(s) in templates"))
+            .wrap_err(ExitStatus::Template);
+    }
+    Ok(())
+}
+
+fn meta(args: &TemplateArgs, source_repos: usize, target_repos: usize) -> Meta {
+    Meta {
+        release_tag: args.release_tag.clone().or_else(|| args.to_tag.clone()),
+        device: args.device.clone(),
+        locale: None,
+        source: SnapshotMeta {
+            tag: args.from_tag.clone(),
+            repos: source_repos,
+        },
+        target: SnapshotMeta {
+            tag: args.to_tag.clone(),
+            repos: target_repos,
+        },
+        generated_at: Utc::now(),
+        tool: Default::default(),
+    }
+}
+
+/// An output of a run, from a profile of the config or a flag
+struct Output {
+    /// The profile or the flag, for error messages
+    name: String,
+    /// With the destination resolved
+    profile: Profile,
+}
+
+/// The outputs of a run: the profiles chosen by `--profile`, every `--template` and `--json`,
+/// checking that their destinations could be written before generating anything
+fn outputs(
+    config: &Config,
+    args: &TemplateArgs,
+    json: Option<&Path>,
+) -> color_eyre::Result<Vec<Output>> {
+    for name in &args.profile {
+        if !config.profiles.contains_key(name) {
+            let available: Vec<&str> = config.profiles.keys().map(String::as_str).collect();
+            bail!(
+                "--profile={name}: no such profile in the config, available: {}",
+                available.join(", ")
+            )
+        }
+    }
+    let mut outputs: Vec<Output> = config
+        .profiles
+        .iter()
+        .filter(|(name, _)| args.profile.is_empty() || args.profile.contains(name))
+        .map(|(name, profile)| Output {
+            name: format!("profile {name:?}"),
+            profile: Profile {
+                destination: config.destination(profile, args.output_dir.as_deref()),
+                ..profile.clone()
+            },
+        })
+        .collect();
+    let flag = |name: String, format, template: Option<String>, destination: &Path| Output {
+        name,
+        profile: Profile {
+            format,
+            template,
+            destination: destination.to_path_buf(),
+            filter: None,
+            locale: None,
+            escape: None,
+        },
+    };
+    for arg in &args.template {
+        outputs.push(flag(
+            format!("--template={}@{}", arg.output.display(), arg.template),
+            ProfileFormat::Template,
+            Some(arg.template.clone()),
+            &arg.output,
+        ));
     }
-    if let Some(output) = cli.json {
-        let mut writer = BufWriter::new(File::create(output)?);
-        serde_json::to_writer_pretty(&mut writer, &changelog)?;
-        writer.flush()?;
+    if let Some(json) = json {
+        outputs.push(flag(
+            format!("--json={}", json.display()),
+            ProfileFormat::Json,
+            None,
+            json,
+        ));
     }
-    for arg in cli.template {
-        let Some((output, template)) = arg.split_once('@') else {
+    for arg in &args.escape {
+        let Some(output) = outputs
+            .iter_mut()
+            .find(|o| o.profile.destination == arg.output)
+        else {
             bail!(
-                "--template={arg} should specify output path and template path like -t=output@template"
+                "--escape={}: not the destination of any output",
+                arg.output.display()
             )
         };
-        let template = std::fs::read_to_string(template)?;
-        let formatted = template::format_changelog(template, &changelog)?;
-        std::fs::write(output, formatted)?;
+        output.profile.escape = Some(arg.mode);
+    }
+    for Output { name, profile } in &outputs {
+        let destination = &profile.destination;
+        if let Some(dir) = destination.parent().filter(|d| !d.as_os_str().is_empty())
+            && !dir.is_dir()
+        {
+            bail!("{name}: the directory of the destination {destination:?} does not exist")
+        }
+    }
+    Ok(outputs)
+}
+
+/// Write every output of the changelog, with the paths touched by changes if `list_paths`
+fn write_outputs(
+    changelog: &ChangeLog,
+    meta: Meta,
+    args: &TemplateArgs,
+    config: &Config,
+    outputs: Vec<Output>,
+    list_paths: bool,
+    written: &mut Vec<PathBuf>,
+) -> color_eyre::Result<()> {
+    let (mut renderer, vars) = load_renderer(&args.renderer, config)?;
+    // Find all the templates before writing anything
+    let mut resolved = Vec::new();
+    for output in outputs {
+        let template = match &output.profile.template {
+            Some(template) => Some(
+                resolve_template(&mut renderer, template)
+                    .with_context(|| format!("the template of {}", output.name))
+                    .wrap_err(ExitStatus::Template)?,
+            ),
+            None => None,
+        };
+        resolved.push((output, template));
+    }
+    for (Output { name, profile }, template) in resolved {
+        let changelog = config.changelog_of(&profile, changelog, list_paths);
+        let content = match template {
+            Some(template) => {
+                let builtin = profile
+                    .template
+                    .as_deref()
+                    .and_then(|t| t.strip_prefix("builtin:"));
+                let escape = profile
+                    .escape
+                    .unwrap_or_else(|| EscapeMode::infer(&profile.destination, builtin));
+                renderer.set_escape(escape);
+                renderer.set_locale(profile.locale);
+                let meta = Meta {
+                    locale: profile.locale.map(|l| l.to_string().replace('_', "-")),
+                    ..meta.clone()
+                };
+                let context = TemplateContext {
+                    meta,
+                    vars: vars.clone(),
+                    ..TemplateContext::new(&changelog)
+                };
+                renderer
+                    .render(&template, &context)
+                    .with_context(|| format!("failed to render {name}"))
+                    .wrap_err(ExitStatus::Template)?
+            }
+            None => serde_json::to_string_pretty(&*changelog)?,
+        };
+        std::fs::write(&profile.destination, content)
+            .with_context(|| format!("{name}: failed to write {:?}", profile.destination))?;
+        info!(output = name, destination = ?profile.destination, "wrote");
+        written.push(profile.destination);
     }
     Ok(())
 }
+
+/// Compile all templates once with the variables of the config and the flags,
+/// as they could be shared by multiple outputs as partials
+fn load_renderer(
+    args: &RendererArgs,
+    config: &Config,
+) -> color_eyre::Result<(Renderer, serde_json::Map<String, serde_json::Value>)> {
+    let mut vars = config.templates.vars.clone();
+    for path in &args.vars_file {
+        vars.extend(load_vars_file(path)?);
+    }
+    for arg in &args.var {
+        let Some((key, value)) = arg.split_once('=') else {
+            bail!("--var={arg} should specify key and value like --var=key=value")
+        };
+        vars.insert(key.to_string(), serde_json::Value::from(value));
+    }
+    let mut renderer = Renderer::new()?;
+    if let Some(timezone) = &args.timezone {
+        let timezone = timezone
+            .parse()
+            .map_err(|e| eyre!("--timezone={timezone}: {e}"))?;
+        renderer.set_timezone(timezone);
+    } else if let Some(timezone) = config.templates.timezone {
+        renderer.set_timezone(timezone);
+    }
+    for dir in config.templates.dirs.iter().chain(&args.template_dir) {
+        renderer
+            .load_dir(dir)
+            .with_context(|| format!("failed to load templates from {dir:?}"))
+            .wrap_err(ExitStatus::Template)?;
+    }
+    Ok((renderer, vars))
+}
+
+/// Find a built-in template (`builtin:NAME`), a template file or a loaded template,
+/// returning its name in the renderer
+fn resolve_template(renderer: &mut Renderer, template: &str) -> color_eyre::Result<String> {
+    if let Some(name) = template.strip_prefix("builtin:") {
+        if !template::BUILTIN_TEMPLATES.iter().any(|(n, _)| *n == name) {
+            let available: Vec<_> = template::BUILTIN_TEMPLATES
+                .iter()
+                .map(|(name, _)| *name)
+                .filter(|name| !name.contains('/'))
+                .collect();
+            bail!(
+                "unknown built-in template {name:?}, available: {}",
+                available.join(", ")
+            )
+        }
+        Ok(name.to_string())
+    } else if std::fs::exists(template)? {
+        let content = std::fs::read_to_string(template)
+            .with_context(|| format!("failed to read template {template:?}"))?;
+        let name = format!("file:{template}");
+        renderer
+            .register(&name, &content)
+            .with_context(|| format!("failed to compile template {template:?}"))?;
+        Ok(name)
+    } else if renderer.has_template(template) {
+        // A template from --template-dir
+        Ok(template.to_string())
+    } else {
+        bail!("template {template:?} is neither a file nor a loaded template")
+    }
+}
+
+/// Load template variables from a JSON or TOML file, which should contain a table
+fn load_vars_file(path: &Path) -> color_eyre::Result<serde_json::Map<String, serde_json::Value>> {
+    let content = std::fs::read_to_string(path)
+        .with_context(|| format!("failed to read vars file {path:?}"))?;
+    let vars = if path.extension().is_some_and(|ext| ext == "toml") {
+        toml::from_str(&content).with_context(|| format!("failed to parse vars file {path:?}"))?
+    } else {
+        serde_json::from_str(&content)
+            .with_context(|| format!("failed to parse vars file {path:?}"))?
+    };
+    match vars {
+        serde_json::Value::Object(vars) => Ok(vars),
+        _ => bail!("vars file {path:?} should contain a table of variables"),
+    }
+}
@@ -1,15 +1,23 @@
 //! Generate changelog for a single repo
 
-use std::{collections::HashSet, error::Error, path::Path, process::Output};
+use std::{
+    collections::{BTreeMap, BTreeSet, HashSet},
+    error::Error,
+    path::Path,
+    process::Output,
+};
 
 use arcstr::ArcStr;
 use chrono::{DateTime, Utc};
-use serde::Serialize;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
 use snafu::{OptionExt, ResultExt, Snafu};
+use tracing::{debug, trace};
 use xshell::{Shell, cmd};
 
 use crate::{
-    changelog::{Change, ChangeKind},
+    changelog::{Change, ChangeKind, GenerateOptions, Signature, Trailer},
+    mailmap::{Mailmap, MailmapError},
     snapshot::RepoStatus,
 };
 
@@ -35,26 +43,134 @@ pub enum RepoChangelogError {
     ParseAuthor { raw: String, reason: &'static str },
     #[snafu(display("failed to parse date {raw:?}: {reason}"))]
     ParseDate { raw: String, reason: &'static str },
+    #[snafu(display("failed to load mailmap"))]
+    Mailmap { source: MailmapError },
 }
 
 pub type Result<T, E = RepoChangelogError> = std::result::Result<T, E>;
 
-#[derive(Debug, Clone, Serialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 pub struct RepoChangeLog {
     pub logs: Vec<Change>,
+    /// Only changes touching these git pathspecs are included, empty if not filtered
+    #[schemars(with = "Vec<String>")]
+    pub pathspec: Vec<ArcStr>,
+    /// The size of all the changes
+    pub diffstat: DiffStat,
+    /// The size of the changes per kind
+    pub diffstat_by_kind: BTreeMap<ChangeKind, DiffStat>,
+    /// The size of the changes per [`Change::category`], leaving out uncategorized ones
+    #[schemars(with = "BTreeMap<String, DiffStat>")]
+    pub diffstat_by_category: BTreeMap<ArcStr, DiffStat>,
+}
+
+impl RepoChangeLog {
+    pub fn new(logs: Vec<Change>, pathspec: Vec<ArcStr>) -> Self {
+        let diffstat = DiffStat::sum(logs.iter().map(|c| &c.diffstat));
+        let diffstat_by_kind = sum_by(&logs, |c| Some(c.kind));
+        let diffstat_by_category = sum_by(&logs, |c| c.category.clone());
+        Self {
+            logs,
+            pathspec,
+            diffstat,
+            diffstat_by_kind,
+            diffstat_by_category,
+        }
+    }
+}
+
+/// Sum the diffstats of the changes with the same key
+fn sum_by<K: Ord>(logs: &[Change], key: impl Fn(&Change) -> Option<K>) -> BTreeMap<K, DiffStat> {
+    let mut keys: Vec<_> = logs.iter().filter_map(&key).collect();
+    keys.sort();
+    keys.dedup();
+    keys.into_iter()
+        .map(|k| {
+            let stats = logs
+                .iter()
+                .filter(|c| key(c).as_ref() == Some(&k))
+                .map(|c| &c.diffstat);
+            let stat = DiffStat::sum(stats);
+            (k, stat)
+        })
+        .collect()
+}
+
+/// Files changed, insertions and deletions, as reported by `git show --numstat`
+#[derive(
+    Debug, Clone, Default, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize, JsonSchema,
+)]
+pub struct DiffStat {
+    pub files_changed: usize,
+    pub insertions: usize,
+    pub deletions: usize,
+    /// The touched paths, only available when requested
+    #[schemars(with = "Option<Vec<String>>")]
+    pub paths: Option<Vec<ArcStr>>,
+}
+
+impl DiffStat {
+    /// Parse the output of `git show --numstat --format=`
+    ///
+    /// Binary files are reported as `-` and counted as changed files without lines.
+    pub fn parse_numstat(output: &str) -> Self {
+        let mut stat = DiffStat::default();
+        let mut paths = Vec::new();
+        for line in output.lines() {
+            let mut fields = line.splitn(3, '\t');
+            let (Some(insertions), Some(deletions), Some(path)) =
+                (fields.next(), fields.next(), fields.next())
+            else {
+                continue;
+            };
+            stat.files_changed += 1;
+            stat.insertions += insertions.parse::<usize>().unwrap_or_default();
+            stat.deletions += deletions.parse::<usize>().unwrap_or_default();
+            paths.push(ArcStr::from(path));
+        }
+        stat.paths = Some(paths);
+        stat
+    }
+
+    /// Aggregate several stats, a file touched by multiple stats is only counted once.
+    pub fn sum<'a>(stats: impl Iterator<Item = &'a DiffStat>) -> Self {
+        let mut total = DiffStat::default();
+        let mut paths = BTreeSet::new();
+        let mut has_paths = true;
+        for stat in stats {
+            total.insertions += stat.insertions;
+            total.deletions += stat.deletions;
+            total.files_changed += stat.files_changed;
+            match &stat.paths {
+                Some(p) => paths.extend(p.iter().cloned()),
+                None => has_paths = false,
+            }
+        }
+        if has_paths {
+            total.files_changed = paths.len();
+            total.paths = Some(paths.into_iter().collect());
+        }
+        total
+    }
 }
 
 #[derive(Debug, Clone)]
-pub struct ParsedCommit {
+pub(crate) struct ParsedCommit {
     author_name: ArcStr,
     author_email: ArcStr,
+    author_date: DateTime<Utc>,
+    committer_name: ArcStr,
+    committer_email: ArcStr,
     commit_date: DateTime<Utc>,
     title: ArcStr,
     description: ArcStr,
     change_id: Option<ArcStr>,
+    trailers: Vec<Trailer>,
+    cherry_picked_from: Vec<ArcStr>,
+    reverts: Option<ArcStr>,
 }
 
-pub fn parse_commit(commit: &str, details: String) -> Result<ParsedCommit> {
+pub(crate) fn parse_commit(commit: &str, details: String) -> Result<ParsedCommit> {
     // Date is UTC unix timestamp.
     // commit c91ae3e2afaee6a578b60fc31d0bd7e793cdf9aa (HEAD, m/lineage-22.2, github/main, github/HEAD)
     // Author:     kxxt <rsworktech@outlook.com>
@@ -70,6 +186,8 @@ pub fn parse_commit(commit: &str, details: String) -> Result<ParsedCommit> {
     //     Trailers
     let lines = details.lines().skip(1);
     let mut author = None;
+    let mut author_date = None;
+    let mut committer = None;
     let mut commit_date = None;
     let mut title = String::new();
     let mut description = String::new();
@@ -92,6 +210,8 @@ pub fn parse_commit(commit: &str, details: String) -> Result<ParsedCommit> {
             let value = value.trim();
             match key {
                 "Author" => author = Some(value),
+                "AuthorDate" => author_date = Some(value),
+                "Commit" => committer = Some(value),
                 "CommitDate" => commit_date = Some(value),
                 _ => continue,
             }
@@ -134,45 +254,38 @@ pub fn parse_commit(commit: &str, details: String) -> Result<ParsedCommit> {
             unreachable!()
         }
     }
-    let author = author.with_context(|| ParseCommitSnafu {
+    let missing = |reason: &'static str| ParseCommitSnafu {
         commit: commit.to_string(),
         raw: details.to_string(),
-        reason: "header does not contain Author field",
-    })?;
-    let (author_name, author_email) =
-        author.rsplit_once(' ').with_context(|| ParseAuthorSnafu {
-            raw: author.to_string(),
-            reason: "Cannot split author into name and email",
-        })?;
-    let author_email = author_email
-        .strip_prefix('<')
-        .with_context(|| ParseAuthorSnafu {
-            raw: author.to_string(),
-            reason: "The email part does not begin with `<`",
-        })?
-        .strip_suffix('>')
-        .with_context(|| ParseAuthorSnafu {
-            raw: author.to_string(),
-            reason: "The email part does not end with `>`",
-        })?;
-    let commit_date = commit_date.with_context(|| ParseCommitSnafu {
-        commit: commit.to_string(),
-        raw: details.to_string(),
-        reason: "header does not contain CommitDate field",
-    })?;
-    let commit_date = commit_date
-        .parse::<u32>()
-        .ok()
-        .with_context(|| ParseDateSnafu {
-            raw: commit_date.to_string(),
-            reason: "Failed to parse date as an unsigned integer. Did you use `--date=unix`?",
-        })?;
-    let commit_date =
-        DateTime::<Utc>::from_timestamp(commit_date as i64, 0).with_context(|| ParseDateSnafu {
-            raw: commit_date.to_string(),
-            reason: "Date is out of range",
-        })?;
+        reason,
+    };
+    let author = author.with_context(|| missing("header does not contain Author field"))?;
+    let (author_name, author_email) = parse_person(author)?;
+    let commit_date = parse_date(
+        commit_date.with_context(|| missing("header does not contain CommitDate field"))?,
+    )?;
+    // Headers without them, like those of `--format=medium`, are still accepted
+    let author_date = author_date
+        .map(parse_date)
+        .transpose()?
+        .unwrap_or(commit_date);
+    let (committer_name, committer_email) = parse_person(committer.unwrap_or(author))?;
     let title = ArcStr::from(title);
+    // `git cherry-pick -x` leaves a note in the body or the trailer block,
+    // which could appear more than once for changes that are picked multiple times.
+    let cherry_picked_from = description
+        .lines()
+        .filter_map(|l| l.trim().strip_prefix("(cherry picked from commit "))
+        .filter_map(|l| l.strip_suffix(')'))
+        .map(|s| ArcStr::from(s.trim()))
+        .collect();
+    // `git revert` leaves `This reverts commit <sha>.` in the body
+    let reverts = description
+        .lines()
+        .filter_map(|l| l.trim().strip_prefix("This reverts commit "))
+        .map(|l| l.trim_end_matches(['.', ',']).trim())
+        .find(|c| !c.is_empty() && c.bytes().all(|b| b.is_ascii_hexdigit()))
+        .map(ArcStr::from);
     // Let's see if it contains trailers
     let (description, trailers) =
         if let Some((description, trailers)) = description.rsplit_once("\n\n") {
@@ -180,60 +293,149 @@ pub fn parse_commit(commit: &str, details: String) -> Result<ParsedCommit> {
         } else {
             (description.as_str(), None)
         };
-    // Then get change id from the trailers
+    // Then parse the trailers
     // If trailers is None, maybe there is no body and description should be parsed as trailers.
-    let (description, change_id) = if let Some(trailers) = trailers {
-        (
-            description,
-            trailers
-                .lines()
-                .filter_map(|l| l.strip_prefix("Change-Id:"))
-                .next()
-                .map(|s| ArcStr::from(s.trim())),
-        )
+    let (description, trailers) = if let Some(trailers) = trailers {
+        (description, parse_trailers(trailers))
+    } else if description.lines().next().is_some_and(|s| s.contains(": ")) {
+        ("", parse_trailers(description))
     } else {
-        if description.lines().next().is_some_and(|s| s.contains(": ")) {
-            (
-                "",
-                description
-                    .lines()
-                    .filter_map(|l| l.strip_prefix("Change-Id:"))
-                    .next()
-                    .map(|s| ArcStr::from(s.trim())),
-            )
-        } else {
-            (description, None)
-        }
+        (description, Vec::new())
     };
+    let change_id = trailers
+        .iter()
+        .find(|t| &*t.key == "Change-Id")
+        .map(|t| t.value.clone());
     Ok(ParsedCommit {
-        author_name: ArcStr::from(author_name),
-        author_email: ArcStr::from(author_email),
+        author_name,
+        author_email,
+        author_date,
+        committer_name,
+        committer_email,
         commit_date,
         title,
         description: ArcStr::from(description),
         change_id,
+        trailers,
+        cherry_picked_from,
+        reverts,
+    })
+}
+
+/// Parse `Key: value` lines of a trailer block, other lines are ignored.
+fn parse_trailers(block: &str) -> Vec<Trailer> {
+    block
+        .lines()
+        .filter_map(|l| l.split_once(':'))
+        .filter(|(key, _)| !key.is_empty() && !key.contains(char::is_whitespace))
+        .map(|(key, value)| Trailer {
+            key: ArcStr::from(key),
+            value: ArcStr::from(value.trim()),
+        })
+        .collect()
+}
+
+/// Parse a person like `kxxt <rsworktech@outlook.com>` into name and email
+fn parse_person(raw: &str) -> Result<(ArcStr, ArcStr)> {
+    let (name, email) = raw.rsplit_once(' ').with_context(|| ParseAuthorSnafu {
+        raw: raw.to_string(),
+        reason: "Cannot split author into name and email",
+    })?;
+    let email = email
+        .strip_prefix('<')
+        .with_context(|| ParseAuthorSnafu {
+            raw: raw.to_string(),
+            reason: "The email part does not begin with `<`",
+        })?
+        .strip_suffix('>')
+        .with_context(|| ParseAuthorSnafu {
+            raw: raw.to_string(),
+            reason: "The email part does not end with `>`",
+        })?;
+    Ok((ArcStr::from(name), ArcStr::from(email)))
+}
+
+/// Find which of the emails, lowercased, authored a commit reachable from `before`,
+/// with a single walk of the history.
+///
+/// Other emails `mailmap` maps to the same person are looked for too,
+/// and the canonical emails of the authors found are reported with their own.
+pub(crate) fn prior_authors(
+    repo: &ArcStr,
+    top: impl AsRef<Path>,
+    before: &RepoStatus,
+    emails: &[&str],
+    mailmap: &Mailmap,
+) -> Result<HashSet<ArcStr>> {
+    let sh = Shell::new().context(ShellCreationSnafu)?;
+    let repo_path = top.as_ref().join(repo.as_str());
+    let mailmap = Mailmap::load(repo_path.join(".mailmap"))
+        .context(MailmapSnafu)?
+        .merged(mailmap);
+    let before = before.commit.as_ref();
+    let emails: BTreeSet<&str> = emails
+        .iter()
+        .flat_map(|e| mailmap.aliases(e).chain([*e]))
+        .collect();
+    let authors = emails.iter().map(|e| format!("--author=<{e}>"));
+    let found = output2string(
+        cmd!(
+            sh,
+            "git -C {repo_path} log --format=%an%x00%ae --fixed-strings --regexp-ignore-case {authors...} {before}"
+        )
+        .output()
+        .context(CommandExecutionSnafu)?,
+    )?;
+    let mut prior = HashSet::new();
+    for (name, email) in found.lines().filter_map(|line| line.split_once('\0')) {
+        let canonical = mailmap.resolve(name, email).email;
+        prior.insert(ArcStr::from(email.to_lowercase()));
+        prior.insert(ArcStr::from(canonical.to_lowercase()));
+    }
+    Ok(prior)
+}
+
+/// Parse a date formatted with `--date=unix`
+fn parse_date(raw: &str) -> Result<DateTime<Utc>> {
+    let timestamp = raw.parse::<u32>().ok().with_context(|| ParseDateSnafu {
+        raw: raw.to_string(),
+        reason: "Failed to parse date as an unsigned integer. Did you use `--date=unix`?",
+    })?;
+    DateTime::<Utc>::from_timestamp(timestamp as i64, 0).with_context(|| ParseDateSnafu {
+        raw: raw.to_string(),
+        reason: "Date is out of range",
     })
 }
 
-pub fn generate_repo_changelog(
+pub(crate) fn generate_repo_changelog(
     source: &RepoStatus,
     target: &RepoStatus,
     repo: &ArcStr,
     top: impl AsRef<Path>,
     sync_stamp_branch: &str,
+    options: &GenerateOptions,
 ) -> Result<RepoChangeLog> {
     let sh = Shell::new().context(ShellCreationSnafu)?;
     let repo_path = top.as_ref().join(repo.as_str());
+    let pathspec = options.pathspec(repo);
+    let paths: Vec<&str> = pathspec.iter().map(|p| p.as_str()).collect();
+    let paths = paths.as_slice();
+    // The global mailmap takes precedence over the one shipped with the repo, like git.
+    let mailmap = Mailmap::load(repo_path.join(".mailmap"))
+        .context(MailmapSnafu)?
+        .merged(&options.mailmap);
     let source_commit = source.commit.as_ref();
     let target_commit = target.commit.as_ref();
     // We will do it in two pass,
     // in the first pass, we figure out which commits should be included into the changelog.
 
     // Get all merge commits, which are handled separately.
+    // Without --full-history, a pathspec would simplify away merges
+    // that are TREESAME to the side they bring in.
     let merge_commits = output2string(
         cmd!(
             sh,
-            "git -C {repo_path} rev-list --min-parents=2 {source_commit}..{target_commit}"
+            "git -C {repo_path} rev-list --min-parents=2 --full-history {source_commit}..{target_commit} -- {paths...}"
         )
         .output()
         .context(CommandExecutionSnafu)?,
@@ -244,12 +446,17 @@ pub fn generate_repo_changelog(
     let commits = output2string(
         cmd!(
             sh,
-            "git -C {repo_path} rev-list --first-parent {source_commit}..{target_commit}"
+            "git -C {repo_path} rev-list --first-parent {source_commit}..{target_commit} -- {paths...}"
         )
         .output()
         .context(CommandExecutionSnafu)?,
     )?;
     let commits: Vec<_> = commits.lines().map(|x| x.trim()).collect();
+    debug!(
+        commits = commits.len(),
+        merges = merge_commits.len(),
+        "listed commits"
+    );
     let mut logs = Vec::new();
 
     // Get the remote commit url
@@ -271,10 +478,24 @@ pub fn generate_repo_changelog(
             .context(CommandExecutionSnafu)?,
     )?;
     let upstream = upstream.trim();
-    let commit_url_template = upstream_url_to_commit_url_template(upstream);
-    let review_url_template = upstream_url_to_review_url_template(upstream);
+    trace!(upstream, "found the remote");
+    let (commit_url_template, review_url_template) = match options
+        .forges
+        .iter()
+        .find(|forge| forge.url.is_match(upstream))
+    {
+        Some(forge) => (
+            forge.commit_url.as_ref().map(|t| t.with_remote(upstream)),
+            forge.review_url.as_ref().map(|t| t.with_remote(upstream)),
+        ),
+        None => (
+            upstream_url_to_commit_url_template(upstream),
+            upstream_url_to_review_url_template(upstream),
+        ),
+    };
 
     for commit in commits {
+        trace!(commit, "reading commit");
         let commit_details = output2string(
             cmd!(
                 sh,
@@ -286,23 +507,64 @@ pub fn generate_repo_changelog(
         let ParsedCommit {
             author_name,
             author_email,
+            author_date,
+            committer_name,
+            committer_email,
             commit_date,
             title,
             description,
             change_id,
+            trailers,
+            cherry_picked_from,
+            reverts,
         } = parse_commit(commit, commit_details)?;
+        let co_authors = trailers
+            .iter()
+            .filter(|t| t.key.eq_ignore_ascii_case("Co-authored-by"))
+            .filter_map(|t| parse_person(&t.value).ok())
+            .map(|(name, email)| mailmap.resolve(&name, &email))
+            .collect();
         let kind = if merge_commits.contains(commit) {
             ChangeKind::Merge
+        } else if reverts.is_some() {
+            ChangeKind::Revert
         } else {
             ChangeKind::Normal
         };
+        // Merge commits do not have a meaningful patch
+        let patch_id = if kind == ChangeKind::Merge {
+            None
+        } else {
+            get_patch_id(&sh, &repo_path, commit)?
+        };
+        // For merges, count what they bring into the first parent
+        let numstat = output2string(
+            cmd!(
+                sh,
+                "git -C {repo_path} show --numstat --format= --diff-merges=first-parent {commit} -- {paths...}"
+            )
+            .output()
+            .context(CommandExecutionSnafu)?,
+        )?;
         logs.push(Change {
             kind,
             repo: repo.clone(),
             title,
             description,
+            author: Signature {
+                canonical: mailmap.resolve(&author_name, &author_email),
+                name: author_name.clone(),
+                email: author_email.clone(),
+                date: author_date,
+            },
             author_name,
             author_email,
+            committer: Signature {
+                canonical: mailmap.resolve(&committer_name, &committer_email),
+                name: committer_name,
+                email: committer_email,
+                date: commit_date,
+            },
             datetime: commit_date,
             commit: ArcStr::from(commit),
             commit_url: commit_url_template
@@ -311,33 +573,56 @@ pub fn generate_repo_changelog(
             review_url: change_id.as_ref().and_then(|c| {
                 review_url_template
                     .as_ref()
-                    .map(|s| ArcStr::from(s.replace("{change-id}", &c)))
+                    .map(|s| ArcStr::from(s.replace("{change-id}", c)))
             }),
             change_id,
+            trailers,
+            co_authors,
+            cherry_picked_from,
+            reverts,
+            patch_id,
+            diffstat: DiffStat::parse_numstat(&numstat),
+            duplicates: Vec::new(),
+            category: None,
         });
     }
-    Ok(RepoChangeLog { logs })
+    Ok(RepoChangeLog::new(logs, pathspec.to_vec()))
+}
+
+/// Get the stable patch id of a commit, which stays the same when a change
+/// is cherry-picked into another branch or repo.
+///
+/// Returns `None` for empty commits.
+fn get_patch_id(sh: &Shell, repo_path: &Path, commit: &str) -> Result<Option<ArcStr>> {
+    let diff = cmd!(sh, "git -C {repo_path} show --format= {commit}")
+        .output()
+        .context(CommandExecutionSnafu)?;
+    let patch_id = output2string(
+        cmd!(sh, "git -C {repo_path} patch-id --stable")
+            .stdin(diff.stdout)
+            .output()
+            .context(CommandExecutionSnafu)?,
+    )?;
+    Ok(patch_id.split_whitespace().next().map(ArcStr::from))
 }
 
 fn upstream_url_to_commit_url_template(url: &str) -> Option<String> {
     if url.contains("/android.googlesource.com/") {
-        return Some(format!("{url}/+/{{commit}}"));
+        Some(format!("{url}/+/{{commit}}"))
     } else if url.contains("/github.com/") {
-        return Some(format!("{url}/commit/{{commit}}"));
+        Some(format!("{url}/commit/{{commit}}"))
     } else {
-        return None;
+        None
     }
 }
 
 fn upstream_url_to_review_url_template(url: &str) -> Option<String> {
     if url.contains("/android.googlesource.com/") {
-        return Some(format!(
-            "https://android-review.googlesource.com/q/{{change-id}}"
-        ));
+        Some("https://android-review.googlesource.com/q/{change-id}".to_string())
     } else if url.contains("/github.com/LineageOS/") {
-        return Some(format!("https://review.lineageos.org/q/{{change-id}}"));
+        Some("https://review.lineageos.org/q/{change-id}".to_string())
     } else {
-        return None;
+        None
     }
 }
 
@@ -367,6 +652,7 @@ fn shorten_commit(commit: &str) -> &str {
 #[cfg(test)]
 mod test {
     use super::*;
+    use crate::snapshot::CommitHash;
     use chrono::{TimeZone, Utc};
 
     #[test]
@@ -390,6 +676,7 @@ CommitDate: 1751211480
         let result = parse_commit(commit, details).unwrap();
 
         assert_eq!(&*result.author_name, "kxxt");
+        assert_eq!(&*result.committer_name, "kxxt");
         assert_eq!(&*result.author_email, "rsworktech@outlook.com");
         assert_eq!(
             result.commit_date,
@@ -433,6 +720,50 @@ CommitDate: 1751211480
         assert!(format!("{err}").contains("does not contain Author field"));
     }
 
+    #[test]
+    fn test_parse_commit_distinct_committer() {
+        let commit = "abcdef";
+        let details = r#"
+Author:     Some One <someone@example.com>
+AuthorDate: 1751211000
+Commit:     Other Person <other@example.com>
+CommitDate: 1751211480
+
+    A valid title
+"#
+        .to_string();
+
+        let result = parse_commit(commit, details).unwrap();
+        assert_eq!(&*result.author_name, "Some One");
+        assert_eq!(&*result.committer_name, "Other Person");
+        assert_eq!(&*result.committer_email, "other@example.com");
+        assert_eq!(
+            result.author_date,
+            Utc.timestamp_opt(1751211000, 0).unwrap()
+        );
+        assert_eq!(
+            result.commit_date,
+            Utc.timestamp_opt(1751211480, 0).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_parse_commit_without_committer() {
+        let commit = "abcdef";
+        let details = r#"
+Author:     Some One <someone@example.com>
+CommitDate: 1751211480
+
+    A valid title
+"#
+        .to_string();
+
+        let result = parse_commit(commit, details).unwrap();
+        assert_eq!(&*result.committer_name, "Some One");
+        assert_eq!(&*result.committer_email, "someone@example.com");
+        assert_eq!(result.author_date, result.commit_date);
+    }
+
     #[test]
     fn test_parse_commit_invalid_date() {
         let commit = "abcdef";
@@ -448,6 +779,111 @@ CommitDate: not_a_date
         assert!(format!("{err}").contains("Failed to parse date"));
     }
 
+    #[test]
+    fn test_parse_commit_cherry_picked() {
+        let commit = "abcdef";
+        let details = r#"
+Author:     Someone <someone@example.com>
+CommitDate: 1751211480
+
+    Fix a bug
+    
+    Bug: 123456
+    Change-Id: I0123456789abcdef
+    (cherry picked from commit 0123456789abcdef0123456789abcdef01234567)
+"#
+        .to_string();
+
+        let result = parse_commit(commit, details).unwrap();
+        assert_eq!(result.change_id.as_deref(), Some("I0123456789abcdef"));
+        assert_eq!(
+            result.cherry_picked_from,
+            vec![ArcStr::from("0123456789abcdef0123456789abcdef01234567")]
+        );
+    }
+
+    #[test]
+    fn test_parse_commit_revert() {
+        let commit = "abcdef";
+        let details = r#"
+Author:     Someone <someone@example.com>
+CommitDate: 1751211480
+
+    Revert "Fix a bug"
+    
+    This reverts commit 0123456789abcdef0123456789abcdef01234567.
+    
+    Reason for revert: breaks boot
+    
+    Change-Id: I0123456789abcdef
+"#
+        .to_string();
+
+        let result = parse_commit(commit, details).unwrap();
+        assert_eq!(
+            result.reverts.as_deref(),
+            Some("0123456789abcdef0123456789abcdef01234567")
+        );
+    }
+
+    #[test]
+    fn test_parse_commit_trailers() {
+        let commit = "abcdef";
+        let details = r#"
+Author:     Someone <someone@example.com>
+CommitDate: 1751211480
+
+    Add a feature
+    
+    Body: not a trailer
+    
+    Bug: 123456
+    Co-authored-by: Other Person <other@example.com>
+    Change-Id: I0123456789abcdef
+"#
+        .to_string();
+
+        let result = parse_commit(commit, details).unwrap();
+        let trailers: Vec<_> = result
+            .trailers
+            .iter()
+            .map(|t| (&*t.key, &*t.value))
+            .collect();
+        assert_eq!(
+            trailers,
+            [
+                ("Bug", "123456"),
+                ("Co-authored-by", "Other Person <other@example.com>"),
+                ("Change-Id", "I0123456789abcdef")
+            ]
+        );
+        assert_eq!(&*result.description, "Body: not a trailer");
+        assert_eq!(result.change_id.as_deref(), Some("I0123456789abcdef"));
+    }
+
+    #[test]
+    fn test_parse_numstat() {
+        let stat =
+            DiffStat::parse_numstat("10\t2\tREADME.md\n-\t-\tlogo.png\n0\t5\tsrc/{a => b}.rs\n");
+        assert_eq!(stat.files_changed, 3);
+        assert_eq!(stat.insertions, 10);
+        assert_eq!(stat.deletions, 7);
+        assert_eq!(
+            stat.paths.unwrap(),
+            ["README.md", "logo.png", "src/{a => b}.rs"]
+        );
+    }
+
+    #[test]
+    fn test_sum_diffstat() {
+        let a = DiffStat::parse_numstat("1\t2\tREADME.md\n3\t4\tMakefile\n");
+        let b = DiffStat::parse_numstat("5\t6\tREADME.md\n");
+        let total = DiffStat::sum([&a, &b].into_iter());
+        assert_eq!(total.files_changed, 2);
+        assert_eq!(total.insertions, 9);
+        assert_eq!(total.deletions, 12);
+    }
+
     #[test]
     fn test_parse_commit_no_trailers() {
         let commit = "abcdef";
@@ -465,4 +901,123 @@ CommitDate: 1751211480
         assert_eq!(result.change_id, None);
         assert_eq!(&*result.description, "Body without trailer.\n");
     }
+
+    /// Run git in `repo` with a fixed identity and clock
+    fn git(repo: &Path, args: &[&str]) -> String {
+        let output = std::process::Command::new("git")
+            .arg("-C")
+            .arg(repo)
+            .args([
+                "-c",
+                "user.name=Someone",
+                "-c",
+                "user.email=someone@example.com",
+            ])
+            .args(args)
+            .env("GIT_AUTHOR_DATE", "1751211480 +0000")
+            .env("GIT_COMMITTER_DATE", "1751211480 +0000")
+            .output()
+            .unwrap();
+        assert!(output.status.success(), "git {args:?} failed: {output:?}");
+        String::from_utf8(output.stdout).unwrap().trim().to_string()
+    }
+
+    fn commit_file(repo: &Path, path: &str, title: &str) {
+        let file = repo.join(path);
+        std::fs::create_dir_all(file.parent().unwrap()).unwrap();
+        std::fs::write(file, title).unwrap();
+        git(repo, &["add", path]);
+        git(repo, &["commit", "-q", "-m", title]);
+    }
+
+    #[test]
+    fn test_pathspec_keeps_merges() {
+        let top = std::env::temp_dir().join(format!("changelog-pathspec-{}", std::process::id()));
+        let repo = top.join("platform/test");
+        std::fs::create_dir_all(&repo).unwrap();
+        git(&repo, &["init", "-q", "-b", "main"]);
+        commit_file(&repo, "dir/a", "Add a");
+        let source = git(&repo, &["rev-parse", "HEAD"]);
+        git(&repo, &["checkout", "-q", "-b", "side"]);
+        commit_file(&repo, "dir/b", "Add b");
+        git(&repo, &["checkout", "-q", "main"]);
+        commit_file(&repo, "other/c", "Add c");
+        git(
+            &repo,
+            &["merge", "-q", "--no-ff", "-m", "Merge side", "side"],
+        );
+        let target = git(&repo, &["rev-parse", "HEAD"]);
+        git(
+            &repo,
+            &[
+                "remote",
+                "add",
+                "origin",
+                "https://example.com/platform/test",
+            ],
+        );
+        git(&repo, &["update-ref", "refs/remotes/origin/main", "HEAD"]);
+
+        let status = |commit: String| RepoStatus {
+            commit: CommitHash::try_new(commit).unwrap(),
+        };
+        let mut options = GenerateOptions::default();
+        options
+            .pathspecs
+            .insert(ArcStr::from("platform/test"), vec![ArcStr::from("dir")]);
+        let log = generate_repo_changelog(
+            &status(source),
+            &status(target),
+            &ArcStr::from("platform/test"),
+            &top,
+            "origin/main",
+            &options,
+        );
+        std::fs::remove_dir_all(&top).unwrap();
+        let log = log.unwrap();
+        // The change to other/ is filtered out, but the merge bringing in dir/b is kept
+        let changes: Vec<_> = log.logs.iter().map(|c| (&*c.title, c.kind)).collect();
+        assert_eq!(changes, [("Merge side", ChangeKind::Merge)]);
+        assert_eq!(log.pathspec, ["dir"]);
+    }
+
+    #[test]
+    fn test_prior_authors() {
+        let top = std::env::temp_dir().join(format!("changelog-authors-{}", std::process::id()));
+        let repo = top.join("platform/test");
+        std::fs::create_dir_all(&repo).unwrap();
+        git(&repo, &["init", "-q", "-b", "main"]);
+        commit_file(&repo, "a", "Add a");
+        let before = RepoStatus {
+            commit: CommitHash::try_new(git(&repo, &["rev-parse", "HEAD"])).unwrap(),
+        };
+        let prior = prior_authors(
+            &ArcStr::from("platform/test"),
+            &top,
+            &before,
+            &["Someone@Example.com", "new@example.com"],
+            &Mailmap::default(),
+        );
+        // The prior commit is by an old address of the person
+        let mailmap = Mailmap::parse("<New@Example.com> <someone@example.com>").unwrap();
+        let aliased = prior_authors(
+            &ArcStr::from("platform/test"),
+            &top,
+            &before,
+            &["new@example.com"],
+            &mailmap,
+        );
+        std::fs::remove_dir_all(&top).unwrap();
+        assert_eq!(
+            prior.unwrap(),
+            HashSet::from([ArcStr::from("someone@example.com")])
+        );
+        assert_eq!(
+            aliased.unwrap(),
+            HashSet::from([
+                ArcStr::from("someone@example.com"),
+                ArcStr::from("new@example.com")
+            ])
+        );
+    }
 }
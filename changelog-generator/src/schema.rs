@@ -0,0 +1,384 @@
+//! The versioned JSON format of [`ChangeLog`]
+//!
+//! Changelogs saved by `--json` record the [`SCHEMA_VERSION`] they are written in,
+//! and [`read`] migrates changelogs of older versions to the current one,
+//! so that a changelog saved by any released version could be rendered again.
+//!
+//! The JSON Schema of the current version is published as `changelog.schema.json`.
+
+use std::{convert::Infallible, io::Read, mem};
+
+use schemars::Schema;
+use serde_json::{Map, Value, json};
+use snafu::{OptionExt, ResultExt, Snafu, ensure};
+
+use crate::{changelog::ChangeLog, contributors::Contributors, repo_log::RepoChangeLog};
+
+/// The version of the changelog JSON written by this build
+///
+/// Bump it and add a migration to `MIGRATIONS` for every change
+/// that older changelogs could not be deserialized with.
+pub const SCHEMA_VERSION: u32 = 3;
+
+/// `MIGRATIONS[v]` upgrades a changelog of version `v` to `v + 1`
+const MIGRATIONS: [fn(&mut Map<String, Value>); SCHEMA_VERSION as usize] =
+    [migrate_v0, migrate_v1, migrate_v2];
+
+#[derive(Debug, Snafu)]
+pub enum SchemaError {
+    #[snafu(display("failed to parse changelog as JSON"))]
+    Json { source: serde_json::Error },
+    #[snafu(display("the changelog is not a JSON object"))]
+    NotAnObject,
+    #[snafu(display("invalid schema_version {version}, expected a non-negative integer"))]
+    InvalidVersion { version: Value },
+    #[snafu(display(
+        "the changelog has schema version {version} but only versions up to {SCHEMA_VERSION} are supported, please upgrade"
+    ))]
+    UnsupportedVersion { version: u64 },
+    #[snafu(display("failed to read changelog of schema version {version}"))]
+    Deserialize {
+        version: u64,
+        source: serde_json::Error,
+    },
+}
+
+pub type Result<T, E = SchemaError> = std::result::Result<T, E>;
+
+/// Read a changelog of any supported schema version.
+pub fn read(reader: impl Read) -> Result<ChangeLog> {
+    read_versioned(reader).map(|(changelog, _)| changelog)
+}
+
+/// Read a changelog of any supported schema version, together with that version.
+pub fn read_versioned(reader: impl Read) -> Result<(ChangeLog, u64)> {
+    let value = serde_json::from_reader(reader).context(JsonSnafu)?;
+    from_value_versioned(value)
+}
+
+/// Convert a changelog of any supported schema version.
+pub fn from_value(value: Value) -> Result<ChangeLog> {
+    from_value_versioned(value).map(|(changelog, _)| changelog)
+}
+
+fn from_value_versioned(value: Value) -> Result<(ChangeLog, u64)> {
+    let Value::Object(mut object) = value else {
+        return NotAnObjectSnafu.fail();
+    };
+    let version = match object.get("schema_version") {
+        None => 0,
+        Some(version) => version.as_u64().with_context(|| InvalidVersionSnafu {
+            version: version.clone(),
+        })?,
+    };
+    ensure!(
+        version <= u64::from(SCHEMA_VERSION),
+        UnsupportedVersionSnafu { version }
+    );
+    for migrate in &MIGRATIONS[version as usize..] {
+        migrate(&mut object);
+    }
+    object.insert("schema_version".to_string(), json!(SCHEMA_VERSION));
+    let mut changelog: ChangeLog =
+        serde_json::from_value(Value::Object(object)).context(DeserializeSnafu { version })?;
+    if version == 0 {
+        recompute_v0(&mut changelog);
+    }
+    if version < 2 {
+        recompute_v1(&mut changelog);
+    }
+    Ok((changelog, version))
+}
+
+/// The JSON Schema of the current version
+pub fn json_schema() -> Schema {
+    schemars::schema_for!(ChangeLog)
+}
+
+/// Version 0 is the unversioned format
+///
+/// Authors were flattened into `author_name` and `author_email`,
+/// and most fields of a change did not exist yet.
+/// Fields are only added when missing, so that unversioned changelogs written
+/// by later development builds are accepted as well.
+fn migrate_v0(changelog: &mut Map<String, Value>) {
+    for key in ["added_repos", "removed_repos", "changes"] {
+        insert_missing(changelog, key, json!({}));
+    }
+    insert_missing(changelog, "log", json!([]));
+    insert_missing(changelog, "hidden_reverts", json!([]));
+    // Recomputed from the log after deserializing
+    insert_missing(
+        changelog,
+        "contributors",
+        serde_json::to_value(Contributors::default()).unwrap(),
+    );
+
+    if let Some(Value::Object(repos)) = changelog.get_mut("changes") {
+        for repo in repos.values_mut().filter_map(Value::as_object_mut) {
+            insert_missing(repo, "pathspec", json!([]));
+            // Recomputed from the logs after deserializing
+            insert_missing(repo, "diffstat", empty_diffstat());
+            insert_missing(repo, "diffstat_by_kind", json!({}));
+        }
+    }
+    for change in changes_mut(changelog) {
+        migrate_v0_change(change);
+    }
+}
+
+fn migrate_v0_change(change: &mut Map<String, Value>) {
+    if !change.contains_key("author") {
+        let name = change.get("author_name").cloned().unwrap_or(json!(""));
+        let email = change.get("author_email").cloned().unwrap_or(json!(""));
+        let date = change.get("datetime").cloned().unwrap_or(Value::Null);
+        let author = json!({
+            "name": name,
+            "email": email,
+            "date": date,
+            "canonical": { "name": name, "email": email },
+        });
+        change.insert("author".to_string(), author);
+    }
+    let author = change["author"].clone();
+    insert_missing(change, "committer", author);
+    for key in ["trailers", "co_authors", "cherry_picked_from", "duplicates"] {
+        insert_missing(change, key, json!([]));
+    }
+    insert_missing(change, "diffstat", empty_diffstat());
+}
+
+/// Version 1 did not record the tag of the source snapshot,
+/// nor the size of the changes per category
+fn migrate_v1(changelog: &mut Map<String, Value>) {
+    insert_missing(changelog, "source_tag", Value::Null);
+    if let Some(Value::Object(repos)) = changelog.get_mut("changes") {
+        for repo in repos.values_mut().filter_map(Value::as_object_mut) {
+            // Recomputed from the logs after deserializing
+            insert_missing(repo, "diffstat_by_category", json!({}));
+        }
+    }
+}
+
+/// Version 2 did not write the flat `author_name` and `author_email`
+/// of version 0 alongside the author
+fn migrate_v2(changelog: &mut Map<String, Value>) {
+    for change in changes_mut(changelog) {
+        let author = change.get("author").cloned().unwrap_or(Value::Null);
+        insert_missing(change, "author_name", author["name"].clone());
+        insert_missing(change, "author_email", author["email"].clone());
+    }
+}
+
+/// Every change in the log, the per-repo logs and the recent changes of added repos
+fn changes_mut(changelog: &mut Map<String, Value>) -> Vec<&mut Map<String, Value>> {
+    let mut changes: Vec<&mut Value> = Vec::new();
+    for (key, value) in changelog.iter_mut() {
+        match (key.as_str(), value) {
+            ("log", Value::Array(log)) => changes.extend(log),
+            ("added_repos", Value::Object(repos)) => changes.extend(
+                repos
+                    .values_mut()
+                    .filter_map(|repo| repo.get_mut("recent_changes"))
+                    .filter_map(Value::as_array_mut)
+                    .flatten(),
+            ),
+            ("changes", Value::Object(repos)) => changes.extend(
+                repos
+                    .values_mut()
+                    .filter_map(|repo| repo.get_mut("logs"))
+                    .filter_map(Value::as_array_mut)
+                    .flatten(),
+            ),
+            _ => {}
+        }
+    }
+    changes
+        .into_iter()
+        .filter_map(Value::as_object_mut)
+        .collect()
+}
+
+/// Fill in the contributors version 0 did not save
+fn recompute_v0(changelog: &mut ChangeLog) {
+    if changelog.contributors.authors.is_empty() {
+        // Whether an author contributed before is unknown without the tree,
+        // so nobody is reported as a first-time contributor.
+        let Ok(contributors) = Contributors::collect(&changelog.log, &changelog.changes, |_, _| {
+            Ok::<_, Infallible>(None)
+        });
+        changelog.contributors = contributors;
+    }
+}
+
+/// Fill in the summaries per repo version 1 did not save, and version 0 neither
+fn recompute_v1(changelog: &mut ChangeLog) {
+    for repo in changelog.changes.values_mut() {
+        *repo = RepoChangeLog::new(mem::take(&mut repo.logs), mem::take(&mut repo.pathspec));
+    }
+}
+
+fn insert_missing(object: &mut Map<String, Value>, key: &str, value: Value) {
+    object.entry(key).or_insert(value);
+}
+
+fn empty_diffstat() -> Value {
+    json!({ "files_changed": 0, "insertions": 0, "deletions": 0, "paths": null })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A changelog written before versioning
+    const V0: &str = r#"{
+  "added_repos": {
+    "vendor/foo": {
+      "upstream": "lineage-23.0",
+      "recent_changes": [
+        {
+          "datetime": "2025-06-01T08:00:00Z",
+          "kind": "Normal",
+          "repo": "vendor/foo",
+          "title": "Initial commit",
+          "description": "",
+          "author_name": "Bob",
+          "author_email": "bob@example.com",
+          "change_id": null,
+          "commit": "1111111111111111111111111111111111111111",
+          "commit_url": null,
+          "review_url": null
+        }
+      ],
+      "commit": "1111111111111111111111111111111111111111"
+    }
+  },
+  "removed_repos": {
+    "vendor/bar": { "last_seen_commit": "2222222222222222222222222222222222222222" }
+  },
+  "log": [
+    {
+      "datetime": "2025-06-02T08:00:00Z",
+      "kind": "Merge",
+      "repo": "system/core",
+      "title": "Merge tag 'android-16.0.0_r2'",
+      "description": "",
+      "author_name": "Alice",
+      "author_email": "alice@example.com",
+      "change_id": "I0123",
+      "commit": "3333333333333333333333333333333333333333",
+      "commit_url": "https://example.com/commit",
+      "review_url": "https://example.com/review"
+    }
+  ],
+  "changes": {
+    "system/core": {
+      "logs": [
+        {
+          "datetime": "2025-06-02T08:00:00Z",
+          "kind": "Merge",
+          "repo": "system/core",
+          "title": "Merge tag 'android-16.0.0_r2'",
+          "description": "",
+          "author_name": "Alice",
+          "author_email": "alice@example.com",
+          "change_id": "I0123",
+          "commit": "3333333333333333333333333333333333333333",
+          "commit_url": "https://example.com/commit",
+          "review_url": "https://example.com/review"
+        }
+      ]
+    }
+  }
+}"#;
+
+    #[test]
+    fn test_read_v0() {
+        let changelog = read(V0.as_bytes()).unwrap();
+        assert_eq!(changelog.schema_version, SCHEMA_VERSION);
+        let change = &changelog.log[0];
+        assert_eq!(change.author.name, "Alice");
+        assert_eq!(change.author_name, "Alice");
+        assert_eq!(change.author.canonical.email, "alice@example.com");
+        assert_eq!(change.author.date, change.datetime);
+        assert_eq!(change.committer, change.author);
+        assert_eq!(
+            changelog.added_repos["vendor/foo"].recent_changes[0]
+                .author
+                .name,
+            "Bob"
+        );
+        let repo = &changelog.changes["system/core"];
+        assert_eq!(repo.logs.len(), 1);
+        assert_eq!(repo.diffstat_by_kind.len(), 1);
+        assert_eq!(changelog.contributors.authors[0].identity.name, "Alice");
+        assert!(changelog.contributors.first_time.is_empty());
+    }
+
+    #[test]
+    fn test_read_v1() {
+        let mut json = serde_json::to_value(ChangeLog::sample()).unwrap();
+        json["schema_version"] = json!(1);
+        json.as_object_mut().unwrap().remove("source_tag");
+        for repo in json["changes"].as_object_mut().unwrap().values_mut() {
+            repo.as_object_mut().unwrap().remove("diffstat_by_category");
+        }
+        let (changelog, version) = read_versioned(json.to_string().as_bytes()).unwrap();
+        assert_eq!(version, 1);
+        assert_eq!(changelog.schema_version, SCHEMA_VERSION);
+        assert_eq!(changelog.source_tag, None);
+        let sample = ChangeLog::sample();
+        let (repo, expected) = sample.changes.first_key_value().unwrap();
+        assert!(!expected.diffstat_by_category.is_empty());
+        assert_eq!(
+            changelog.changes[repo].diffstat_by_category,
+            expected.diffstat_by_category
+        );
+    }
+
+    #[test]
+    fn test_read_v2() {
+        let mut json = serde_json::to_value(ChangeLog::sample()).unwrap();
+        json["schema_version"] = json!(2);
+        for change in changes_mut(json.as_object_mut().unwrap()) {
+            change.remove("author_name");
+            change.remove("author_email");
+        }
+        let changelog = from_value(json).unwrap();
+        let change = &changelog.log[0];
+        assert_eq!(change.author_name, change.author.name);
+        assert_eq!(change.author_email, change.author.email);
+    }
+
+    #[test]
+    fn test_read_current() {
+        let sample = ChangeLog::sample();
+        let json = serde_json::to_value(&sample).unwrap();
+        assert_eq!(json["schema_version"], json!(SCHEMA_VERSION));
+        let changelog = from_value(json.clone()).unwrap();
+        assert_eq!(serde_json::to_value(&changelog).unwrap(), json);
+    }
+
+    #[test]
+    fn test_read_newer_version() {
+        let json = json!({ "schema_version": SCHEMA_VERSION + 1 });
+        assert!(matches!(
+            from_value(json),
+            Err(SchemaError::UnsupportedVersion { .. })
+        ));
+        assert!(matches!(
+            from_value(json!({ "schema_version": "1" })),
+            Err(SchemaError::InvalidVersion { .. })
+        ));
+    }
+
+    #[test]
+    fn test_published_schema_is_up_to_date() {
+        let schema = serde_json::to_string_pretty(&json_schema()).unwrap() + "\n";
+        assert_eq!(
+            schema,
+            include_str!("../changelog.schema.json"),
+            "run `changelog-generator schema --output changelog.schema.json` to update it"
+        );
+    }
+}
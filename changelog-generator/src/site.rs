@@ -0,0 +1,386 @@
+//! A static site of the [`History`] of a device: an index of releases, a page per release,
+//! a page per repo, a contributors page and a search index, rendered by the `site/*` templates.
+
+use std::collections::BTreeMap;
+
+use arcstr::ArcStr;
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+
+use crate::{
+    changelog::{Change, ChangeLog},
+    history::{Anchors, History, HistoryOrder, Release},
+    mailmap::Identity,
+    template::Meta,
+};
+
+/// The search index loaded by the pages
+pub const SEARCH_INDEX_FILE: &str = "search-index.json";
+
+/// The pages of a site, built from the releases of a device
+#[derive(Debug, Clone)]
+pub struct Site {
+    pub releases: Vec<Release>,
+    pub summary: SiteSummary,
+    pub repos: Vec<RepoHistory>,
+    pub contributors: Vec<SiteContributor>,
+}
+
+/// What every page could see for navigation
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct SiteSummary {
+    /// The newest first
+    pub releases: Vec<ReleaseSummary>,
+    /// Repos with changes, by name
+    pub repos: Vec<RepoSummary>,
+    pub contributors: usize,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ReleaseSummary {
+    pub tag: String,
+    /// The page of the release, relative to the root of the site
+    pub url: String,
+    pub date: Option<DateTime<Utc>>,
+    pub previous_tag: Option<String>,
+    pub changes: usize,
+    pub added_repos: usize,
+    pub removed_repos: usize,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct RepoSummary {
+    pub repo: ArcStr,
+    /// The page of the repo, relative to the root of the site
+    pub url: String,
+    pub changes: usize,
+}
+
+/// The changes of a repo in every release, the newest first
+#[derive(Debug, Clone, Serialize)]
+pub struct RepoHistory {
+    pub repo: ArcStr,
+    pub url: String,
+    pub releases: Vec<RepoRelease>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct RepoRelease {
+    pub tag: String,
+    /// The page of the release, relative to the root of the site
+    pub url: String,
+    pub changes: Vec<Change>,
+}
+
+/// An author of any release
+#[derive(Debug, Clone, Serialize)]
+pub struct SiteContributor {
+    #[serde(flatten)]
+    pub identity: Identity,
+    /// The number of changes in all the releases
+    pub changes: usize,
+    /// The number of releases with changes of the author
+    pub releases: usize,
+    /// The oldest release with changes of the author
+    pub first_release: String,
+    pub first_release_url: String,
+}
+
+/// A change in [`SEARCH_INDEX_FILE`]
+#[derive(Debug, Clone, Serialize)]
+pub struct SearchEntry {
+    pub title: String,
+    pub repo: ArcStr,
+    pub author: ArcStr,
+    pub commit: String,
+    pub tag: String,
+    /// The page of the release, relative to the root of the site
+    pub url: String,
+}
+
+/// A page to render: the template and what it sees besides [`SiteSummary`]
+#[derive(Debug, Clone, Serialize)]
+pub struct SitePage<'a> {
+    /// The file of the page, relative to the root of the site
+    pub path: String,
+    #[serde(skip)]
+    pub template: &'static str,
+    /// The relative path from the page to the root of the site, e.g. `../`
+    pub root: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub release: Option<&'a Release>,
+    /// The newer and the older release of a release page
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub newer: Option<&'a ReleaseSummary>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub older: Option<&'a ReleaseSummary>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub repo: Option<&'a RepoHistory>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub contributors: Option<&'a [SiteContributor]>,
+}
+
+/// What site templates could see
+#[derive(Debug, Serialize)]
+pub struct SiteContext<'a> {
+    pub meta: Meta,
+    pub site: &'a SiteSummary,
+    pub page: SitePage<'a>,
+    /// User defined variables from `--vars-file` and `--var`
+    pub vars: serde_json::Map<String, serde_json::Value>,
+}
+
+impl SiteContext<'_> {
+    /// A serialized context built from [`ChangeLog::sample`] with every page field filled in,
+    /// and the `title` that pages pass to `site/layout`, for checking templates
+    pub(crate) fn sample() -> serde_json::Value {
+        let history = History::new(
+            vec![
+                Release::new("build-2".to_string(), ChangeLog::sample()),
+                Release::without_source("build-1".to_string(), ChangeLog::sample()),
+            ],
+            HistoryOrder::Tag,
+        );
+        let site = Site::new(history);
+        let context = SiteContext {
+            meta: Meta::default(),
+            site: &site.summary,
+            page: SitePage {
+                path: "releases/build-2.html".to_string(),
+                template: "site/release",
+                root: "../",
+                release: site.releases.first(),
+                newer: site.summary.releases.first(),
+                older: site.summary.releases.get(1),
+                repo: site.repos.first(),
+                contributors: Some(&site.contributors),
+            },
+            vars: Default::default(),
+        };
+        let mut context =
+            serde_json::to_value(context).expect("the site context is always serializable");
+        context["title"] = "build-2".into();
+        context
+    }
+}
+
+impl Site {
+    pub fn new(history: History) -> Self {
+        let releases = history.releases;
+        let release_url = |release: &Release| format!("releases/{}.html", release.anchor);
+
+        let mut by_repo: BTreeMap<ArcStr, Vec<RepoRelease>> = BTreeMap::new();
+        for release in &releases {
+            for (repo, log) in &release.changelog.changes {
+                if !log.logs.is_empty() {
+                    by_repo.entry(repo.clone()).or_default().push(RepoRelease {
+                        tag: release.tag.clone(),
+                        url: release_url(release),
+                        changes: log.logs.clone(),
+                    });
+                }
+            }
+        }
+        let mut anchors = Anchors::default();
+        let repos: Vec<RepoHistory> = by_repo
+            .into_iter()
+            .map(|(repo, releases)| RepoHistory {
+                url: format!("repos/{}.html", anchors.unique(&repo)),
+                repo,
+                releases,
+            })
+            .collect();
+
+        // From the oldest release, so that the first one seen is the first release
+        let mut contributors: BTreeMap<&Identity, SiteContributor> = BTreeMap::new();
+        for release in releases.iter().rev() {
+            for author in &release.changelog.contributors.authors {
+                let contributor =
+                    contributors
+                        .entry(&author.identity)
+                        .or_insert_with(|| SiteContributor {
+                            identity: author.identity.clone(),
+                            changes: 0,
+                            releases: 0,
+                            first_release: release.tag.clone(),
+                            first_release_url: release_url(release),
+                        });
+                contributor.changes += author.changes;
+                contributor.releases += 1;
+            }
+        }
+        let mut contributors: Vec<SiteContributor> = contributors.into_values().collect();
+        contributors.sort_by(|a, b| {
+            b.changes
+                .cmp(&a.changes)
+                .then_with(|| a.identity.cmp(&b.identity))
+        });
+
+        let summary = SiteSummary {
+            releases: releases
+                .iter()
+                .map(|release| ReleaseSummary {
+                    tag: release.tag.clone(),
+                    url: release_url(release),
+                    date: release.date,
+                    previous_tag: release.previous_tag.clone(),
+                    changes: release.changelog.log.len(),
+                    added_repos: release.changelog.added_repos.len(),
+                    removed_repos: release.changelog.removed_repos.len(),
+                })
+                .collect(),
+            repos: repos
+                .iter()
+                .map(|repo| RepoSummary {
+                    repo: repo.repo.clone(),
+                    url: repo.url.clone(),
+                    changes: repo.releases.iter().map(|r| r.changes.len()).sum(),
+                })
+                .collect(),
+            contributors: contributors.len(),
+        };
+        Self {
+            releases,
+            summary,
+            repos,
+            contributors,
+        }
+    }
+
+    /// Every page of the site
+    pub fn pages(&self) -> Vec<SitePage<'_>> {
+        let page = |path: String, template, root| SitePage {
+            path,
+            template,
+            root,
+            release: None,
+            newer: None,
+            older: None,
+            repo: None,
+            contributors: None,
+        };
+        let mut pages = vec![
+            page("index.html".to_string(), "site/index", ""),
+            SitePage {
+                contributors: Some(&self.contributors),
+                ..page("contributors.html".to_string(), "site/contributors", "")
+            },
+        ];
+        let summaries = &self.summary.releases;
+        for (i, (release, summary)) in self.releases.iter().zip(summaries).enumerate() {
+            pages.push(SitePage {
+                release: Some(release),
+                newer: i.checked_sub(1).map(|j| &summaries[j]),
+                older: summaries.get(i + 1),
+                ..page(summary.url.clone(), "site/release", "../")
+            });
+        }
+        for repo in &self.repos {
+            pages.push(SitePage {
+                repo: Some(repo),
+                ..page(repo.url.clone(), "site/repo", "../")
+            });
+        }
+        pages
+    }
+
+    /// Every change of every release, for searching in the browser
+    pub fn search_index(&self) -> Vec<SearchEntry> {
+        self.releases
+            .iter()
+            .zip(&self.summary.releases)
+            .flat_map(|(release, summary)| {
+                release.changelog.log.iter().map(|change| SearchEntry {
+                    title: change.title.to_string(),
+                    repo: change.repo.clone(),
+                    author: change.author.canonical.name.clone(),
+                    commit: change
+                        .commit
+                        .get(..12)
+                        .unwrap_or(&change.commit)
+                        .to_string(),
+                    tag: release.tag.clone(),
+                    url: summary.url.clone(),
+                })
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::template::Renderer;
+
+    fn site() -> Site {
+        let history = History::new(
+            vec![
+                Release::new("build-1".to_string(), ChangeLog::sample()),
+                Release::new("build-2".to_string(), ChangeLog::sample()),
+                Release::new("build-3".to_string(), ChangeLog::default()),
+            ],
+            HistoryOrder::Tag,
+        );
+        Site::new(history)
+    }
+
+    #[test]
+    fn test_site() {
+        let site = site();
+        let sample = ChangeLog::sample();
+        let tags: Vec<_> = site.summary.releases.iter().map(|r| &r.tag).collect();
+        assert_eq!(tags, ["build-3", "build-2", "build-1"]);
+        assert_eq!(site.summary.releases[2].url, "releases/build-1.html");
+
+        assert_eq!(site.repos.len(), sample.changes.len());
+        let repo = &site.repos[0];
+        assert_eq!(repo.url, format!("repos/{}.html", anchor_of(&repo.repo)));
+        let tags: Vec<_> = repo.releases.iter().map(|r| r.tag.as_str()).collect();
+        assert_eq!(tags, ["build-2", "build-1"]);
+
+        let author = &sample.contributors.authors[0];
+        let contributor = site
+            .contributors
+            .iter()
+            .find(|c| c.identity == author.identity)
+            .unwrap();
+        assert_eq!(contributor.changes, author.changes * 2);
+        assert_eq!(contributor.releases, 2);
+        assert_eq!(contributor.first_release, "build-1");
+
+        assert_eq!(site.search_index().len(), sample.log.len() * 2);
+        // The index, the contributors, 3 releases and the repos
+        assert_eq!(site.pages().len(), 2 + 3 + site.repos.len());
+    }
+
+    fn anchor_of(repo: &str) -> String {
+        Anchors::default().unique(repo)
+    }
+
+    #[test]
+    fn test_render_builtin_site() {
+        let site = site();
+        let renderer = Renderer::new().unwrap();
+        for page in site.pages() {
+            let (path, template) = (page.path.clone(), page.template);
+            let context = SiteContext {
+                meta: Meta::default(),
+                site: &site.summary,
+                page,
+                vars: Default::default(),
+            };
+            let html = renderer
+                .render(template, &context)
+                .unwrap_or_else(|e| panic!("failed to render {path}: {e}"));
+            assert!(html.contains(r#"<nav class="site-nav">"#), "{path}");
+            if path.starts_with("releases/") || path.starts_with("repos/") {
+                assert!(html.contains(r#"href="../index.html""#), "{path}");
+            }
+            if path == "releases/build-2.html" {
+                assert!(html.contains(r#"href="../releases/build-3.html""#));
+                assert!(html.contains(r#"href="../releases/build-1.html""#));
+                assert!(html.contains(&ChangeLog::sample().log[0].title.to_string()));
+            }
+        }
+    }
+}
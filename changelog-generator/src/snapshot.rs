@@ -1,10 +1,12 @@
 //! [`Snapshot`] captures the status of the entire tree,
 //! which could be compared with each other.
 
-use std::collections::BTreeMap;
+use std::{borrow::Cow, collections::BTreeMap, fmt};
 
 use arcstr::ArcStr;
 use nutype::nutype;
+use schemars::{JsonSchema, Schema, SchemaGenerator, json_schema};
+use serde::Serialize;
 use snafu::{ResultExt, Snafu};
 
 #[derive(Debug, Clone)]
@@ -19,12 +21,48 @@ pub struct RepoStatus {
 
 #[nutype(
     derive(
-        Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Display, AsRef, Serialize
+        Debug,
+        Clone,
+        PartialEq,
+        Eq,
+        PartialOrd,
+        Ord,
+        Display,
+        AsRef,
+        Serialize,
+        Deserialize
     ),
     validate(not_empty, regex = "[0-9a-f]{8, 40}")
 )]
 pub struct CommitHash(String);
 
+impl JsonSchema for CommitHash {
+    fn schema_name() -> Cow<'static, str> {
+        Cow::Borrowed("CommitHash")
+    }
+
+    fn json_schema(_: &mut SchemaGenerator) -> Schema {
+        json_schema!({
+            "type": "string",
+            "pattern": "[0-9a-f]{8,40}",
+        })
+    }
+}
+
+/// The structural difference between two [`Snapshot`]s, see [`Snapshot::diff`]
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct SnapshotDiff {
+    pub added: BTreeMap<ArcStr, CommitHash>,
+    pub removed: BTreeMap<ArcStr, CommitHash>,
+    pub changed: BTreeMap<ArcStr, ChangedRepo>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ChangedRepo {
+    pub from: CommitHash,
+    pub to: CommitHash,
+}
+
 type Result<T, E = SnapshotError> = std::result::Result<T, E>;
 
 #[derive(Debug, Clone, Snafu)]
@@ -75,6 +113,57 @@ impl Snapshot {
         }
         Ok(Self { repos })
     }
+
+    /// Compare the repos and commits with a newer snapshot, without looking into git.
+    pub fn diff(&self, target: &Snapshot) -> SnapshotDiff {
+        let mut diff = SnapshotDiff::default();
+        for (repo, status) in &self.repos {
+            match target.repos.get(repo) {
+                None => {
+                    diff.removed.insert(repo.clone(), status.commit.clone());
+                }
+                Some(new) if new.commit != status.commit => {
+                    let changed = ChangedRepo {
+                        from: status.commit.clone(),
+                        to: new.commit.clone(),
+                    };
+                    diff.changed.insert(repo.clone(), changed);
+                }
+                Some(_) => {}
+            }
+        }
+        for (repo, status) in &target.repos {
+            if !self.repos.contains_key(repo) {
+                diff.added.insert(repo.clone(), status.commit.clone());
+            }
+        }
+        diff
+    }
+}
+
+/// Formats in the same format accepted by [`Snapshot::parse`]
+impl fmt::Display for Snapshot {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (repo, status) in &self.repos {
+            writeln!(f, "{repo}: {}", status.commit)?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for SnapshotDiff {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (repo, commit) in &self.added {
+            writeln!(f, "+ {repo}: {commit}")?;
+        }
+        for (repo, commit) in &self.removed {
+            writeln!(f, "- {repo}: {commit}")?;
+        }
+        for (repo, ChangedRepo { from, to }) in &self.changed {
+            writeln!(f, "~ {repo}: {from}..{to}")?;
+        }
+        Ok(())
+    }
 }
 
 #[cfg(test)]
@@ -133,6 +222,35 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_display_roundtrip() {
+        let input = "external/lib: abcd1234ef567890\nsystem/core: 413223ae32d8f\n";
+        let snapshot = Snapshot::parse(input.to_string()).unwrap();
+        assert_eq!(snapshot.to_string(), input);
+    }
+
+    #[test]
+    fn test_diff() {
+        let orig = Snapshot::parse(
+            "system/core: 413223ae32d8f\nexternal/lib: abcd1234ef567890\nvendor/foo: 12345678"
+                .to_string(),
+        )
+        .unwrap();
+        let target = Snapshot::parse(
+            "system/core: 413223ae32d8f\nexternal/lib: 0123456789ab\ndevice/bar: 87654321"
+                .to_string(),
+        )
+        .unwrap();
+        let diff = orig.diff(&target);
+        assert_eq!(diff.added.keys().collect::<Vec<_>>(), ["device/bar"]);
+        assert_eq!(diff.removed.keys().collect::<Vec<_>>(), ["vendor/foo"]);
+        assert_eq!(diff.changed["external/lib"].to.to_string(), "0123456789ab");
+        assert_eq!(
+            diff.to_string(),
+            "+ device/bar: 87654321\n- vendor/foo: 12345678\n~ external/lib: abcd1234ef567890..0123456789ab\n"
+        );
+    }
+
     #[test]
     fn test_parse_trims_whitespace() {
         let input = " system/core :  413223ae32d8f  ".to_string();
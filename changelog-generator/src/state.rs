@@ -0,0 +1,176 @@
+//! The state repo, which keeps the snapshot and the changelogs of every build
+//!
+//! ```text
+//! state/
+//! ├── changelog.toml        the config shared by all devices, optional
+//! └── <device>/
+//!     ├── changelog.toml    the config of the device, optional
+//!     ├── last_build        the tag of the previous build
+//!     └── <build-tag>/
+//!         ├── snapshot
+//!         └── changelog.json and other outputs
+//! ```
+
+use std::{io, path::PathBuf};
+
+use snafu::{ResultExt, Snafu, ensure};
+
+use crate::{
+    config::CONFIG_FILE,
+    snapshot::{Snapshot, SnapshotError},
+};
+
+/// The file pointing to the tag of the previous build of a device
+pub const LAST_BUILD_FILE: &str = "last_build";
+/// The snapshot in the directory of a build
+pub const SNAPSHOT_FILE: &str = "snapshot";
+
+#[derive(Debug, Snafu)]
+pub enum StateError {
+    #[snafu(display("{name:?} is not a valid {what}, which should be a single path component"))]
+    InvalidName { what: &'static str, name: String },
+    #[snafu(display("the state of device {device:?} does not exist: {dir:?} is not a directory"))]
+    MissingDevice { device: String, dir: PathBuf },
+    #[snafu(display("build {tag:?} does not exist: {dir:?} is not a directory"))]
+    MissingBuild { tag: String, dir: PathBuf },
+    #[snafu(display("build {tag:?} has no snapshot at {path:?}"))]
+    MissingSnapshot { tag: String, path: PathBuf },
+    #[snafu(display("failed to read {path:?}"))]
+    Read { path: PathBuf, source: io::Error },
+    #[snafu(display("invalid snapshot {path:?}"))]
+    InvalidSnapshot {
+        path: PathBuf,
+        source: SnapshotError,
+    },
+}
+
+pub type Result<T, E = StateError> = std::result::Result<T, E>;
+
+/// The builds of a device in the state repo, i.e. `state/<device>`
+#[derive(Debug, Clone)]
+pub struct DeviceState {
+    pub state: PathBuf,
+    pub device: String,
+    pub dir: PathBuf,
+}
+
+/// Device triples and build tags are directory names
+fn check_name(what: &'static str, name: &str) -> Result<()> {
+    ensure!(
+        !name.is_empty() && name != "." && name != ".." && !name.contains('/'),
+        InvalidNameSnafu { what, name }
+    );
+    Ok(())
+}
+
+impl DeviceState {
+    /// Open the state of a device, which should exist
+    pub fn open(state: impl Into<PathBuf>, device: &str) -> Result<Self> {
+        check_name("device triple", device)?;
+        let state = state.into();
+        let dir = state.join(device);
+        ensure!(dir.is_dir(), MissingDeviceSnafu { device, dir });
+        Ok(Self {
+            state,
+            device: device.to_string(),
+            dir,
+        })
+    }
+
+    /// Find the config of the device, falling back to the one shared by all devices
+    pub fn find_config(&self) -> Option<PathBuf> {
+        [self.dir.join(CONFIG_FILE), self.state.join(CONFIG_FILE)]
+            .into_iter()
+            .find(|path| path.is_file())
+    }
+
+    /// The tag in `last_build`, or `None` without a previous build
+    pub fn last_build(&self) -> Result<Option<String>> {
+        let path = self.dir.join(LAST_BUILD_FILE);
+        match std::fs::read_to_string(&path) {
+            Ok(content) => Ok(Some(content.trim().to_string()).filter(|tag| !tag.is_empty())),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(source) => Err(StateError::Read { path, source }),
+        }
+    }
+
+    /// The directory of a build, which should exist
+    pub fn build_dir(&self, tag: &str) -> Result<PathBuf> {
+        check_name("build tag", tag)?;
+        let dir = self.dir.join(tag);
+        ensure!(dir.is_dir(), MissingBuildSnafu { tag, dir });
+        Ok(dir)
+    }
+
+    /// The tags of all the builds, in no particular order
+    pub fn builds(&self) -> Result<Vec<String>> {
+        let entries = std::fs::read_dir(&self.dir).context(ReadSnafu { path: &self.dir })?;
+        let mut tags = Vec::new();
+        for entry in entries {
+            let entry = entry.context(ReadSnafu { path: &self.dir })?;
+            // Skip hidden directories like .git, and names that are not build tags
+            if let Ok(tag) = entry.file_name().into_string()
+                && !tag.starts_with('.')
+                && entry.path().is_dir()
+            {
+                tags.push(tag);
+            }
+        }
+        Ok(tags)
+    }
+
+    /// Read the snapshot of a build
+    pub fn snapshot(&self, tag: &str) -> Result<Snapshot> {
+        let path = self.build_dir(tag)?.join(SNAPSHOT_FILE);
+        ensure!(path.is_file(), MissingSnapshotSnafu { tag, path: &path });
+        let content = std::fs::read_to_string(&path).context(ReadSnafu { path: &path })?;
+        Snapshot::parse(content).context(InvalidSnapshotSnafu { path })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_device_state() {
+        let state = std::env::temp_dir().join(format!("changelog-state-{}", std::process::id()));
+        let dir = state.join("raven-bp2a-userdebug");
+        std::fs::create_dir_all(dir.join("20261001")).unwrap();
+        std::fs::create_dir_all(dir.join("20261019")).unwrap();
+        std::fs::write(dir.join("20261019/snapshot"), "system/core: 59dd8782b851\n").unwrap();
+        std::fs::write(state.join(CONFIG_FILE), "").unwrap();
+
+        assert!(matches!(
+            DeviceState::open(&state, "husky-bp2a-userdebug"),
+            Err(StateError::MissingDevice { .. })
+        ));
+        assert!(matches!(
+            DeviceState::open(&state, ".."),
+            Err(StateError::InvalidName { .. })
+        ));
+        let device = DeviceState::open(&state, "raven-bp2a-userdebug").unwrap();
+        assert_eq!(device.find_config(), Some(state.join(CONFIG_FILE)));
+        assert_eq!(device.last_build().unwrap(), None);
+        std::fs::write(dir.join(LAST_BUILD_FILE), "20261001\n").unwrap();
+        assert_eq!(device.last_build().unwrap().as_deref(), Some("20261001"));
+
+        let mut builds = device.builds().unwrap();
+        builds.sort();
+        assert_eq!(builds, ["20261001", "20261019"]);
+        assert_eq!(device.snapshot("20261019").unwrap().repos.len(), 1);
+        let missing_snapshot = device.snapshot("20261001");
+        let missing_build = device.snapshot("20261002");
+        let invalid_tag = device.build_dir("../20261001");
+        std::fs::remove_dir_all(&state).unwrap();
+        assert!(matches!(
+            missing_snapshot,
+            Err(StateError::MissingSnapshot { .. })
+        ));
+        assert!(matches!(
+            missing_build,
+            Err(StateError::MissingBuild { .. })
+        ));
+        assert!(matches!(invalid_tag, Err(StateError::InvalidName { .. })));
+    }
+}
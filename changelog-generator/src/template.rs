@@ -1,41 +1,107 @@
 //! Format the change log into various formats using handlebars
 
+mod check;
+mod collection;
+mod commit_body;
+mod datetime;
+mod escape;
+mod string;
+
+use std::{collections::BTreeSet, path::Path};
+
+use chrono::{DateTime, Locale, Utc};
+use chrono_tz::Tz;
 use handlebars::{
-    Context, Handlebars, Helper, HelperResult, JsonRender, Output, RenderContext, RenderError,
-    RenderErrorReason,
+    Context, DirectorySourceOptions, Handlebars, Helper, HelperDef, HelperResult, Output,
+    RenderContext, RenderError, RenderErrorReason, TemplateError,
 };
+use serde::Serialize;
 
-use crate::changelog::ChangeLog;
+pub use self::{check::Diagnostic, datetime::parse_locale, escape::EscapeMode};
+use crate::{changelog::ChangeLog, history::HistoryContext, site::SiteContext};
 
-/// Escape content to be used in a markdown link description.
+/// Templates compiled into the binary, which could be selected with `builtin:<name>`.
 ///
-/// Note: this function is not security oriented. We assume that
-///       some input might already be markdown. For example, some
-///       may use `code` in commit title.
-fn md_link_desc_escape(
-    h: &Helper,
-    bar: &Handlebars,
-    _: &Context,
-    rc: &mut RenderContext,
-    out: &mut dyn Output,
-) -> HelperResult {
-    let param = h
-        .param(0)
-        .ok_or_else(|| RenderErrorReason::ParamNotFoundForIndex("md_link_desc_escape", 0))?;
-    let mut s = param.value().render();
-    s = s.replace("[", "\\[");
-    s = s.replace("]", "\\]");
-    s = s.replace("\n", " ");
-    let escaped = bar.get_escape_fn()(&s);
-    out.write(&escaped)?;
-    Ok(())
-}
+/// Every template is also a partial, e.g. `markdown` renders each change with
+/// `{{> markdown/change}}`, which could be overridden by a template directory.
+/// `markdown/history` and `html/history` render a page of [`crate::history::History`] instead,
+/// and `site/*` render the pages of a [`crate::site::Site`].
+pub const BUILTIN_TEMPLATES: &[(&str, &str)] = &[
+    ("markdown", include_str!("../templates/markdown.handlebars")),
+    (
+        "markdown/change",
+        include_str!("../templates/markdown/change.handlebars"),
+    ),
+    (
+        "markdown/hidden-reverts",
+        include_str!("../templates/markdown/hidden-reverts.handlebars"),
+    ),
+    (
+        "markdown/contributors",
+        include_str!("../templates/markdown/contributors.handlebars"),
+    ),
+    (
+        "markdown/history",
+        include_str!("../templates/markdown/history.handlebars"),
+    ),
+    ("html", include_str!("../templates/html.handlebars")),
+    (
+        "html/change",
+        include_str!("../templates/html/change.handlebars"),
+    ),
+    (
+        "html/contributors",
+        include_str!("../templates/html/contributors.handlebars"),
+    ),
+    (
+        "html/header",
+        include_str!("../templates/html/header.handlebars"),
+    ),
+    (
+        "html/layout",
+        include_str!("../templates/html/layout.handlebars"),
+    ),
+    (
+        "html/history",
+        include_str!("../templates/html/history.handlebars"),
+    ),
+    (
+        "html-page",
+        include_str!("../templates/html-page.handlebars"),
+    ),
+    ("rss", include_str!("../templates/rss.handlebars")),
+    (
+        "site/layout",
+        include_str!("../templates/site/layout.handlebars"),
+    ),
+    (
+        "site/index",
+        include_str!("../templates/site/index.handlebars"),
+    ),
+    (
+        "site/release",
+        include_str!("../templates/site/release.handlebars"),
+    ),
+    (
+        "site/repo",
+        include_str!("../templates/site/repo.handlebars"),
+    ),
+    (
+        "site/contributors",
+        include_str!("../templates/site/contributors.handlebars"),
+    ),
+    ("text", include_str!("../templates/text.handlebars")),
+    (
+        "text/change",
+        include_str!("../templates/text/change.handlebars"),
+    ),
+];
 
 fn indent(
     h: &Helper,
     _: &Handlebars,
     _: &Context,
-    rc: &mut RenderContext,
+    _: &mut RenderContext,
     out: &mut dyn Output,
 ) -> HelperResult {
     let n = h
@@ -53,58 +119,368 @@ fn indent(
     let whitespace = " ".repeat(n as usize);
     for line in content.lines() {
         out.write(&whitespace)?;
-        out.write(&line)?;
+        out.write(line)?;
         out.write("\n")?;
     }
     Ok(())
 }
 
-fn slice(
-    h: &Helper,
-    _: &Handlebars,
-    _: &Context,
-    rc: &mut RenderContext,
-    out: &mut dyn Output,
-) -> HelperResult {
-    let start = h
-        .param(0)
-        .ok_or_else(|| RenderErrorReason::ParamNotFoundForIndex("slice", 0))?
-        .try_get_constant_value()
-        .ok_or_else(|| RenderErrorReason::InvalidParamType("expected an index (integer)"))?
-        .as_u64()
-        .ok_or_else(|| RenderErrorReason::InvalidParamType("expected an index (integer)"))?
-        as usize;
-    let max_len = h
-        .param(1)
-        .ok_or_else(|| RenderErrorReason::ParamNotFoundForIndex("slice", 1))?
-        .try_get_constant_value()
-        .ok_or_else(|| RenderErrorReason::InvalidParamType("expected length (integer)"))?
-        .as_u64()
-        .ok_or_else(|| RenderErrorReason::InvalidParamType("expected length (integer)"))?
-        as usize;
-    let content = h
-        .param(2)
-        .ok_or_else(|| RenderErrorReason::ParamNotFoundForIndex("slice", 2))?
-        .render();
-    if content.is_empty() {
-        return Ok(());
-    }
-    if start >= content.len() {
-        return Err(RenderErrorReason::Other(format!(
-            "index {start} is out of range {}",
-            content.len()
-        ))
-        .into());
-    }
-    let end = (start + max_len).clamp(0, content.len().saturating_sub(1));
-    out.write(&content[start..end])?;
-    Ok(())
+/// Helpers by name
+type Helpers = Vec<(&'static str, Box<dyn HelperDef + Send + Sync>)>;
+
+/// A set of compiled templates and partials, which could render several outputs.
+pub struct Renderer {
+    registry: Handlebars<'static>,
+    /// Names of the helpers we registered
+    helpers: BTreeSet<&'static str>,
+    /// The defaults of date helpers
+    timezone: Tz,
+    locale: Option<Locale>,
+}
+
+impl Renderer {
+    /// Create a renderer with the built-in templates and helpers
+    pub fn new() -> Result<Self, TemplateError> {
+        let mut renderer = Self {
+            registry: Handlebars::new(),
+            helpers: BTreeSet::new(),
+            timezone: Tz::UTC,
+            locale: None,
+        };
+        renderer.register_helpers(vec![("indent", Box::new(indent))]);
+        renderer.register_helpers(string::helpers());
+        renderer.register_helpers(collection::helpers(renderer.timezone));
+        renderer.register_helpers(escape::helpers());
+        renderer.register_helpers(commit_body::helpers());
+        renderer.register_helpers(datetime::helpers(renderer.timezone, renderer.locale));
+        for (name, builtin) in BUILTIN_TEMPLATES {
+            renderer.registry.register_template_string(name, builtin)?;
+        }
+        Ok(renderer)
+    }
+
+    fn register_helpers(&mut self, helpers: Helpers) {
+        for (name, helper) in helpers {
+            self.registry.register_helper(name, helper);
+            self.helpers.insert(name);
+        }
+    }
+
+    /// Set the default timezone of date helpers and of `group_by` by `day`
+    pub fn set_timezone(&mut self, timezone: Tz) {
+        self.timezone = timezone;
+        self.register_helpers(datetime::helpers(self.timezone, self.locale));
+        self.register_helpers(collection::helpers(self.timezone));
+    }
+
+    /// Set the default locale of date helpers in the following renders, English if `None`
+    pub fn set_locale(&mut self, locale: Option<Locale>) {
+        self.locale = locale;
+        self.register_helpers(datetime::helpers(self.timezone, self.locale));
+    }
+
+    /// Set how `{{ expressions }}` are escaped in the following renders
+    pub fn set_escape(&mut self, mode: EscapeMode) {
+        self.registry.register_escape_fn(mode.escape_fn());
+    }
+
+    /// Load all `*.handlebars` templates in a directory.
+    ///
+    /// A template is named by its path relative to the directory without the extension,
+    /// e.g. `markdown/change.handlebars` is named `markdown/change`, overriding the
+    /// built-in partial of the same name.
+    pub fn load_dir(&mut self, dir: impl AsRef<Path>) -> Result<(), TemplateError> {
+        let mut options = DirectorySourceOptions::default();
+        options.tpl_extension = ".handlebars".to_string();
+        self.registry.register_templates_directory(dir, options)
+    }
+
+    /// Register a template, which could also be used as a partial.
+    pub fn register(&mut self, name: &str, template: &str) -> Result<(), TemplateError> {
+        self.registry.register_template_string(name, template)
+    }
+
+    /// Report unknown helpers, partials and fields used by a template and its partials,
+    /// when rendered with the context of `kind`
+    pub fn check(&self, name: &str, kind: ContextKind) -> Vec<Diagnostic> {
+        check::check(&self.registry, &self.helpers, name, &kind.sample())
+    }
+
+    pub fn has_template(&self, name: &str) -> bool {
+        self.registry.has_template(name)
+    }
+
+    /// Render a template with a [`TemplateContext`], or another context like
+    /// [`HistoryContext`] for the templates made for it
+    pub fn render(&self, name: &str, context: &impl Serialize) -> Result<String, RenderError> {
+        self.registry.render(name, context)
+    }
+}
+
+/// The context a template is rendered with, which [`Renderer::check`] checks it against
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ContextKind {
+    /// [`TemplateContext`]
+    #[default]
+    ChangeLog,
+    /// [`HistoryContext`]
+    History,
+    /// [`SiteContext`], and the `title` passed to `site/layout`
+    Site,
+}
+
+impl ContextKind {
+    /// Guess the context of a template from its name, e.g. `markdown/history` renders a history
+    /// and `site/index` a page of a site
+    pub fn of(name: &str) -> Self {
+        if name.ends_with("/history") {
+            Self::History
+        } else if name.starts_with("site/") {
+            Self::Site
+        } else {
+            Self::ChangeLog
+        }
+    }
+
+    /// A context of this kind built from [`ChangeLog::sample`]
+    fn sample(self) -> serde_json::Value {
+        match self {
+            Self::ChangeLog => serde_json::to_value(TemplateContext::new(&ChangeLog::sample()))
+                .expect("the template context is always serializable"),
+            Self::History => HistoryContext::sample(),
+            Self::Site => SiteContext::sample(),
+        }
+    }
+}
+
+/// What templates could see: the fields of [`ChangeLog`], `meta` and `vars`
+#[derive(Debug, Serialize)]
+pub struct TemplateContext<'a> {
+    #[serde(flatten)]
+    pub changelog: &'a ChangeLog,
+    pub meta: Meta,
+    /// User defined variables from `--vars-file` and `--var`
+    pub vars: serde_json::Map<String, serde_json::Value>,
+}
+
+impl<'a> TemplateContext<'a> {
+    pub fn new(changelog: &'a ChangeLog) -> Self {
+        Self {
+            changelog,
+            meta: Meta::default(),
+            vars: Default::default(),
+        }
+    }
+}
+
+/// Metadata about the release and the generation of the changelog
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct Meta {
+    pub release_tag: Option<String>,
+    /// The device triple, e.g. `raven-bp2a-userdebug`
+    pub device: Option<String>,
+    /// The locale of the output, e.g. `zh-CN`
+    pub locale: Option<String>,
+    pub source: SnapshotMeta,
+    pub target: SnapshotMeta,
+    pub generated_at: DateTime<Utc>,
+    pub tool: ToolMeta,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct SnapshotMeta {
+    /// The identifier of the snapshot, e.g. the tag of the build
+    pub tag: Option<String>,
+    /// The number of repos in the snapshot
+    pub repos: usize,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ToolMeta {
+    pub name: &'static str,
+    pub version: &'static str,
 }
 
-pub fn format_changelog(template: String, changelog: &ChangeLog) -> Result<String, RenderError> {
-    let mut reg = Handlebars::new();
-    reg.register_helper("md_link_desc_escape", Box::new(md_link_desc_escape));
-    reg.register_helper("indent", Box::new(indent));
-    reg.register_helper("slice", Box::new(slice));
-    reg.render_template(&template, &changelog)
+impl Default for ToolMeta {
+    fn default() -> Self {
+        Self {
+            name: env!("CARGO_PKG_NAME"),
+            version: env!("CARGO_PKG_VERSION"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_builtin_templates_render() {
+        let renderer = Renderer::new().unwrap();
+        let changelog = ChangeLog::default();
+        // Partials like layouts may not be rendered on their own
+        for (name, _) in BUILTIN_TEMPLATES.iter().filter(|(n, _)| !n.contains('/')) {
+            renderer
+                .render(name, &TemplateContext::new(&changelog))
+                .unwrap_or_else(|e| panic!("failed to render built-in template {name}: {e}"));
+        }
+        let sample = ChangeLog::sample();
+        for (name, added, removed) in [
+            (
+                "markdown",
+                "- `system/core` from lineage-23.0",
+                "### Removed repositories",
+            ),
+            (
+                "html",
+                "<code>system/core</code> from",
+                "<h3>Removed repositories</h3>",
+            ),
+            (
+                "text",
+                "+ New repository system/core",
+                "- Removed repository system/core",
+            ),
+        ] {
+            let output = renderer
+                .render(name, &TemplateContext::new(&sample))
+                .unwrap();
+            assert!(output.contains(added), "{name}: {output}");
+            assert!(output.contains(removed), "{name}: {output}");
+        }
+    }
+
+    #[test]
+    fn test_builtin_templates_check() {
+        let renderer = Renderer::new().unwrap();
+        // Partials rendered with a change, like `markdown/change`, are checked where they are used
+        for (name, _) in BUILTIN_TEMPLATES
+            .iter()
+            .filter(|(n, _)| !n.contains('/') || ContextKind::of(n) != ContextKind::ChangeLog)
+        {
+            let kind = ContextKind::of(name);
+            assert_eq!(renderer.check(name, kind), [], "built-in template {name}");
+        }
+        assert_eq!(ContextKind::of("markdown/history"), ContextKind::History);
+        assert_eq!(ContextKind::of("site/layout"), ContextKind::Site);
+        assert_ne!(
+            renderer.check("markdown/history", ContextKind::ChangeLog),
+            []
+        );
+    }
+
+    #[test]
+    fn test_check_reports_unknown_field() {
+        let mut renderer = Renderer::new().unwrap();
+        renderer
+            .register(
+                "t",
+                "{{#each log}}{{> markdown/change}}{{ this.titel }}{{/each}}",
+            )
+            .unwrap();
+        let diagnostics: Vec<_> = renderer
+            .check("t", ContextKind::ChangeLog)
+            .iter()
+            .map(|d| d.to_string())
+            .collect();
+        assert_eq!(
+            diagnostics,
+            [r#"t:1:35: unknown field "this.titel": no field "titel""#]
+        );
+    }
+
+    #[test]
+    fn test_html_page_embeds_html() {
+        let page = Renderer::new()
+            .unwrap()
+            .render("html-page", &TemplateContext::new(&ChangeLog::default()))
+            .unwrap();
+        assert!(page.contains(r#"<article class="changelog">"#));
+        assert!(page.contains("<h1>Changelog</h1>"));
+        assert!(page.contains(env!("CARGO_PKG_VERSION")));
+    }
+
+    #[test]
+    fn test_builtin_change_dates_in_timezone() {
+        let changelog = ChangeLog::sample();
+        let expected = changelog.log[0]
+            .datetime
+            .with_timezone(&Tz::Asia__Shanghai)
+            .format("%Y-%m-%d %H:%M:%S %Z")
+            .to_string();
+        let mut renderer = Renderer::new().unwrap();
+        renderer.set_timezone(Tz::Asia__Shanghai);
+        for name in ["markdown", "html", "text"] {
+            let output = renderer
+                .render(name, &TemplateContext::new(&changelog))
+                .unwrap();
+            assert!(output.contains(&expected), "{name}");
+        }
+    }
+
+    #[test]
+    fn test_html_links_bug_trailers() {
+        let html = Renderer::new()
+            .unwrap()
+            .render("html", &TemplateContext::new(&ChangeLog::sample()))
+            .unwrap();
+        assert!(html.contains(
+            r#"Bug: <a href="https://issuetracker.google.com/issues/123456" rel="nofollow noopener">123456</a>"#
+        ));
+    }
+
+    #[test]
+    fn test_override_builtin_partial() {
+        let mut renderer = Renderer::new().unwrap();
+        renderer
+            .register("html/header", "<h1>My Changelog</h1>")
+            .unwrap();
+        let page = renderer
+            .render("html-page", &TemplateContext::new(&ChangeLog::default()))
+            .unwrap();
+        assert!(page.contains("<h1>My Changelog</h1>"));
+    }
+
+    #[test]
+    fn test_meta_and_vars() {
+        let mut renderer = Renderer::new().unwrap();
+        renderer
+            .register(
+                "header",
+                "{{ meta.release_tag }} for {{ meta.device }} ({{ meta.target.repos }} repos), {{ vars.channel }}",
+            )
+            .unwrap();
+        let changelog = ChangeLog::default();
+        let mut context = TemplateContext::new(&changelog);
+        context.meta.release_tag = Some("20261019".to_string());
+        context.meta.device = Some("raven-bp2a-userdebug".to_string());
+        context.meta.target.repos = 1000;
+        context
+            .vars
+            .insert("channel".to_string(), serde_json::Value::from("nightly"));
+        assert_eq!(
+            renderer.render("header", &context).unwrap(),
+            "20261019 for raven-bp2a-userdebug (1000 repos), nightly"
+        );
+    }
+
+    #[test]
+    fn test_load_dir() {
+        let dir = std::env::temp_dir().join(format!("changelog-templates-{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("html")).unwrap();
+        std::fs::write(dir.join("html/header.handlebars"), "<h1>Overridden</h1>").unwrap();
+        std::fs::write(
+            dir.join("page.handlebars"),
+            "{{#> html/layout}}body{{/html/layout}}",
+        )
+        .unwrap();
+        let mut renderer = Renderer::new().unwrap();
+        renderer.load_dir(&dir).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+        let page = renderer
+            .render("page", &TemplateContext::new(&ChangeLog::default()))
+            .unwrap();
+        assert!(page.contains("<h1>Overridden</h1>"));
+        assert!(page.contains("body"));
+    }
 }
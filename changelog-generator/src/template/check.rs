@@ -0,0 +1,571 @@
+//! Static checks of templates
+//!
+//! Templates are walked together with the shape of the context they are rendered with,
+//! see [`ContextKind`](super::ContextKind), built from
+//! [`ChangeLog::sample`](crate::changelog::ChangeLog::sample), so that misspelled
+//! fields, unknown helpers and missing partials are reported without a real changelog.
+
+use std::{
+    collections::{BTreeSet, HashMap},
+    fmt::Display,
+};
+
+use handlebars::{
+    Handlebars, Path, Template,
+    template::{BlockParam, DecoratorTemplate, HelperTemplate, Parameter, TemplateElement},
+};
+use serde_json::Value;
+
+/// Helpers that come with handlebars
+const HANDLEBARS_HELPERS: &[&str] = &[
+    "if", "unless", "each", "with", "lookup", "raw", "log", "eq", "ne", "gt", "gte", "lt", "lte",
+    "and", "or", "not", "len",
+];
+
+/// Maps keyed by repos or kinds, where any key is valid
+const DYNAMIC_MAPS: &[&str] = &[
+    "added_repos",
+    "removed_repos",
+    "changes",
+    "diffstat_by_kind",
+    "diffstat_by_category",
+    "vars",
+];
+
+/// A problem found in a template
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Diagnostic {
+    pub template: String,
+    pub line: usize,
+    pub column: usize,
+    pub message: String,
+}
+
+impl Display for Diagnostic {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}:{}:{}: {}",
+            self.template, self.line, self.column, self.message
+        )
+    }
+}
+
+/// What a path could resolve to
+#[derive(Debug, Clone, Copy)]
+enum Shape<'a> {
+    Value {
+        value: &'a Value,
+        /// Whether any key is valid, see [`DYNAMIC_MAPS`]
+        dynamic: bool,
+    },
+    /// Unknown, e.g. the result of a helper
+    Any,
+}
+
+impl<'a> Shape<'a> {
+    fn child(self, segment: &str) -> Result<Shape<'a>, String> {
+        let Shape::Value { value, dynamic } = self else {
+            return Ok(Shape::Any);
+        };
+        match value {
+            Value::Object(map) if dynamic => {
+                Ok(map
+                    .values()
+                    .next()
+                    .map_or(Shape::Any, |value| Shape::Value {
+                        value,
+                        dynamic: false,
+                    }))
+            }
+            Value::Object(map) => map
+                .get(segment)
+                .map(|value| Shape::Value {
+                    value,
+                    dynamic: DYNAMIC_MAPS.contains(&segment),
+                })
+                .ok_or_else(|| format!("no field {segment:?}")),
+            Value::Array(items) if segment.parse::<usize>().is_ok() => Ok(Shape::of_first(items)),
+            Value::Null => Ok(Shape::Any),
+            _ => Err(format!("{segment:?} of a {}", kind(value))),
+        }
+    }
+
+    /// The shape of `this` when iterating with `#each`
+    fn element(self) -> Shape<'a> {
+        match self {
+            Shape::Value {
+                value: Value::Array(items),
+                ..
+            } => Shape::of_first(items),
+            Shape::Value {
+                value: Value::Object(map),
+                dynamic: true,
+            } => map
+                .values()
+                .next()
+                .map_or(Shape::Any, |value| Shape::Value {
+                    value,
+                    dynamic: false,
+                }),
+            _ => Shape::Any,
+        }
+    }
+
+    fn of_first(items: &'a [Value]) -> Shape<'a> {
+        items.first().map_or(Shape::Any, |value| Shape::Value {
+            value,
+            dynamic: false,
+        })
+    }
+}
+
+fn kind(value: &Value) -> &'static str {
+    match value {
+        Value::Null => "null",
+        Value::Bool(_) => "boolean",
+        Value::Number(_) => "number",
+        Value::String(_) => "string",
+        Value::Array(_) => "list",
+        Value::Object(_) => "object",
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Scope<'a> {
+    this: Shape<'a>,
+    /// Names bound by block params like `as |change|`
+    params: Vec<(String, Shape<'a>)>,
+}
+
+struct Checker<'a> {
+    registry: &'a Handlebars<'static>,
+    helpers: &'a BTreeSet<&'static str>,
+    root: Shape<'a>,
+    diagnostics: BTreeSet<Diagnostic>,
+    /// Partials being checked, to stop at recursive ones
+    partials: Vec<String>,
+    /// Partials defined with `{{#*inline "name"}}`
+    inline_partials: HashMap<String, &'a Template>,
+}
+
+/// Check a registered template, and the partials it uses, against the shape of `root`
+pub fn check(
+    registry: &Handlebars<'static>,
+    helpers: &BTreeSet<&'static str>,
+    name: &str,
+    root: &Value,
+) -> Vec<Diagnostic> {
+    let root = Shape::Value {
+        value: root,
+        dynamic: false,
+    };
+    let mut checker = Checker {
+        registry,
+        helpers,
+        root,
+        diagnostics: BTreeSet::new(),
+        partials: vec![name.to_string()],
+        inline_partials: HashMap::new(),
+    };
+    match registry.get_template(name) {
+        Some(template) => {
+            let mut scopes = vec![Scope {
+                this: root,
+                params: Vec::new(),
+            }];
+            checker.template(name, template, &mut scopes);
+        }
+        None => checker.report(name, (0, 0), format!("unknown template {name:?}")),
+    }
+    checker.diagnostics.into_iter().collect()
+}
+
+impl<'a> Checker<'a> {
+    fn report(&mut self, template: &str, (line, column): (usize, usize), message: String) {
+        self.diagnostics.insert(Diagnostic {
+            template: template.to_string(),
+            line,
+            column,
+            message,
+        });
+    }
+
+    fn is_helper(&self, name: &str) -> bool {
+        HANDLEBARS_HELPERS.contains(&name) || self.helpers.contains(name)
+    }
+
+    fn template(&mut self, name: &str, template: &'a Template, scopes: &mut Vec<Scope<'a>>) {
+        for (i, element) in template.elements.iter().enumerate() {
+            let at = template.mapping.get(i).map_or((0, 0), |m| (m.0, m.1));
+            match element {
+                TemplateElement::Expression(h) | TemplateElement::HtmlExpression(h) => {
+                    self.expression(name, at, h, scopes)
+                }
+                TemplateElement::HelperBlock(h) => self.block(name, at, h, scopes),
+                TemplateElement::PartialExpression(d) | TemplateElement::PartialBlock(d) => {
+                    self.partial(name, at, d, scopes)
+                }
+                TemplateElement::DecoratorExpression(d) | TemplateElement::DecoratorBlock(d) => {
+                    // Inline partials are checked where they are used
+                    if let (Some("inline"), Some(Parameter::Literal(Value::String(partial)))) =
+                        (d.name.as_name(), d.params.first())
+                    {
+                        if let Some(body) = &d.template {
+                            self.inline_partials.insert(partial.clone(), body);
+                        }
+                    } else if let Some(body) = &d.template {
+                        self.template(name, body, scopes);
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+
+    fn expression(
+        &mut self,
+        name: &str,
+        at: (usize, usize),
+        h: &'a HelperTemplate,
+        scopes: &[Scope<'a>],
+    ) {
+        if h.params.is_empty() && h.hash.is_empty() {
+            match &h.name {
+                Parameter::Name(helper) if self.is_helper(helper) => {}
+                Parameter::Path(path) if self.is_helper(raw(path)) => {}
+                name_param => {
+                    self.param(name, at, name_param, scopes);
+                }
+            }
+            return;
+        }
+        self.helper_call(name, at, &h.name, &h.params, h.hash.values(), scopes);
+    }
+
+    fn block(
+        &mut self,
+        name: &str,
+        at: (usize, usize),
+        h: &'a HelperTemplate,
+        scopes: &mut Vec<Scope<'a>>,
+    ) {
+        self.helper_call(name, at, &h.name, &h.params, h.hash.values(), scopes);
+        let this = scopes.last().map_or(Shape::Any, |s| s.this);
+        let param = h
+            .params
+            .first()
+            .map_or(Shape::Any, |p| self.param(name, at, p, scopes));
+        let (body_this, bound) = match helper_name(&h.name) {
+            Some("each") => (param.element(), vec![param.element(), Shape::Any]),
+            Some("with") => (param, vec![param]),
+            _ => (this, Vec::new()),
+        };
+        let names = match &h.block_param {
+            Some(BlockParam::Single(p)) => vec![p],
+            Some(BlockParam::Pair((a, b))) => vec![a, b],
+            _ => Vec::new(),
+        };
+        let params = names
+            .into_iter()
+            .enumerate()
+            .filter_map(|(i, p)| {
+                let shape = bound.get(i).copied().unwrap_or(Shape::Any);
+                helper_name(p).map(|n| (n.to_string(), shape))
+            })
+            .collect();
+        if let Some(body) = &h.template {
+            scopes.push(Scope {
+                this: body_this,
+                params,
+            });
+            self.template(name, body, scopes);
+            scopes.pop();
+        }
+        if let Some(inverse) = &h.inverse {
+            self.template(name, inverse, scopes);
+        }
+    }
+
+    fn helper_call(
+        &mut self,
+        name: &str,
+        at: (usize, usize),
+        helper: &Parameter,
+        params: &'a [Parameter],
+        hash: impl Iterator<Item = &'a Parameter>,
+        scopes: &[Scope<'a>],
+    ) {
+        if let Some(helper) = helper_name(helper)
+            && !self.is_helper(helper)
+        {
+            self.report(name, at, format!("unknown helper {helper:?}"));
+        }
+        for param in params.iter().chain(hash) {
+            self.param(name, at, param, scopes);
+        }
+    }
+
+    fn param(
+        &mut self,
+        name: &str,
+        at: (usize, usize),
+        param: &'a Parameter,
+        scopes: &[Scope<'a>],
+    ) -> Shape<'a> {
+        match param {
+            Parameter::Path(path) => self.field(name, at, path, scopes),
+            Parameter::Name(field) => match Path::parse(field) {
+                Ok(path) => self.field(name, at, &path, scopes),
+                Err(_) => Shape::Any,
+            },
+            Parameter::Subexpression(sub) => {
+                let params = sub.params().map(Vec::as_slice).unwrap_or_default();
+                let hash = sub.hash().into_iter().flat_map(|h| h.values());
+                match sub.as_element() {
+                    TemplateElement::Expression(h) if params.is_empty() && h.hash.is_empty() => {
+                        self.expression(name, at, h, scopes)
+                    }
+                    TemplateElement::Expression(h) => {
+                        self.helper_call(name, at, &h.name, params, hash, scopes)
+                    }
+                    _ => {}
+                }
+                Shape::Any
+            }
+            _ => Shape::Any,
+        }
+    }
+
+    fn field(
+        &mut self,
+        name: &str,
+        at: (usize, usize),
+        path: &Path,
+        scopes: &[Scope<'a>],
+    ) -> Shape<'a> {
+        match self.resolve(path, scopes) {
+            Ok(shape) => shape,
+            Err(reason) => {
+                self.report(name, at, format!("unknown field {:?}: {reason}", raw(path)));
+                Shape::Any
+            }
+        }
+    }
+
+    fn partial(
+        &mut self,
+        name: &str,
+        at: (usize, usize),
+        d: &'a DecoratorTemplate,
+        scopes: &mut Vec<Scope<'a>>,
+    ) {
+        let partial = match &d.name {
+            Parameter::Name(n) => Some(n.as_str()),
+            Parameter::Path(path) => Some(raw(path)),
+            Parameter::Literal(Value::String(n)) => Some(n.as_str()),
+            _ => None,
+        };
+        let this = match d.params.first() {
+            Some(param) => self.param(name, at, param, scopes),
+            None => scopes.last().map_or(Shape::Any, |s| s.this),
+        };
+        for param in d.hash.values() {
+            self.param(name, at, param, scopes);
+        }
+        // The body is rendered in the current context
+        if let Some(body) = &d.template {
+            self.template(name, body, scopes);
+        }
+        let Some(partial) = partial.filter(|p| *p != "@partial-block") else {
+            return;
+        };
+        let template = match self.inline_partials.get(partial) {
+            Some(template) => Some(*template),
+            None => self.registry.get_template(partial),
+        };
+        let Some(template) = template else {
+            // The body of a partial block is the fallback of a missing partial
+            if d.template.is_none() {
+                self.report(name, at, format!("unknown partial {partial:?}"));
+            }
+            return;
+        };
+        if self.partials.iter().any(|p| p == partial) {
+            return;
+        }
+        // Hash parameters extend the context, which we don't track
+        let this = if d.hash.is_empty() { this } else { Shape::Any };
+        self.partials.push(partial.to_string());
+        let mut partial_scopes = vec![Scope {
+            this,
+            params: Vec::new(),
+        }];
+        self.template(partial, template, &mut partial_scopes);
+        self.partials.pop();
+    }
+
+    fn resolve(&self, path: &Path, scopes: &[Scope<'a>]) -> Result<Shape<'a>, String> {
+        let Path::Relative((_, raw)) = path else {
+            // Local variables like @index
+            return Ok(Shape::Any);
+        };
+        let mut rest = raw.as_str();
+        let mut level = 0;
+        while let Some(r) = rest.strip_prefix("../") {
+            level += 1;
+            rest = r;
+        }
+        let mut segments = segments(rest);
+        let mut shape = if segments.first() == Some(&"@root") {
+            segments.remove(0);
+            self.root
+        } else {
+            let Some(scope) = scopes.len().checked_sub(level + 1).map(|i| &scopes[i]) else {
+                return Ok(Shape::Any);
+            };
+            match segments.first() {
+                Some(&"this" | &".") => {
+                    segments.remove(0);
+                    scope.this
+                }
+                Some(first) => {
+                    let param = scopes[..=scopes.len() - 1 - level]
+                        .iter()
+                        .rev()
+                        .flat_map(|s| s.params.iter())
+                        .find(|(n, _)| n == first);
+                    match param {
+                        Some((_, shape)) => {
+                            segments.remove(0);
+                            *shape
+                        }
+                        None => scope.this,
+                    }
+                }
+                None => scope.this,
+            }
+        };
+        for segment in segments {
+            shape = shape.child(segment)?;
+        }
+        Ok(shape)
+    }
+}
+
+fn raw(path: &Path) -> &str {
+    match path {
+        Path::Relative((_, raw)) | Path::Local((_, _, raw)) => raw,
+    }
+}
+
+fn helper_name(param: &Parameter) -> Option<&str> {
+    match param {
+        Parameter::Name(name) => Some(name),
+        Parameter::Path(path) => Some(raw(path)),
+        _ => None,
+    }
+}
+
+/// Split a path like `changes.[system/core].logs` into segments
+fn segments(path: &str) -> Vec<&str> {
+    let mut segments = Vec::new();
+    let mut rest = path.trim_start_matches("./");
+    while !rest.is_empty() {
+        if let Some(r) = rest.strip_prefix('[') {
+            let end = r.find(']').unwrap_or(r.len());
+            segments.push(&r[..end]);
+            rest = r.get(end + 1..).unwrap_or_default();
+        } else {
+            let end = rest.find(['.', '/']).unwrap_or(rest.len());
+            segments.push(&rest[..end]);
+            rest = &rest[end..];
+        }
+        rest = rest.trim_start_matches(['.', '/']);
+    }
+    segments
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    fn check_template(template: &str) -> Vec<String> {
+        let mut registry = Handlebars::new();
+        registry
+            .register_template_string("partial", "{{ title }}{{ missing }}")
+            .unwrap();
+        registry.register_template_string("t", template).unwrap();
+        let root = json!({
+            "log": [{ "title": "a", "author": { "name": "n" } }],
+            "changes": { "system/core": { "logs": [] } },
+            "meta": { "release_tag": null },
+            "vars": {},
+        });
+        let helpers = BTreeSet::from(["truncate"]);
+        check(&registry, &helpers, "t", &root)
+            .iter()
+            .map(ToString::to_string)
+            .collect()
+    }
+
+    #[test]
+    fn test_valid_template() {
+        let template = r#"
+{{#each log as |change i|}}
+  {{ this.title }} {{ author.name }} {{ change.author.name }} {{ @index }} {{ ../meta.release_tag }}
+  {{#with author}}{{ name }}{{/with}}
+  {{truncate (truncate title 3) 2}}
+{{else}}
+  {{ meta.release_tag.anything }}
+{{/each}}
+{{#each changes}}{{ @key }}: {{ len logs }}{{/each}}
+{{ changes.[device/foo].logs }} {{ vars.channel }} {{ @root.log.0.title }}
+{{#*inline "row"}}{{ title }}{{/inline}}{{#each log}}{{> row}}{{/each}}
+"#;
+        assert_eq!(check_template(template), Vec::<String>::new());
+    }
+
+    #[test]
+    fn test_unknown_fields() {
+        assert_eq!(
+            check_template("{{ titel }}\n{{#each log}}{{ author.email }}{{ title.x }}{{/each}}"),
+            [
+                r#"t:1:1: unknown field "titel": no field "titel""#,
+                r#"t:2:14: unknown field "author.email": no field "email""#,
+                r#"t:2:32: unknown field "title.x": "x" of a string"#,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_unknown_helpers_and_partials() {
+        assert_eq!(
+            check_template("{{ trunc log 3 }}\n{{#if (upper meta)}}{{/if}}\n{{> nope}}"),
+            [
+                r#"t:1:1: unknown helper "trunc""#,
+                r#"t:2:1: unknown helper "upper""#,
+                r#"t:3:1: unknown partial "nope""#,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_partials_checked_in_context() {
+        assert_eq!(
+            check_template("{{#each log}}{{> partial}}{{/each}}{{#> missing}}fallback{{/missing}}"),
+            [r#"partial:1:12: unknown field "missing": no field "missing""#]
+        );
+    }
+
+    #[test]
+    fn test_segments() {
+        assert_eq!(
+            segments("changes.[system/core].logs"),
+            ["changes", "system/core", "logs"]
+        );
+        assert_eq!(segments("./a/b"), ["a", "b"]);
+    }
+}
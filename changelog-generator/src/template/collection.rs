@@ -0,0 +1,358 @@
+//! Helpers to reshape lists in templates
+//!
+//! Keys are dotted paths into each item, e.g. `repo` or `author.canonical.email`.
+//! `group_by` also accepts `author` for the canonical identity of the author
+//! and `day` for the date of the change in the default timezone of the renderer.
+//!
+//! - `{{#each (group_by log "day")}}{{key}}: {{count items}}{{/each}}`
+//! - `{{#each (take (sort_by log "diffstat.insertions" reverse=true) 20)}}…{{/each}}`
+//! - `{{#each (filter log "kind" "Revert")}}…{{/each}}`
+//! - `{{#each (filter log "title" regex="^camera" not=true)}}…{{/each}}`
+//! - `{{#each (unique log "repo")}}…{{/each}}`, `{{skip log 10}}`
+
+use std::cmp::Ordering;
+
+use chrono::DateTime;
+use chrono_tz::Tz;
+use handlebars::{
+    Context, Handlebars, Helper, HelperDef, JsonRender, RenderContext, RenderError,
+    RenderErrorReason, ScopedJson,
+};
+use regex::Regex;
+use serde_json::{Value, json};
+
+use super::Helpers;
+
+/// Helpers reshaping lists, grouping by `day` in `timezone`
+pub fn helpers(timezone: Tz) -> Helpers {
+    vec![
+        ("group_by", Box::new(GroupBy { timezone })),
+        ("sort_by", Box::new(SortBy)),
+        ("filter", Box::new(Filter)),
+        ("count", Box::new(Count)),
+        ("take", Box::new(Take)),
+        ("skip", Box::new(Skip)),
+        ("unique", Box::new(Unique)),
+    ]
+}
+
+/// Define a helper returning a JSON value
+macro_rules! value_helper {
+    ($name:ident, |$h:ident| $body:expr) => {
+        struct $name;
+
+        impl HelperDef for $name {
+            fn call_inner<'reg: 'rc, 'rc>(
+                &self,
+                $h: &Helper<'rc>,
+                _: &'reg Handlebars<'reg>,
+                _: &'rc Context,
+                _: &mut RenderContext<'reg, 'rc>,
+            ) -> Result<ScopedJson<'rc>, RenderError> {
+                let result: Result<Value, RenderError> = $body;
+                Ok(ScopedJson::Derived(result?))
+            }
+        }
+    };
+}
+
+struct GroupBy {
+    timezone: Tz,
+}
+
+impl HelperDef for GroupBy {
+    fn call_inner<'reg: 'rc, 'rc>(
+        &self,
+        h: &Helper<'rc>,
+        _: &'reg Handlebars<'reg>,
+        _: &'rc Context,
+        _: &mut RenderContext<'reg, 'rc>,
+    ) -> Result<ScopedJson<'rc>, RenderError> {
+        let key = key_param(h, 1)?;
+        let mut groups: Vec<(Value, Vec<Value>)> = Vec::new();
+        for item in list_param(h, 0)? {
+            let value = group_key(item, &key, self.timezone);
+            match groups.iter_mut().find(|(k, _)| *k == value) {
+                Some((_, items)) => items.push(item.clone()),
+                None => groups.push((value, vec![item.clone()])),
+            }
+        }
+        Ok(ScopedJson::Derived(
+            groups
+                .into_iter()
+                .map(|(key, items)| json!({ "key": key, "items": items }))
+                .collect(),
+        ))
+    }
+}
+
+value_helper!(SortBy, |h| {
+    let key = key_param(h, 1)?;
+    let mut items = list_param(h, 0)?.to_vec();
+    items.sort_by(|a, b| compare(lookup(a, &key), lookup(b, &key)));
+    if bool_hash(h, "reverse") {
+        items.reverse();
+    }
+    Ok(Value::Array(items))
+});
+
+value_helper!(Filter, |h| {
+    let key = key_param(h, 1)?;
+    let regex =
+        match h.hash_get("regex") {
+            Some(pattern) => {
+                let pattern = pattern.value().render();
+                Some(Regex::new(&pattern).map_err(|e| {
+                    RenderErrorReason::Other(format!("invalid regex {pattern:?}: {e}"))
+                })?)
+            }
+            None => None,
+        };
+    let expected = h.param(2).map(|p| p.value());
+    if regex.is_none() && expected.is_none() {
+        return Err(RenderErrorReason::Other(
+            "filter: expected a value or a regex to match".to_string(),
+        )
+        .into());
+    }
+    let negate = bool_hash(h, "not");
+    Ok(list_param(h, 0)?
+        .iter()
+        .filter(|item| {
+            let value = lookup(item, &key);
+            let matched = match (&regex, expected) {
+                (Some(regex), _) => value.is_some_and(|v| regex.is_match(&v.render())),
+                (None, Some(expected)) => value.unwrap_or(&Value::Null) == expected,
+                (None, None) => unreachable!(),
+            };
+            matched != negate
+        })
+        .cloned()
+        .collect())
+});
+
+value_helper!(Count, |h| {
+    let value = h
+        .param(0)
+        .ok_or_else(|| RenderErrorReason::ParamNotFoundForIndex("count", 0))?
+        .value();
+    Ok(match value {
+        Value::Array(a) => a.len().into(),
+        Value::Object(o) => o.len().into(),
+        Value::Null => 0.into(),
+        _ => return Err(RenderErrorReason::InvalidParamType("expected a list").into()),
+    })
+});
+
+value_helper!(Take, |h| {
+    let n = usize_param(h, 1)?;
+    Ok(list_param(h, 0)?.iter().take(n).cloned().collect())
+});
+
+value_helper!(Skip, |h| {
+    let n = usize_param(h, 1)?;
+    Ok(list_param(h, 0)?.iter().skip(n).cloned().collect())
+});
+
+value_helper!(Unique, |h| {
+    let key = match h.param(1) {
+        Some(_) => Some(key_param(h, 1)?),
+        None => None,
+    };
+    let mut seen: Vec<Option<&Value>> = Vec::new();
+    let mut items = Vec::new();
+    for item in list_param(h, 0)? {
+        let value = match &key {
+            Some(key) => lookup(item, key),
+            None => Some(item),
+        };
+        if !seen.contains(&value) {
+            seen.push(value);
+            items.push(item.clone());
+        }
+    }
+    Ok(Value::Array(items))
+});
+
+fn lookup<'a>(item: &'a Value, key: &str) -> Option<&'a Value> {
+    key.split('.').try_fold(item, |value, segment| match value {
+        Value::Object(o) => o.get(segment),
+        Value::Array(a) => a.get(segment.parse::<usize>().ok()?),
+        _ => None,
+    })
+}
+
+fn group_key(item: &Value, key: &str, timezone: Tz) -> Value {
+    let value = match key {
+        "author" => lookup(item, "author.canonical"),
+        "day" => {
+            return lookup(item, "datetime")
+                .and_then(Value::as_str)
+                .and_then(|d| DateTime::parse_from_rfc3339(d).ok())
+                .map_or(Value::Null, |d| {
+                    Value::from(d.with_timezone(&timezone).date_naive().to_string())
+                });
+        }
+        key => lookup(item, key),
+    };
+    value.cloned().unwrap_or(Value::Null)
+}
+
+/// Missing values sort first, numbers are compared numerically
+fn compare(a: Option<&Value>, b: Option<&Value>) -> Ordering {
+    match (a, b) {
+        (Some(Value::Number(a)), Some(Value::Number(b))) => a
+            .as_f64()
+            .partial_cmp(&b.as_f64())
+            .unwrap_or(Ordering::Equal),
+        (Some(Value::Bool(a)), Some(Value::Bool(b))) => a.cmp(b),
+        (Some(a), Some(b)) => a.render().cmp(&b.render()),
+        (a, b) => a.is_some().cmp(&b.is_some()),
+    }
+}
+
+fn list_param<'a>(h: &'a Helper, index: usize) -> Result<&'a [Value], RenderError> {
+    let value = h
+        .param(index)
+        .ok_or_else(|| RenderErrorReason::ParamNotFoundForIndex("collection helper", index))?
+        .value();
+    match value {
+        Value::Array(a) => Ok(a),
+        Value::Null => Ok(&[]),
+        _ => Err(RenderErrorReason::InvalidParamType("expected a list").into()),
+    }
+}
+
+fn key_param(h: &Helper, index: usize) -> Result<String, RenderError> {
+    h.param(index)
+        .ok_or_else(|| RenderErrorReason::ParamNotFoundForIndex("collection helper", index))?
+        .value()
+        .as_str()
+        .map(str::to_string)
+        .ok_or_else(|| RenderErrorReason::InvalidParamType("expected a key (string)").into())
+}
+
+fn usize_param(h: &Helper, index: usize) -> Result<usize, RenderError> {
+    h.param(index)
+        .ok_or_else(|| RenderErrorReason::ParamNotFoundForIndex("collection helper", index))?
+        .value()
+        .as_u64()
+        .map(|n| n as usize)
+        .ok_or_else(|| {
+            RenderErrorReason::InvalidParamType("expected a non-negative integer").into()
+        })
+}
+
+fn bool_hash(h: &Helper, name: &str) -> bool {
+    h.hash_get(name)
+        .is_some_and(|v| v.value().as_bool() == Some(true))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn render(template: &str) -> Result<String, RenderError> {
+        render_in(template, Tz::UTC)
+    }
+
+    fn render_in(template: &str, timezone: Tz) -> Result<String, RenderError> {
+        let mut registry = Handlebars::new();
+        for (name, helper) in helpers(timezone) {
+            registry.register_helper(name, helper);
+        }
+        let change = |repo: &str, title: &str, kind: &str, datetime: &str, email: &str, n: u64| {
+            json!({
+                "repo": repo,
+                "title": title,
+                "kind": kind,
+                "datetime": datetime,
+                "author": { "canonical": { "name": email.to_uppercase(), "email": email } },
+                "diffstat": { "insertions": n },
+            })
+        };
+        registry.render_template(
+            template,
+            &json!({
+                "log": [
+                    change("system/core", "init: fix crash", "Normal", "2026-10-17T10:00:00Z", "a", 5),
+                    change("device/foo", "camera: fix HDR", "Normal", "2026-10-17T12:00:00Z", "b", 100),
+                    change("system/core", "Revert \"init: fix crash\"", "Revert", "2026-10-18T08:00:00Z", "a", 0),
+                    change("device/foo", "camera: tune", "Normal", "2026-10-19T09:00:00Z", "a", 20),
+                ]
+            }),
+        )
+    }
+
+    #[test]
+    fn test_group_by() {
+        assert_eq!(
+            render(r#"{{#each (group_by log "day")}}{{key}}={{count items}} {{/each}}"#).unwrap(),
+            "2026-10-17=2 2026-10-18=1 2026-10-19=1 "
+        );
+        // 12:00 UTC is already the next day in Auckland
+        assert_eq!(
+            render_in(
+                r#"{{#each (group_by log "day")}}{{key}}={{count items}} {{/each}}"#,
+                Tz::Pacific__Auckland
+            )
+            .unwrap(),
+            "2026-10-17=1 2026-10-18=2 2026-10-19=1 "
+        );
+        assert_eq!(
+            render(r#"{{#each (group_by log "author")}}{{key.name}}:{{#each items}}{{repo}},{{/each}} {{/each}}"#)
+                .unwrap(),
+            "A:system/core,system/core,device/foo, B:device/foo, "
+        );
+        assert_eq!(
+            render(r#"{{#each (group_by log "repo")}}{{key}}={{count items}} {{/each}}"#).unwrap(),
+            "system/core=2 device/foo=2 "
+        );
+    }
+
+    #[test]
+    fn test_sort_take_skip() {
+        assert_eq!(
+            render(r#"{{#each (sort_by log "diffstat.insertions" reverse=true)}}{{diffstat.insertions}} {{/each}}"#)
+                .unwrap(),
+            "100 20 5 0 "
+        );
+        assert_eq!(
+            render(r#"{{#each (take (sort_by log "title") 2)}}{{title}}; {{/each}}"#).unwrap(),
+            "Revert &quot;init: fix crash&quot;; camera: fix HDR; "
+        );
+        assert_eq!(
+            render(r#"{{#each (skip log 3)}}{{title}}{{/each}}"#).unwrap(),
+            "camera: tune"
+        );
+    }
+
+    #[test]
+    fn test_filter() {
+        assert_eq!(
+            render(r#"{{count (filter log "kind" "Revert")}}"#).unwrap(),
+            "1"
+        );
+        assert_eq!(
+            render(r#"{{#each (filter log "title" regex="^camera")}}{{title}}; {{/each}}"#)
+                .unwrap(),
+            "camera: fix HDR; camera: tune; "
+        );
+        assert_eq!(
+            render(r#"{{count (filter log "kind" "Revert" not=true)}}"#).unwrap(),
+            "3"
+        );
+        assert!(render(r#"{{filter log "kind"}}"#).is_err());
+    }
+
+    #[test]
+    fn test_unique_and_count() {
+        assert_eq!(
+            render(r#"{{#each (unique log "repo")}}{{repo}} {{/each}}"#).unwrap(),
+            "system/core device/foo "
+        );
+        assert_eq!(render("{{count (unique log)}}").unwrap(), "4");
+        assert_eq!(render("{{count missing}}").unwrap(), "0");
+        assert!(render(r#"{{count "text"}}"#).is_err());
+    }
+}
@@ -0,0 +1,339 @@
+//! Render commit message bodies to HTML
+//!
+//! `{{commit_body_html this.description}}` converts a body following Gerrit conventions
+//! to HTML: wrapped lines are joined into paragraphs, `-`/`*`/`1.` lines become lists,
+//! indented blocks become code, and URLs, bugs and CVEs are linked.
+//! All text is escaped, so the result is safe to embed in a page.
+//!
+//! Trailers are moved out of the body, so `{{trailer_html this}}` renders one of
+//! [`Change::trailers`](crate::changelog::Change::trailers) like `Bug: 123456`
+//! with the same links.
+//!
+//! Bugs like `Bug: 123456` and `b/123456` link to the Google issue tracker by default,
+//! which could be changed with `bug_url="https://example.com/issues/{}"`.
+
+use std::sync::LazyLock;
+
+use handlebars::{
+    Context, Handlebars, Helper, HelperResult, JsonRender, Output, RenderContext, RenderErrorReason,
+};
+use regex::{Captures, Regex};
+
+use super::Helpers;
+
+const DEFAULT_BUG_URL: &str = "https://issuetracker.google.com/issues/{}";
+
+static LIST_ITEM: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"^\s{0,3}(?:([-*+])|\d{1,3}[.)])\s+(.*)$").unwrap());
+
+static LINK: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(
+        r"(?x)
+        (?P<url>https?://[^\s<>\x22]+)
+        | \b(?P<cve>CVE-\d{4}-\d{4,})\b
+        | (?P<bug_prefix>(?i:\bbug:\s*|\bb/))(?P<bug>\d+)\b",
+    )
+    .unwrap()
+});
+
+pub fn helpers() -> Helpers {
+    vec![
+        ("commit_body_html", Box::new(commit_body_html)),
+        ("trailer_html", Box::new(trailer_html)),
+    ]
+}
+
+fn commit_body_html(
+    h: &Helper,
+    _: &Handlebars,
+    _: &Context,
+    _: &mut RenderContext,
+    out: &mut dyn Output,
+) -> HelperResult {
+    let body = h
+        .param(0)
+        .ok_or_else(|| RenderErrorReason::ParamNotFoundForIndex("commit_body_html", 0))?
+        .value()
+        .render();
+    out.write(&to_html(&body, &bug_url(h)))?;
+    Ok(())
+}
+
+fn trailer_html(
+    h: &Helper,
+    _: &Handlebars,
+    _: &Context,
+    _: &mut RenderContext,
+    out: &mut dyn Output,
+) -> HelperResult {
+    let trailer = h
+        .param(0)
+        .ok_or_else(|| RenderErrorReason::ParamNotFoundForIndex("trailer_html", 0))?
+        .value();
+    let (Some(key), Some(value)) = (trailer["key"].as_str(), trailer["value"].as_str()) else {
+        return Err(RenderErrorReason::InvalidParamType("expected a trailer").into());
+    };
+    out.write(&linkify(&format!("{key}: {value}"), &bug_url(h)))?;
+    Ok(())
+}
+
+fn bug_url(h: &Helper) -> String {
+    match h.hash_get("bug_url") {
+        Some(url) => url.value().render(),
+        None => DEFAULT_BUG_URL.to_string(),
+    }
+}
+
+#[derive(Debug, PartialEq)]
+enum Block {
+    Paragraph(Vec<String>),
+    List { ordered: bool, items: Vec<String> },
+    Code(Vec<String>),
+}
+
+pub fn to_html(body: &str, bug_url: &str) -> String {
+    let mut html = Vec::new();
+    for block in parse(body) {
+        html.push(match block {
+            Block::Paragraph(lines) => format!("<p>{}</p>", linkify(&lines.join(" "), bug_url)),
+            Block::List { ordered, items } => {
+                let tag = if ordered { "ol" } else { "ul" };
+                let items: String = items
+                    .iter()
+                    .map(|item| format!("<li>{}</li>", linkify(item, bug_url)))
+                    .collect();
+                format!("<{tag}>{items}</{tag}>")
+            }
+            Block::Code(lines) => {
+                format!("<pre><code>{}</code></pre>", html_escape(&lines.join("\n")))
+            }
+        });
+    }
+    html.join("\n")
+}
+
+fn parse(body: &str) -> Vec<Block> {
+    let mut blocks = Vec::new();
+    let mut chunk: Vec<&str> = Vec::new();
+    for line in body.lines().chain(std::iter::once("")) {
+        if !line.trim().is_empty() {
+            chunk.push(line.trim_end());
+            continue;
+        }
+        // Indented lines are code, also right after a line of text like `Run:`
+        let mut rest = chunk.as_slice();
+        while !rest.is_empty() {
+            let code = rest.iter().take_while(|l| is_code(l)).count();
+            if code > 0 {
+                blocks.push(Block::Code(
+                    rest[..code].iter().map(|l| dedent(l)).collect(),
+                ));
+                rest = &rest[code..];
+            } else {
+                let text = rest.iter().position(|l| is_code(l)).unwrap_or(rest.len());
+                parse_chunk(&rest[..text], &mut blocks);
+                rest = &rest[text..];
+            }
+        }
+        chunk.clear();
+    }
+    blocks
+}
+
+/// Split a chunk of non-blank lines into paragraphs and lists
+fn parse_chunk(lines: &[&str], blocks: &mut Vec<Block>) {
+    let mut current: Option<Block> = None;
+    for line in lines {
+        if let Some(captures) = LIST_ITEM.captures(line) {
+            let ordered = captures.get(1).is_none();
+            let item = captures[2].to_string();
+            match &mut current {
+                Some(Block::List { ordered: o, items }) if *o == ordered => items.push(item),
+                _ => {
+                    blocks.extend(current.take());
+                    current = Some(Block::List {
+                        ordered,
+                        items: vec![item],
+                    });
+                }
+            }
+            continue;
+        }
+        let is_continuation = line.starts_with(char::is_whitespace);
+        match &mut current {
+            Some(Block::List { items, .. }) if is_continuation => {
+                let last = items.last_mut().expect("lists are never empty");
+                last.push(' ');
+                last.push_str(line.trim());
+            }
+            Some(Block::Paragraph(lines)) => lines.push(line.trim().to_string()),
+            _ => {
+                blocks.extend(current.take());
+                current = Some(Block::Paragraph(vec![line.trim().to_string()]));
+            }
+        }
+    }
+    blocks.extend(current);
+}
+
+fn is_code(line: &str) -> bool {
+    line.starts_with("    ") || line.starts_with('\t')
+}
+
+fn dedent(line: &str) -> String {
+    line.strip_prefix("    ")
+        .or_else(|| line.strip_prefix('\t'))
+        .unwrap_or(line)
+        .to_string()
+}
+
+fn html_escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Escape text and link URLs, CVEs and bugs in it
+fn linkify(text: &str, bug_url: &str) -> String {
+    let mut html = String::with_capacity(text.len());
+    let mut last = 0;
+    for captures in LINK.captures_iter(text) {
+        let whole = captures.get(0).unwrap();
+        let (link, end) = link(&captures, bug_url);
+        html.push_str(&html_escape(&text[last..whole.start()]));
+        html.push_str(&link);
+        html.push_str(&html_escape(&text[end..whole.end()]));
+        last = whole.end();
+    }
+    html.push_str(&html_escape(&text[last..]));
+    html
+}
+
+/// Create the link of a match, returning it and where the linked text ends
+fn link(captures: &Captures, bug_url: &str) -> (String, usize) {
+    let anchor = |href: &str, text: &str| {
+        format!(
+            r#"<a href="{}" rel="nofollow noopener">{}</a>"#,
+            html_escape(href),
+            html_escape(text)
+        )
+    };
+    if let Some(url) = captures.name("url") {
+        // Trailing punctuation most likely belongs to the sentence
+        let mut trimmed = url
+            .as_str()
+            .trim_end_matches(['.', ',', ';', ':', '!', '?', '\'']);
+        if trimmed.ends_with(')') && !trimmed.contains('(') {
+            trimmed = &trimmed[..trimmed.len() - 1];
+        }
+        (anchor(trimmed, trimmed), url.start() + trimmed.len())
+    } else if let Some(cve) = captures.name("cve") {
+        let href = format!("https://www.cve.org/CVERecord?id={}", cve.as_str());
+        (anchor(&href, cve.as_str()), cve.end())
+    } else {
+        let prefix = &captures["bug_prefix"];
+        let bug = captures.name("bug").unwrap();
+        let href = bug_url.replace("{}", bug.as_str());
+        (
+            format!("{}{}", html_escape(prefix), anchor(&href, bug.as_str())),
+            bug.end(),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn html(body: &str) -> String {
+        to_html(body, DEFAULT_BUG_URL)
+    }
+
+    #[test]
+    fn test_paragraphs() {
+        assert_eq!(
+            html("This change fixes a crash\nwhen the camera is opened.\n\n\nSecond paragraph."),
+            "<p>This change fixes a crash when the camera is opened.</p>\n<p>Second paragraph.</p>"
+        );
+        assert_eq!(html(""), "");
+    }
+
+    #[test]
+    fn test_lists() {
+        assert_eq!(
+            html("Changes:\n- foo\n- bar which is\n  wrapped\n* baz\n1. one\n2) two"),
+            "<p>Changes:</p>\n<ul><li>foo</li><li>bar which is wrapped</li><li>baz</li></ul>\n<ol><li>one</li><li>two</li></ol>"
+        );
+    }
+
+    #[test]
+    fn test_code() {
+        assert_eq!(
+            html("Run:\n\n    adb shell\n      setprop a <b>"),
+            "<p>Run:</p>\n<pre><code>adb shell\n  setprop a &lt;b&gt;</code></pre>"
+        );
+        assert_eq!(
+            html("Run:\n    adb shell\nto check."),
+            "<p>Run:</p>\n<pre><code>adb shell</code></pre>\n<p>to check.</p>"
+        );
+    }
+
+    #[test]
+    fn test_trailer() {
+        let mut registry = Handlebars::new();
+        for (name, helper) in helpers() {
+            registry.register_helper(name, helper);
+        }
+        let trailer = serde_json::json!({ "key": "Bug", "value": "123, b/456" });
+        assert_eq!(
+            registry
+                .render_template("{{trailer_html this}}", &trailer)
+                .unwrap(),
+            concat!(
+                r#"Bug: <a href="https://issuetracker.google.com/issues/123" rel="nofollow noopener">123</a>, "#,
+                r#"b/<a href="https://issuetracker.google.com/issues/456" rel="nofollow noopener">456</a>"#
+            )
+        );
+    }
+
+    #[test]
+    fn test_links() {
+        assert_eq!(
+            html("See https://source.android.com/docs/a_b. (https://x.org/y)"),
+            r#"<p>See <a href="https://source.android.com/docs/a_b" rel="nofollow noopener">https://source.android.com/docs/a_b</a>. (<a href="https://x.org/y" rel="nofollow noopener">https://x.org/y</a>)</p>"#
+        );
+        assert_eq!(
+            html("Fix CVE-2026-12345, Bug: 123 and b/456"),
+            concat!(
+                r#"<p>Fix <a href="https://www.cve.org/CVERecord?id=CVE-2026-12345" rel="nofollow noopener">CVE-2026-12345</a>, "#,
+                r#"Bug: <a href="https://issuetracker.google.com/issues/123" rel="nofollow noopener">123</a> and "#,
+                r#"b/<a href="https://issuetracker.google.com/issues/456" rel="nofollow noopener">456</a></p>"#
+            )
+        );
+        assert_eq!(
+            to_html("Bug: 7", "https://bugs.example.com/{}"),
+            r#"<p>Bug: <a href="https://bugs.example.com/7" rel="nofollow noopener">7</a></p>"#
+        );
+    }
+
+    #[test]
+    fn test_sanitize() {
+        assert_eq!(
+            html("<script>alert(1)</script> javascript:alert(1)"),
+            "<p>&lt;script&gt;alert(1)&lt;/script&gt; javascript:alert(1)</p>"
+        );
+        assert_eq!(
+            html(r#"https://evil.example/"onmouseover="x"#),
+            r#"<p><a href="https://evil.example/" rel="nofollow noopener">https://evil.example/</a>&quot;onmouseover=&quot;x</p>"#
+        );
+    }
+}
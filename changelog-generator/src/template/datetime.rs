@@ -0,0 +1,291 @@
+//! Date and time helpers
+//!
+//! Dates could be RFC 3339 strings like [`Change::datetime`](crate::changelog::Change::datetime)
+//! or unix timestamps. They are shown in the default timezone unless a `tz` is given.
+//! Names of months and weekdays are those of the default locale unless a `locale` is given.
+//!
+//! - `{{date_format this.datetime "%Y-%m-%d %H:%M" tz="Asia/Shanghai"}}`
+//! - `{{date_format this.datetime "%e %B %Y" locale="de-DE"}}`: `19 Oktober 2026`
+//! - `{{relative_time this.datetime now=meta.generated_at}}`: `3 days ago`
+//! - `{{iso_week this.datetime}}`: `2026-W42`
+//! - `{{to_timezone this.datetime "Europe/Berlin"}}`: RFC 3339 with the offset of the timezone
+
+use chrono::{DateTime, Datelike, Locale, TimeDelta, Utc, format::StrftimeItems};
+use chrono_tz::Tz;
+use handlebars::{
+    Context, Handlebars, Helper, HelperDef, RenderContext, RenderError, RenderErrorReason,
+    ScopedJson,
+};
+use serde_json::Value;
+
+use super::Helpers;
+
+/// Helpers showing dates in `timezone` and `locale`, English if no locale is given
+pub fn helpers(timezone: Tz, locale: Option<Locale>) -> Helpers {
+    vec![
+        ("date_format", Box::new(DateFormat { timezone, locale })),
+        ("relative_time", Box::new(RelativeTime)),
+        ("iso_week", Box::new(IsoWeek { timezone })),
+        ("to_timezone", Box::new(ToTimezone { timezone })),
+    ]
+}
+
+struct DateFormat {
+    timezone: Tz,
+    locale: Option<Locale>,
+}
+
+impl HelperDef for DateFormat {
+    fn call_inner<'reg: 'rc, 'rc>(
+        &self,
+        h: &Helper<'rc>,
+        _: &'reg Handlebars<'reg>,
+        _: &'rc Context,
+        _: &mut RenderContext<'reg, 'rc>,
+    ) -> Result<ScopedJson<'rc>, RenderError> {
+        let date = datetime_param(h, 0)?;
+        let format = match h.param(1) {
+            Some(format) => format
+                .value()
+                .as_str()
+                .ok_or(RenderErrorReason::InvalidParamType(
+                    "expected a format string",
+                ))?,
+            None => "%Y-%m-%d %H:%M:%S %Z",
+        };
+        let items = StrftimeItems::new(format).parse().map_err(|e| {
+            RenderErrorReason::Other(format!("invalid date format {format:?}: {e}"))
+        })?;
+        let date = date.with_timezone(&timezone_hash(h, self.timezone)?);
+        let locale = match h.hash_get("locale") {
+            Some(locale) => Some(locale.value().as_str().and_then(parse_locale).ok_or_else(
+                || RenderErrorReason::Other(format!("unknown locale {}", locale.value())),
+            )?),
+            None => self.locale,
+        };
+        let formatted = match locale {
+            Some(locale) => date
+                .format_localized_with_items(items.iter(), locale)
+                .to_string(),
+            None => date.format_with_items(items.iter()).to_string(),
+        };
+        Ok(ScopedJson::Derived(Value::String(formatted)))
+    }
+}
+
+struct RelativeTime;
+
+impl HelperDef for RelativeTime {
+    fn call_inner<'reg: 'rc, 'rc>(
+        &self,
+        h: &Helper<'rc>,
+        _: &'reg Handlebars<'reg>,
+        _: &'rc Context,
+        _: &mut RenderContext<'reg, 'rc>,
+    ) -> Result<ScopedJson<'rc>, RenderError> {
+        let date = datetime_param(h, 0)?;
+        let now = match h.hash_get("now") {
+            Some(now) => parse_datetime(now.value(), "relative_time")?,
+            None => Utc::now(),
+        };
+        Ok(ScopedJson::Derived(Value::String(relative_time(
+            now - date,
+        ))))
+    }
+}
+
+struct IsoWeek {
+    timezone: Tz,
+}
+
+impl HelperDef for IsoWeek {
+    fn call_inner<'reg: 'rc, 'rc>(
+        &self,
+        h: &Helper<'rc>,
+        _: &'reg Handlebars<'reg>,
+        _: &'rc Context,
+        _: &mut RenderContext<'reg, 'rc>,
+    ) -> Result<ScopedJson<'rc>, RenderError> {
+        let date = datetime_param(h, 0)?.with_timezone(&timezone_hash(h, self.timezone)?);
+        let week = date.iso_week();
+        Ok(ScopedJson::Derived(Value::String(format!(
+            "{}-W{:02}",
+            week.year(),
+            week.week()
+        ))))
+    }
+}
+
+struct ToTimezone {
+    timezone: Tz,
+}
+
+impl HelperDef for ToTimezone {
+    fn call_inner<'reg: 'rc, 'rc>(
+        &self,
+        h: &Helper<'rc>,
+        _: &'reg Handlebars<'reg>,
+        _: &'rc Context,
+        _: &mut RenderContext<'reg, 'rc>,
+    ) -> Result<ScopedJson<'rc>, RenderError> {
+        let date = datetime_param(h, 0)?;
+        let timezone = match h.param(1) {
+            Some(tz) => parse_timezone(tz.value())?,
+            None => self.timezone,
+        };
+        Ok(ScopedJson::Derived(Value::String(
+            date.with_timezone(&timezone).to_rfc3339(),
+        )))
+    }
+}
+
+/// Describe a duration like `3 days ago` or `in 2 hours`
+fn relative_time(delta: TimeDelta) -> String {
+    let seconds = delta.num_seconds();
+    let abs = seconds.unsigned_abs();
+    let (n, unit) = match abs {
+        0..60 => return "just now".to_string(),
+        60..3600 => (abs / 60, "minute"),
+        3600..86400 => (abs / 3600, "hour"),
+        86400..2592000 => (abs / 86400, "day"),
+        2592000..31536000 => (abs / 2592000, "month"),
+        _ => (abs / 31536000, "year"),
+    };
+    let plural = if n == 1 { "" } else { "s" };
+    if seconds > 0 {
+        format!("{n} {unit}{plural} ago")
+    } else {
+        format!("in {n} {unit}{plural}")
+    }
+}
+
+fn datetime_param(h: &Helper, index: usize) -> Result<DateTime<Utc>, RenderError> {
+    let param = h
+        .param(index)
+        .ok_or_else(|| RenderErrorReason::ParamNotFoundForIndex("date helper", index))?;
+    parse_datetime(param.value(), h.name())
+}
+
+/// Accept RFC 3339 strings and unix timestamps
+fn parse_datetime(value: &Value, helper: &str) -> Result<DateTime<Utc>, RenderError> {
+    let date = match value {
+        Value::String(s) => DateTime::parse_from_rfc3339(s)
+            .ok()
+            .map(|d| d.with_timezone(&Utc)),
+        Value::Number(n) => n.as_i64().and_then(|n| DateTime::from_timestamp(n, 0)),
+        _ => None,
+    };
+    date.ok_or_else(|| {
+        RenderErrorReason::Other(format!(
+            "{helper}: expected a RFC 3339 date or a unix timestamp, got {value}"
+        ))
+        .into()
+    })
+}
+
+fn parse_timezone(value: &Value) -> Result<Tz, RenderError> {
+    value
+        .as_str()
+        .and_then(|tz| tz.parse().ok())
+        .ok_or_else(|| RenderErrorReason::Other(format!("unknown timezone {value}")).into())
+}
+
+/// Parse a locale like `zh-CN` or `zh_CN`
+pub fn parse_locale(locale: &str) -> Option<Locale> {
+    Locale::try_from(locale.replace('-', "_").as_str()).ok()
+}
+
+fn timezone_hash(h: &Helper, default: Tz) -> Result<Tz, RenderError> {
+    match h.hash_get("tz") {
+        Some(tz) => parse_timezone(tz.value()),
+        None => Ok(default),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    fn render(template: &str, timezone: Tz) -> Result<String, RenderError> {
+        let mut registry = Handlebars::new();
+        for (name, helper) in helpers(timezone, None) {
+            registry.register_helper(name, helper);
+        }
+        registry.render_template(
+            template,
+            &json!({ "date": "2026-10-19T04:42:02Z", "now": "2026-10-22T05:00:00Z" }),
+        )
+    }
+
+    #[test]
+    fn test_date_format() {
+        assert_eq!(
+            render(r#"{{date_format date "%Y-%m-%d %H:%M"}}"#, Tz::UTC).unwrap(),
+            "2026-10-19 04:42"
+        );
+        assert_eq!(
+            render(
+                r#"{{date_format date "%Y-%m-%d %H:%M %Z" tz="Asia/Shanghai"}}"#,
+                Tz::UTC
+            )
+            .unwrap(),
+            "2026-10-19 12:42 CST"
+        );
+        // the default timezone
+        assert_eq!(
+            render(r#"{{date_format date "%H:%M"}}"#, Tz::Europe__Berlin).unwrap(),
+            "06:42"
+        );
+        // unix timestamps
+        assert_eq!(
+            render(r#"{{date_format 0 "%Y-%m-%d"}}"#, Tz::UTC).unwrap(),
+            "1970-01-01"
+        );
+        assert_eq!(
+            render(r#"{{date_format date "%e %B %Y" locale="de-DE"}}"#, Tz::UTC).unwrap(),
+            "19 Oktober 2026"
+        );
+        assert!(render(r#"{{date_format date "%B" locale="xx"}}"#, Tz::UTC).is_err());
+        assert!(render(r#"{{date_format date "%Q"}}"#, Tz::UTC).is_err());
+        assert!(render(r#"{{date_format "yesterday" "%Y"}}"#, Tz::UTC).is_err());
+    }
+
+    #[test]
+    fn test_relative_time() {
+        assert_eq!(
+            render("{{relative_time date now=now}}", Tz::UTC).unwrap(),
+            "3 days ago"
+        );
+        assert_eq!(
+            render("{{relative_time now now=date}}", Tz::UTC).unwrap(),
+            "in 3 days"
+        );
+        assert_eq!(relative_time(TimeDelta::seconds(30)), "just now");
+        assert_eq!(relative_time(TimeDelta::seconds(3600)), "1 hour ago");
+        assert_eq!(relative_time(TimeDelta::days(400)), "1 year ago");
+    }
+
+    #[test]
+    fn test_iso_week() {
+        assert_eq!(render("{{iso_week date}}", Tz::UTC).unwrap(), "2026-W43");
+        assert_eq!(
+            render(r#"{{iso_week "2027-01-01T00:00:00Z"}}"#, Tz::UTC).unwrap(),
+            "2026-W53"
+        );
+    }
+
+    #[test]
+    fn test_to_timezone() {
+        assert_eq!(
+            render(r#"{{to_timezone date "Asia/Tokyo"}}"#, Tz::UTC).unwrap(),
+            "2026-10-19T13:42:02+09:00"
+        );
+        assert_eq!(
+            render("{{to_timezone date}}", Tz::America__New_York).unwrap(),
+            "2026-10-19T00:42:02-04:00"
+        );
+        assert!(render(r#"{{to_timezone date "Mars/Olympus"}}"#, Tz::UTC).is_err());
+    }
+}
@@ -0,0 +1,337 @@
+//! Context-specific escaping
+//!
+//! Every output has an [`EscapeMode`] applied to `{{ expressions }}`, while the
+//! helpers below escape for a specific context and write their result as is.
+//!
+//! - `{{md_escape this.title}}`: markdown inline text
+//! - `[{{md_link_text this.title}}](…)`: markdown link text, keeping `code` spans
+//! - `<a title="{{html_attr this.title}}">`: HTML attribute value
+//! - `https://example.com/?q={{url_component this.repo}}`: URL component
+//! - `"title": {{json this.title}}`: JSON value, strings are quoted
+//! - `{{tg_escape this.title}}`: Telegram MarkdownV2
+
+use std::{path::Path, str::FromStr};
+
+use handlebars::{
+    Context, Handlebars, Helper, HelperResult, JsonRender, Output, RenderContext, RenderErrorReason,
+};
+use serde::{Deserialize, Deserializer, de};
+
+use super::Helpers;
+
+/// How `{{ expressions }}` are escaped in an output
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum EscapeMode {
+    #[default]
+    Html,
+    Markdown,
+    /// Telegram MarkdownV2
+    Telegram,
+    /// The content of JSON strings, without quotes
+    Json,
+    None,
+}
+
+impl FromStr for EscapeMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s {
+            "html" => Self::Html,
+            "markdown" => Self::Markdown,
+            "telegram" => Self::Telegram,
+            "json" => Self::Json,
+            "none" => Self::None,
+            _ => {
+                return Err(format!(
+                    "unknown escape mode {s:?}, expected html, markdown, telegram, json or none"
+                ));
+            }
+        })
+    }
+}
+
+impl<'de> Deserialize<'de> for EscapeMode {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        String::deserialize(deserializer)?
+            .parse()
+            .map_err(de::Error::custom)
+    }
+}
+
+impl EscapeMode {
+    /// Guess the mode from the family of a built-in template, e.g. `markdown`,
+    /// or else from the extension of the output file.
+    pub fn infer(output: impl AsRef<Path>, builtin: Option<&str>) -> Self {
+        let family = builtin.map(|name| name.split('/').next().unwrap_or(name));
+        match family {
+            Some("markdown") => return Self::Markdown,
+            Some("html" | "html-page" | "rss") => return Self::Html,
+            Some("text") => return Self::None,
+            _ => {}
+        }
+        let extension = output.as_ref().extension().and_then(|e| e.to_str());
+        match extension {
+            Some("md" | "markdown") => Self::Markdown,
+            Some("json") => Self::Json,
+            Some("txt") => Self::None,
+            _ => Self::Html,
+        }
+    }
+
+    pub(super) fn escape_fn(self) -> fn(&str) -> String {
+        match self {
+            Self::Html => handlebars::html_escape,
+            Self::Markdown => md_escape,
+            Self::Telegram => tg_escape,
+            Self::Json => json_string_content,
+            Self::None => handlebars::no_escape,
+        }
+    }
+}
+
+pub fn helpers() -> Helpers {
+    vec![
+        ("md_escape", Box::new(escape_helper(md_escape))),
+        ("md_link_text", Box::new(escape_helper(md_link_text))),
+        // The old name of md_link_text
+        ("md_link_desc_escape", Box::new(escape_helper(md_link_text))),
+        (
+            "html_attr",
+            Box::new(escape_helper(handlebars::html_escape)),
+        ),
+        ("url_component", Box::new(escape_helper(url_component))),
+        ("tg_escape", Box::new(escape_helper(tg_escape))),
+        ("json", Box::new(json)),
+    ]
+}
+
+/// A helper writing its escaped parameter, regardless of the escape mode
+fn escape_helper(
+    escape: fn(&str) -> String,
+) -> impl Fn(&Helper, &Handlebars, &Context, &mut RenderContext, &mut dyn Output) -> HelperResult
++ Send
++ Sync {
+    move |h, _, _, _, out| {
+        let param = h
+            .param(0)
+            .ok_or_else(|| RenderErrorReason::ParamNotFoundForIndex("escape helper", 0))?;
+        out.write(&escape(&param.value().render()))?;
+        Ok(())
+    }
+}
+
+fn json(
+    h: &Helper,
+    _: &Handlebars,
+    _: &Context,
+    _: &mut RenderContext,
+    out: &mut dyn Output,
+) -> HelperResult {
+    let param = h
+        .param(0)
+        .ok_or_else(|| RenderErrorReason::ParamNotFoundForIndex("json", 0))?;
+    out.write(&param.value().to_string())?;
+    Ok(())
+}
+
+/// Escape characters with a special meaning in markdown inline text
+pub fn md_escape(s: &str) -> String {
+    backslash_escape(s, |c| {
+        matches!(
+            c,
+            '\\' | '`' | '*' | '_' | '[' | ']' | '<' | '>' | '&' | '~' | '|'
+        )
+    })
+}
+
+/// Escape content to be used in a markdown link text.
+///
+/// Unlike [`md_escape`], code spans like `` `init` `` are kept, as some
+/// commit titles already use them, and line breaks become spaces.
+pub fn md_link_text(s: &str) -> String {
+    let s = s.replace(['\r', '\n'], " ");
+    let mut escaped = String::with_capacity(s.len());
+    let mut rest = s.as_str();
+    while let Some(start) = rest.find('`') {
+        let ticks = rest[start..].len() - rest[start..].trim_start_matches('`').len();
+        let fence = &rest[start..start + ticks];
+        let after = &rest[start + ticks..];
+        // A code span ends with a backtick string of the same length
+        let end = after
+            .match_indices(fence)
+            .find(|(i, _)| !after[i + ticks..].starts_with('`') && !after[..*i].ends_with('`'));
+        escaped.push_str(&md_escape(&rest[..start]));
+        match end {
+            Some((end, _)) => {
+                escaped.push_str(&rest[start..start + 2 * ticks + end]);
+                rest = &after[end + ticks..];
+            }
+            None => {
+                escaped.push_str(&md_escape(fence));
+                rest = after;
+            }
+        }
+    }
+    escaped.push_str(&md_escape(rest));
+    escaped
+}
+
+/// Escape for Telegram's MarkdownV2 parse mode
+pub fn tg_escape(s: &str) -> String {
+    backslash_escape(s, |c| {
+        matches!(
+            c,
+            '\\' | '_'
+                | '*'
+                | '['
+                | ']'
+                | '('
+                | ')'
+                | '~'
+                | '`'
+                | '>'
+                | '#'
+                | '+'
+                | '-'
+                | '='
+                | '|'
+                | '{'
+                | '}'
+                | '.'
+                | '!'
+        )
+    })
+}
+
+/// Percent-encode everything but the unreserved characters of RFC 3986
+pub fn url_component(s: &str) -> String {
+    let mut encoded = String::with_capacity(s.len());
+    for byte in s.bytes() {
+        if byte.is_ascii_alphanumeric() || matches!(byte, b'-' | b'.' | b'_' | b'~') {
+            encoded.push(byte as char);
+        } else {
+            encoded.push_str(&format!("%{byte:02X}"));
+        }
+    }
+    encoded
+}
+
+fn json_string_content(s: &str) -> String {
+    let quoted = serde_json::Value::from(s).to_string();
+    quoted[1..quoted.len() - 1].to_string()
+}
+
+fn backslash_escape(s: &str, special: impl Fn(char) -> bool) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        if special(c) {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    const TITLE: &str = "Update <overlay> & *config* for `foo_bar`";
+
+    fn render(template: &str, mode: EscapeMode) -> String {
+        let mut registry = Handlebars::new();
+        registry.register_escape_fn(mode.escape_fn());
+        for (name, helper) in helpers() {
+            registry.register_helper(name, helper);
+        }
+        registry
+            .render_template(template, &json!({ "title": TITLE, "n": 1 }))
+            .unwrap()
+    }
+
+    #[test]
+    fn test_md_escape() {
+        assert_eq!(
+            md_escape(TITLE),
+            r"Update \<overlay\> \& \*config\* for \`foo\_bar\`"
+        );
+        // not affected by the escape mode
+        assert_eq!(
+            render("{{md_escape title}}", EscapeMode::Html),
+            md_escape(TITLE)
+        );
+    }
+
+    #[test]
+    fn test_md_link_text() {
+        assert_eq!(
+            md_link_text(TITLE),
+            r"Update \<overlay\> \& \*config\* for `foo_bar`"
+        );
+        assert_eq!(md_link_text("[a]\nb"), r"\[a\] b");
+        assert_eq!(md_link_text("``a`b`` `c_"), r"``a`b`` \`c\_");
+        assert_eq!(
+            render("{{md_link_desc_escape title}}", EscapeMode::Html),
+            md_link_text(TITLE)
+        );
+    }
+
+    #[test]
+    fn test_tg_escape() {
+        assert_eq!(
+            tg_escape("v1.2 (beta) - fix #12!"),
+            r"v1\.2 \(beta\) \- fix \#12\!"
+        );
+    }
+
+    #[test]
+    fn test_url_component() {
+        assert_eq!(
+            url_component("device/foo bar&日"),
+            "device%2Ffoo%20bar%26%E6%97%A5"
+        );
+    }
+
+    #[test]
+    fn test_html_attr_and_json() {
+        assert_eq!(
+            render(r#"<a title="{{html_attr title}}">"#, EscapeMode::None),
+            r#"<a title="Update &lt;overlay&gt; &amp; *config* for &#x60;foo_bar&#x60;">"#
+        );
+        assert_eq!(
+            render(
+                r#"{"title": {{json title}}, "n": {{json n}}}"#,
+                EscapeMode::Html
+            ),
+            r#"{"title": "Update <overlay> & *config* for `foo_bar`", "n": 1}"#
+        );
+    }
+
+    #[test]
+    fn test_escape_modes() {
+        assert_eq!(render("{{title}}", EscapeMode::Markdown), md_escape(TITLE));
+        assert_eq!(render("{{title}}", EscapeMode::None), TITLE);
+        assert_eq!(
+            render(r#""{{title}}""#, EscapeMode::Json),
+            json!(TITLE).to_string()
+        );
+        assert_eq!(render("{{title}}", EscapeMode::Telegram), tg_escape(TITLE));
+        assert!(render("{{title}}", EscapeMode::Html).contains("&lt;overlay&gt;"));
+    }
+
+    #[test]
+    fn test_infer() {
+        assert_eq!(
+            EscapeMode::infer("out.txt", Some("markdown")),
+            EscapeMode::Markdown
+        );
+        assert_eq!(EscapeMode::infer("out", Some("text")), EscapeMode::None);
+        assert_eq!(EscapeMode::infer("feed.rss", Some("rss")), EscapeMode::Html);
+        assert_eq!(EscapeMode::infer("out.md", None), EscapeMode::Markdown);
+        assert_eq!(EscapeMode::infer("out.json", None), EscapeMode::Json);
+        assert_eq!(EscapeMode::infer("index.html", None), EscapeMode::Html);
+    }
+}
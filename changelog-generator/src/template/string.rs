@@ -0,0 +1,323 @@
+//! Unicode-aware string helpers
+//!
+//! `truncate` and `slice` count grapheme clusters, so emoji and combining
+//! characters are never split. `pad` and `wrap` measure the display width,
+//! where a CJK character takes two columns.
+//!
+//! - `{{truncate this.title 50 ellipsis="…"}}`
+//! - `{{slice 0 12 this.commit}}`
+//! - `{{pad this.repo 30 align="right"}}`
+//! - `{{wrap this.description 72}}`
+//! - `{{first_line this.description}}`
+//! - `{{lower this.repo}}`, `{{upper this.repo}}`
+//! - `{{replace this.repo "/" "_"}}`
+//! - `{{regex_replace this.title "^([a-z]+): " "[$1] "}}`, note that handlebars
+//!   string literals can't contain backslashes
+
+use handlebars::{
+    Context, Handlebars, Helper, HelperDef, JsonRender, RenderContext, RenderError,
+    RenderErrorReason, ScopedJson,
+};
+use regex::Regex;
+use serde_json::Value;
+use unicode_segmentation::UnicodeSegmentation;
+use unicode_width::UnicodeWidthStr;
+
+use super::Helpers;
+
+pub fn helpers() -> Helpers {
+    vec![
+        ("truncate", Box::new(Truncate)),
+        ("slice", Box::new(Slice)),
+        ("pad", Box::new(Pad)),
+        ("wrap", Box::new(Wrap)),
+        ("first_line", Box::new(FirstLine)),
+        ("lower", Box::new(Lower)),
+        ("upper", Box::new(Upper)),
+        ("replace", Box::new(Replace)),
+        ("regex_replace", Box::new(RegexReplace)),
+    ]
+}
+
+/// Define a helper returning a string
+macro_rules! string_helper {
+    ($name:ident, |$h:ident| $body:expr) => {
+        struct $name;
+
+        impl HelperDef for $name {
+            fn call_inner<'reg: 'rc, 'rc>(
+                &self,
+                $h: &Helper<'rc>,
+                _: &'reg Handlebars<'reg>,
+                _: &'rc Context,
+                _: &mut RenderContext<'reg, 'rc>,
+            ) -> Result<ScopedJson<'rc>, RenderError> {
+                let result: Result<String, RenderError> = $body;
+                Ok(ScopedJson::Derived(Value::String(result?)))
+            }
+        }
+    };
+}
+
+string_helper!(Truncate, |h| {
+    let text = text_param(h, 0)?;
+    let max = usize_param(h, 1)?;
+    Ok(truncate(&text, max, &ellipsis(h)))
+});
+
+string_helper!(Slice, |h| {
+    let start = usize_param(h, 0)?;
+    let len = usize_param(h, 1)?;
+    let text = text_param(h, 2)?;
+    let graphemes: Vec<&str> = text.graphemes(true).collect();
+    let end = start.saturating_add(len).min(graphemes.len());
+    let mut sliced = graphemes.get(start..end).unwrap_or_default().concat();
+    if end < graphemes.len() {
+        sliced.push_str(&ellipsis(h));
+    }
+    Ok(sliced)
+});
+
+string_helper!(Pad, |h| {
+    let text = text_param(h, 0)?;
+    let width = usize_param(h, 1)?;
+    let fill = match h.hash_get("char") {
+        Some(c) => c.value().render(),
+        None => " ".to_string(),
+    };
+    let align = match h.hash_get("align") {
+        Some(align) => align.value().render(),
+        None => "left".to_string(),
+    };
+    pad(&text, width, &fill, &align)
+});
+
+string_helper!(Wrap, |h| {
+    let text = text_param(h, 0)?;
+    let width = usize_param(h, 1)?;
+    Ok(wrap(&text, width.max(1)))
+});
+
+string_helper!(FirstLine, |h| {
+    Ok(text_param(h, 0)?
+        .lines()
+        .next()
+        .unwrap_or_default()
+        .to_string())
+});
+
+string_helper!(Lower, |h| Ok(text_param(h, 0)?.to_lowercase()));
+
+string_helper!(Upper, |h| Ok(text_param(h, 0)?.to_uppercase()));
+
+string_helper!(Replace, |h| {
+    let text = text_param(h, 0)?;
+    Ok(text.replace(&text_param(h, 1)?, &text_param(h, 2)?))
+});
+
+string_helper!(RegexReplace, |h| {
+    let text = text_param(h, 0)?;
+    let pattern = text_param(h, 1)?;
+    let regex = Regex::new(&pattern)
+        .map_err(|e| RenderErrorReason::Other(format!("invalid regex {pattern:?}: {e}")))?;
+    Ok(regex.replace_all(&text, text_param(h, 2)?).into_owned())
+});
+
+/// Keep at most `max` graphemes, including the ellipsis
+fn truncate(text: &str, max: usize, ellipsis: &str) -> String {
+    if text.graphemes(true).count() <= max {
+        return text.to_string();
+    }
+    let keep = max.saturating_sub(ellipsis.graphemes(true).count());
+    let mut truncated: String = text.graphemes(true).take(keep).collect();
+    truncated.push_str(ellipsis);
+    truncated
+}
+
+fn pad(text: &str, width: usize, fill: &str, align: &str) -> Result<String, RenderError> {
+    if fill.width() != 1 {
+        return Err(RenderErrorReason::Other(format!(
+            "pad: expected a single column char, got {fill:?}"
+        ))
+        .into());
+    }
+    let n = width.saturating_sub(text.width());
+    let (left, right) = match align {
+        "left" => (0, n),
+        "right" => (n, 0),
+        "center" => (n / 2, n - n / 2),
+        _ => {
+            return Err(RenderErrorReason::Other(format!(
+                "pad: align should be left, right or center, got {align:?}"
+            ))
+            .into());
+        }
+    };
+    Ok(format!("{}{text}{}", fill.repeat(left), fill.repeat(right)))
+}
+
+/// Wrap each line at whitespace so that it fits in `width` columns.
+/// Words wider than that, e.g. CJK sentences, are broken between graphemes.
+fn wrap(text: &str, width: usize) -> String {
+    let mut lines = Vec::new();
+    for line in text.lines() {
+        let mut current = String::new();
+        for word in line.split_whitespace() {
+            let sep = usize::from(!current.is_empty());
+            if current.width() + sep + word.width() <= width {
+                if sep == 1 {
+                    current.push(' ');
+                }
+                current.push_str(word);
+                continue;
+            }
+            if !current.is_empty() {
+                lines.push(std::mem::take(&mut current));
+            }
+            for grapheme in word.graphemes(true) {
+                if !current.is_empty() && current.width() + grapheme.width() > width {
+                    lines.push(std::mem::take(&mut current));
+                }
+                current.push_str(grapheme);
+            }
+        }
+        lines.push(current);
+    }
+    lines.join("\n")
+}
+
+fn ellipsis(h: &Helper) -> String {
+    h.hash_get("ellipsis")
+        .map(|e| e.value().render())
+        .unwrap_or_default()
+}
+
+fn text_param(h: &Helper, index: usize) -> Result<String, RenderError> {
+    Ok(h.param(index)
+        .ok_or_else(|| RenderErrorReason::ParamNotFoundForIndex("string helper", index))?
+        .value()
+        .render())
+}
+
+fn usize_param(h: &Helper, index: usize) -> Result<usize, RenderError> {
+    h.param(index)
+        .ok_or_else(|| RenderErrorReason::ParamNotFoundForIndex("string helper", index))?
+        .value()
+        .as_u64()
+        .map(|n| n as usize)
+        .ok_or_else(|| {
+            RenderErrorReason::InvalidParamType("expected a non-negative integer").into()
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    const CJK: &str = "修复相机崩溃";
+    const EMOJI: &str = "Fix 👨‍👩‍👧 family emoji";
+
+    fn render(template: &str) -> Result<String, RenderError> {
+        let mut registry = Handlebars::new();
+        registry.register_escape_fn(handlebars::no_escape);
+        for (name, helper) in helpers() {
+            registry.register_helper(name, helper);
+        }
+        registry.render_template(
+            template,
+            &json!({
+                "cjk": CJK,
+                "emoji": EMOJI,
+                "description": "First line\n\nSecond paragraph",
+                "commit": "0123456789abcdef0123456789abcdef01234567",
+            }),
+        )
+    }
+
+    #[test]
+    fn test_truncate() {
+        assert_eq!(render("{{truncate cjk 4}}").unwrap(), "修复相机");
+        assert_eq!(
+            render(r#"{{truncate cjk 4 ellipsis="…"}}"#).unwrap(),
+            "修复相…"
+        );
+        assert_eq!(render(r#"{{truncate cjk 6 ellipsis="…"}}"#).unwrap(), CJK);
+        // the family emoji is a single grapheme of several code points
+        assert_eq!(render("{{truncate emoji 5}}").unwrap(), "Fix 👨‍👩‍👧");
+    }
+
+    #[test]
+    fn test_slice() {
+        assert_eq!(render("{{slice 0 12 commit}}").unwrap(), "0123456789ab");
+        // the last character is kept
+        assert_eq!(
+            render("{{slice 0 40 commit}}").unwrap(),
+            render("{{commit}}").unwrap()
+        );
+        assert_eq!(render("{{slice 2 3 cjk}}").unwrap(), "相机崩");
+        assert_eq!(
+            render(r#"{{slice 0 2 cjk ellipsis="..."}}"#).unwrap(),
+            "修复..."
+        );
+        assert_eq!(render("{{slice 4 1 emoji}}").unwrap(), "👨‍👩‍👧");
+        assert_eq!(render("{{slice 100 1 cjk}}").unwrap(), "");
+        assert_eq!(
+            render("{{slice 1 18446744073709551615 cjk}}").unwrap(),
+            "复相机崩溃"
+        );
+    }
+
+    #[test]
+    fn test_pad() {
+        assert_eq!(render("[{{pad cjk 14}}]").unwrap(), "[修复相机崩溃  ]");
+        assert_eq!(
+            render(r#"[{{pad "ab" 5 align="right" char="."}}]"#).unwrap(),
+            "[...ab]"
+        );
+        assert_eq!(
+            render(r#"[{{pad "ab" 5 align="center"}}]"#).unwrap(),
+            "[ ab  ]"
+        );
+        assert_eq!(render("[{{pad cjk 4}}]").unwrap(), format!("[{CJK}]"));
+        assert!(render(r#"{{pad cjk 4 align="top"}}"#).is_err());
+    }
+
+    #[test]
+    fn test_wrap() {
+        assert_eq!(
+            render(r#"{{wrap "the quick brown fox" 10}}"#).unwrap(),
+            "the quick\nbrown fox"
+        );
+        // CJK characters are two columns wide
+        assert_eq!(render("{{wrap cjk 5}}").unwrap(), "修复\n相机\n崩溃");
+        assert_eq!(
+            render("{{wrap description 80}}").unwrap(),
+            "First line\n\nSecond paragraph"
+        );
+    }
+
+    #[test]
+    fn test_first_line_and_case() {
+        assert_eq!(render("{{first_line description}}").unwrap(), "First line");
+        assert_eq!(
+            render("{{upper (first_line description)}}").unwrap(),
+            "FIRST LINE"
+        );
+        assert_eq!(render(r#"{{lower "ÄÖÜ"}}"#).unwrap(), "äöü");
+    }
+
+    #[test]
+    fn test_replace() {
+        assert_eq!(
+            render(r#"{{replace "device/google/raven" "/" "_"}}"#).unwrap(),
+            "device_google_raven"
+        );
+        assert_eq!(
+            render(r#"{{regex_replace "[camera] 修复崩溃" "^.([a-z]+)[]] " "$1: "}}"#).unwrap(),
+            "camera: 修复崩溃"
+        );
+        assert!(render(r#"{{regex_replace cjk "(" ""}}"#).is_err());
+    }
+}
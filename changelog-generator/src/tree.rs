@@ -0,0 +1,231 @@
+//! Inspect a repo checkout, replacing `snapshot.c` and `format-repo-commit.sh`
+
+use std::{collections::BTreeMap, fmt, path::Path, string::FromUtf8Error};
+
+use arcstr::ArcStr;
+use serde::Serialize;
+use snafu::{ResultExt, Snafu, ensure};
+use tracing::{debug, info};
+use xshell::{Shell, cmd};
+
+use crate::{
+    changelog::{ChangeLogError, get_sync_stamp_branch},
+    snapshot::{CommitHash, CommitHashError, RepoStatus, Snapshot},
+};
+
+#[derive(Debug, Snafu)]
+pub enum TreeError {
+    #[snafu(display("failed to open a command shell"))]
+    ShellCreation { source: xshell::Error },
+    #[snafu(display("command exec failed"))]
+    CommandExecution { source: xshell::Error },
+    #[snafu(display("command failed, operation: {operation}, {message}"))]
+    CommandFailure {
+        operation: &'static str,
+        message: String,
+    },
+    #[snafu(display("failed to parse command output as UTF-8"))]
+    InvalidEncoding { source: FromUtf8Error },
+    #[snafu(display("failed to find the sync stamp branch"))]
+    SyncStamp { source: ChangeLogError },
+    #[snafu(display("invalid commit {commit:?} from {repo}"))]
+    InvalidCommit {
+        repo: ArcStr,
+        commit: String,
+        source: CommitHashError,
+    },
+}
+
+pub type Result<T, E = TreeError> = std::result::Result<T, E>;
+
+/// The commit and uncommitted changes of a repo
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct TreeStatus {
+    pub repo: ArcStr,
+    pub commit: ArcStr,
+    pub modified: Vec<ArcStr>,
+    pub untracked: Vec<ArcStr>,
+    pub deleted: Vec<ArcStr>,
+    pub other: Vec<ArcStr>,
+}
+
+impl TreeStatus {
+    pub fn is_clean(&self) -> bool {
+        self.modified.is_empty()
+            && self.untracked.is_empty()
+            && self.deleted.is_empty()
+            && self.other.is_empty()
+    }
+
+    /// Classify the files in the output of `git status --porcelain=v1`
+    fn parse_porcelain(&mut self, output: &str) {
+        for line in output.lines() {
+            let Some((status, file)) = line.trim().split_once(' ') else {
+                continue;
+            };
+            let files = match status {
+                "??" => &mut self.untracked,
+                "M" => &mut self.modified,
+                "D" => &mut self.deleted,
+                _ => &mut self.other,
+            };
+            files.push(ArcStr::from(file.trim_start()));
+        }
+    }
+}
+
+/// A markdown list item, e.g. ``- `system/core`: `413223ae32d8` (Dirty, Modified: `init/main.cpp`)``
+impl fmt::Display for TreeStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "- `{}`: `{}`", self.repo, self.commit)?;
+        if self.is_clean() {
+            return Ok(());
+        }
+        let groups = [
+            ("Modified", &self.modified),
+            ("Untracked", &self.untracked),
+            ("Deleted", &self.deleted),
+            ("Other", &self.other),
+        ];
+        let groups: Vec<String> = groups
+            .into_iter()
+            .filter(|(_, files)| !files.is_empty())
+            .map(|(name, files)| {
+                let files: Vec<String> = files
+                    .iter()
+                    .map(|file| format!("`{}`", file.replace('`', "\\`")))
+                    .collect();
+                format!("{name}: {}", files.join(", "))
+            })
+            .collect();
+        write!(f, " (Dirty, {})", groups.join(", "))
+    }
+}
+
+/// The repos of the tree at `top`, as reported by `repo list`
+fn list_repos(sh: &Shell, top: &Path) -> Result<Vec<ArcStr>> {
+    let output = cmd!(sh, "env -C {top} repo list -p")
+        .ignore_status()
+        .output()
+        .context(CommandExecutionSnafu)?;
+    ensure!(
+        output.status.success(),
+        CommandFailureSnafu {
+            operation: "repo list",
+            message: String::from_utf8_lossy(&output.stderr).into_owned(),
+        }
+    );
+    let repos = String::from_utf8(output.stdout).context(InvalidEncodingSnafu)?;
+    Ok(repos
+        .lines()
+        .map(str::trim)
+        .filter(|repo| !repo.is_empty())
+        .map(ArcStr::from)
+        .collect())
+}
+
+/// The short commit of the sync stamp branch in a repo
+fn stamp_commit(sh: &Shell, top: &Path, repo: &str, stamp: &str) -> Result<ArcStr> {
+    let repo_path = top.join(repo);
+    let output = cmd!(sh, "git -C {repo_path} rev-parse --short=12 {stamp}")
+        .ignore_status()
+        .output()
+        .context(CommandExecutionSnafu)?;
+    ensure!(
+        output.status.success(),
+        CommandFailureSnafu {
+            operation: "git rev-parse",
+            message: String::from_utf8_lossy(&output.stderr).into_owned(),
+        }
+    );
+    let commit = String::from_utf8(output.stdout).context(InvalidEncodingSnafu)?;
+    Ok(ArcStr::from(commit.trim()))
+}
+
+/// Capture the synced commit of every repo in the tree at `top`,
+/// the sync stamp branch is `stamp_branch_prefix` followed by the manifest branch.
+pub fn capture(top: impl AsRef<Path>, stamp_branch_prefix: &str) -> Result<Snapshot> {
+    let top = top.as_ref();
+    let sh = Shell::new().context(ShellCreationSnafu)?;
+    let stamp = get_sync_stamp_branch(&top, stamp_branch_prefix).context(SyncStampSnafu)?;
+    let mut repos = BTreeMap::new();
+    let listed = list_repos(&sh, top)?;
+    info!(repos = listed.len(), stamp, "capturing snapshot");
+    for repo in listed {
+        let commit = stamp_commit(&sh, top, &repo, &stamp)?;
+        debug!(repo = repo.as_str(), commit = commit.as_str(), "captured");
+        let commit =
+            CommitHash::try_new(commit.to_string()).with_context(|_| InvalidCommitSnafu {
+                repo: repo.clone(),
+                commit: commit.to_string(),
+            })?;
+        repos.insert(repo, RepoStatus { commit });
+    }
+    Ok(Snapshot { repos })
+}
+
+/// Report the synced commit and uncommitted changes of every repo in the tree at `top`.
+pub fn status(top: impl AsRef<Path>, stamp_branch_prefix: &str) -> Result<Vec<TreeStatus>> {
+    let top = top.as_ref();
+    let sh = Shell::new().context(ShellCreationSnafu)?;
+    let stamp = get_sync_stamp_branch(&top, stamp_branch_prefix).context(SyncStampSnafu)?;
+    let mut statuses = Vec::new();
+    let listed = list_repos(&sh, top)?;
+    info!(repos = listed.len(), stamp, "checking status");
+    for repo in listed {
+        let mut status = TreeStatus {
+            commit: stamp_commit(&sh, top, &repo, &stamp)?,
+            repo,
+            modified: Vec::new(),
+            untracked: Vec::new(),
+            deleted: Vec::new(),
+            other: Vec::new(),
+        };
+        let repo_path = top.join(status.repo.as_str());
+        let output = cmd!(sh, "git -C {repo_path} status --porcelain=v1")
+            .output()
+            .context(CommandExecutionSnafu)?;
+        status.parse_porcelain(&String::from_utf8(output.stdout).context(InvalidEncodingSnafu)?);
+        debug!(
+            repo = status.repo.as_str(),
+            clean = status.is_clean(),
+            "checked"
+        );
+        statuses.push(status);
+    }
+    Ok(statuses)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tree_status() -> TreeStatus {
+        TreeStatus {
+            repo: ArcStr::from("system/core"),
+            commit: ArcStr::from("413223ae32d8"),
+            modified: Vec::new(),
+            untracked: Vec::new(),
+            deleted: Vec::new(),
+            other: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn test_clean_status() {
+        assert_eq!(tree_status().to_string(), "- `system/core`: `413223ae32d8`");
+    }
+
+    #[test]
+    fn test_dirty_status() {
+        let mut status = tree_status();
+        status.parse_porcelain(" M init/main.cpp\n?? a`b\n D README.md\nMM Android.bp\n");
+        assert_eq!(status.modified, ["init/main.cpp"]);
+        assert_eq!(status.other, ["Android.bp"]);
+        assert_eq!(
+            status.to_string(),
+            "- `system/core`: `413223ae32d8` (Dirty, Modified: `init/main.cpp`, \
+             Untracked: `a\\`b`, Deleted: `README.md`, Other: `Android.bp`)"
+        );
+    }
+}